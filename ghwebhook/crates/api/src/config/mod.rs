@@ -7,6 +7,13 @@ pub struct AppConfig {
     pub rabbitmq_host: String,
     #[serde(default = "default_rabbitmq_port")]
     pub rabbitmq_port: u16,
+    // comma separated so that a new secret can be added before the old one is retired
+    pub webhook_secrets: Vec<String>,
+    // the run database written by the worker
+    #[serde(default = "default_database_path")]
+    pub database_path: String,
+    // bearer token of the endpoints that change state, they are disabled without one
+    pub admin_token: Option<String>,
 }
 
 fn default_rabbitmq_host() -> String {
@@ -17,15 +24,34 @@ fn default_rabbitmq_port() -> u16 {
     5552
 }
 
+fn default_database_path() -> String {
+    "slurm-actions.db".to_string()
+}
+
 impl AppConfig {
     pub fn new() -> Result<Self, lib::errors::AppError> {
         let settings = Config::builder()
-            .add_source(config::Environment::with_prefix("GHWEBHOOKS_API"))
+            .add_source(
+                config::Environment::with_prefix("GHWEBHOOKS_API")
+                    .try_parsing(true)
+                    .list_separator(",")
+                    .with_list_parse_key("webhook_secrets"),
+            )
             .build()
-            .map_err(|e| lib::errors::AppError::ConfigError(e))?;
+            .map_err(lib::errors::AppError::ConfigError)?;
 
-        settings
+        let mut config = settings
             .try_deserialize::<Self>()
-            .map_err(|e| lib::errors::AppError::ConfigError(e))
+            .map_err(lib::errors::AppError::ConfigError)?;
+
+        config.webhook_secrets.retain(|secret| !secret.is_empty());
+        config.admin_token = config.admin_token.filter(|token| !token.is_empty());
+        if config.webhook_secrets.is_empty() {
+            return Err(lib::errors::AppError::ConfigError(
+                config::ConfigError::Message("webhook_secrets must not be empty".to_string()),
+            ));
+        }
+
+        Ok(config)
     }
 }
@@ -0,0 +1,269 @@
+use hmac::{Hmac, Mac};
+use rocket::data::{ByteUnit, Data, FromData, Outcome};
+use rocket::http::Status;
+use rocket::outcome::Outcome::{Error, Success};
+use rocket::request::{self, FromRequest, Request};
+use sha2::{Digest, Sha256};
+
+use crate::types::AppState;
+
+// github caps webhook payloads at 25 MB
+const WEBHOOK_PAYLOAD_LIMIT: ByteUnit = ByteUnit::Mebibyte(25);
+
+/// Raw webhook body whose `X-Hub-Signature-256` header matched one of the configured secrets.
+pub struct SignedPayload(pub Vec<u8>);
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for SignedPayload {
+    type Error = lib::errors::AppError;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> Outcome<'r, Self> {
+        let state = match req.rocket().state::<AppState>() {
+            Some(state) => state,
+            None => {
+                return Error((
+                    Status::InternalServerError,
+                    lib::errors::AppError::WebhookSignatureError(
+                        "application state is not managed".to_string(),
+                    ),
+                ));
+            }
+        };
+
+        let signature = match req.headers().get_one("X-Hub-Signature-256") {
+            Some(signature) => signature,
+            None => {
+                return Error((
+                    Status::Unauthorized,
+                    lib::errors::AppError::WebhookSignatureError(
+                        "missing X-Hub-Signature-256 header".to_string(),
+                    ),
+                ));
+            }
+        };
+
+        let limit = req.limits().get("webhook").unwrap_or(WEBHOOK_PAYLOAD_LIMIT);
+        let body = match data.open(limit).into_bytes().await {
+            Ok(body) if body.is_complete() => body.into_inner(),
+            Ok(_) => {
+                return Error((
+                    Status::PayloadTooLarge,
+                    lib::errors::AppError::WebhookSignatureError(
+                        "payload exceeds the webhook size limit".to_string(),
+                    ),
+                ));
+            }
+            Err(err) => {
+                return Error((
+                    Status::BadRequest,
+                    lib::errors::AppError::WebhookSignatureError(err.to_string()),
+                ));
+            }
+        };
+
+        if let Err(err) = verify_signature(&state.webhook_secrets, &body, signature) {
+            eprintln!("Rejecting webhook: {}", err);
+            return Error((Status::Unauthorized, err));
+        }
+
+        Success(SignedPayload(body))
+    }
+}
+
+/// Checks a `sha256=<hex>` signature against every secret, so secrets can be rotated
+/// without dropping deliveries signed with the previous one.
+pub fn verify_signature(
+    secrets: &[String],
+    body: &[u8],
+    signature: &str,
+) -> Result<(), lib::errors::AppError> {
+    let signature = signature
+        .strip_prefix("sha256=")
+        .and_then(|hex_digest| hex::decode(hex_digest).ok())
+        .ok_or_else(|| {
+            lib::errors::AppError::WebhookSignatureError("malformed signature header".to_string())
+        })?;
+
+    for secret in secrets {
+        let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+            Ok(mac) => mac,
+            Err(_) => continue,
+        };
+        mac.update(body);
+        // verify_slice compares in constant time
+        if mac.verify_slice(&signature).is_ok() {
+            return Ok(());
+        }
+    }
+
+    Err(lib::errors::AppError::WebhookSignatureError(
+        "signature does not match any configured secret".to_string(),
+    ))
+}
+
+/// A request with the `Authorization: Bearer <admin_token>` header. Without a configured
+/// token every request is forbidden.
+pub struct AdminToken;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminToken {
+    type Error = lib::errors::AppError;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let Some(admin_token) = req
+            .rocket()
+            .state::<AppState>()
+            .and_then(|state| state.admin_token.as_deref())
+        else {
+            return Error((
+                Status::Forbidden,
+                lib::errors::AppError::ApiAuthError("admin_token is not configured".to_string()),
+            ));
+        };
+
+        match req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+        {
+            Some(token) if tokens_match(admin_token, token) => Success(AdminToken),
+            _ => Error((
+                Status::Unauthorized,
+                lib::errors::AppError::ApiAuthError("invalid bearer token".to_string()),
+            )),
+        }
+    }
+}
+
+// compares digests so the time taken doesn't depend on the token or its length
+fn tokens_match(expected: &str, token: &str) -> bool {
+    Sha256::digest(expected.as_bytes())
+        .iter()
+        .zip(Sha256::digest(token.as_bytes()).iter())
+        .fold(0, |difference, (a, b)| difference | (a ^ b))
+        == 0
+}
+
+/// The `X-GitHub-Event` header of a webhook delivery.
+pub struct GithubEventName(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for GithubEventName {
+    type Error = lib::errors::AppError;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match req.headers().get_one("X-GitHub-Event") {
+            Some(event_name) => Success(GithubEventName(event_name.to_string())),
+            None => Error((
+                Status::BadRequest,
+                lib::errors::AppError::WebhookPayloadError(
+                    "missing X-GitHub-Event header".to_string(),
+                ),
+            )),
+        }
+    }
+}
+
+/// The `X-GitHub-Delivery` header, a GUID that stays the same when a delivery is retried.
+pub struct GithubDeliveryId(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for GithubDeliveryId {
+    type Error = lib::errors::AppError;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match req.headers().get_one("X-GitHub-Delivery") {
+            Some(delivery_id) => Success(GithubDeliveryId(delivery_id.to_string())),
+            None => Error((
+                Status::BadRequest,
+                lib::errors::AppError::WebhookPayloadError(
+                    "missing X-GitHub-Delivery header".to_string(),
+                ),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the example of GitHub's webhook validation docs
+    const SECRET: &str = "It's a Secret to Everybody";
+    const BODY: &[u8] = b"Hello, World!";
+    const SIGNATURE: &str =
+        "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+    fn secrets(secrets: &[&str]) -> Vec<String> {
+        secrets.iter().map(|secret| secret.to_string()).collect()
+    }
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    #[test]
+    fn valid_signatures_pass() {
+        assert!(verify_signature(&secrets(&[SECRET]), BODY, SIGNATURE).is_ok());
+    }
+
+    #[test]
+    fn every_rotated_secret_is_accepted() {
+        let rotated = secrets(&["old secret", SECRET, "new secret"]);
+        assert!(verify_signature(&rotated, BODY, SIGNATURE).is_ok());
+        assert!(verify_signature(&rotated, BODY, &sign("old secret", BODY)).is_ok());
+        assert!(verify_signature(&rotated, BODY, &sign("new secret", BODY)).is_ok());
+        assert!(verify_signature(&rotated, BODY, &sign("retired secret", BODY)).is_err());
+    }
+
+    #[test]
+    fn wrong_secrets_and_bodies_are_rejected() {
+        assert!(verify_signature(&secrets(&["another secret"]), BODY, SIGNATURE).is_err());
+        assert!(verify_signature(&secrets(&[SECRET]), b"Hello, World?", SIGNATURE).is_err());
+        assert!(verify_signature(&secrets(&[]), BODY, SIGNATURE).is_err());
+    }
+
+    #[test]
+    fn malformed_signatures_are_rejected() {
+        let digest = SIGNATURE.strip_prefix("sha256=").unwrap();
+        for signature in [
+            digest.to_string(),
+            format!("sha1={}", digest),
+            format!("SHA256={}", digest),
+            format!("sha256= {}", digest),
+            "sha256=".to_string(),
+            "sha256=not hex at all".to_string(),
+            format!("sha256={}", &digest[1..]),
+            format!("sha256={}zz", &digest[2..]),
+            String::new(),
+        ] {
+            assert!(
+                matches!(
+                    verify_signature(&secrets(&[SECRET]), BODY, &signature),
+                    Err(lib::errors::AppError::WebhookSignatureError(_))
+                ),
+                "{:?}",
+                signature
+            );
+        }
+    }
+
+    #[test]
+    fn empty_bodies_need_a_signature_too() {
+        let signature = sign(SECRET, b"");
+        assert!(verify_signature(&secrets(&[SECRET]), b"", &signature).is_ok());
+        assert!(verify_signature(&secrets(&[SECRET]), b"", SIGNATURE).is_err());
+        assert!(verify_signature(&secrets(&[SECRET]), BODY, &signature).is_err());
+    }
+
+    #[test]
+    fn admin_tokens_must_match_exactly() {
+        assert!(tokens_match("s3cret-token", "s3cret-token"));
+        assert!(!tokens_match("s3cret-token", "s3cret-tokeN"));
+        assert!(!tokens_match("s3cret-token", "s3cret-token "));
+        assert!(!tokens_match("s3cret-token", "s3cret"));
+        assert!(!tokens_match("s3cret-token", ""));
+    }
+}
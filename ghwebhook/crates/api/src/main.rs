@@ -1,3 +1,6 @@
+use std::path::Path;
+
+use lib::store::RunStore;
 use rocket::http::Method;
 use rocket_cors::{AllowedHeaders, AllowedOrigins, CorsOptions};
 
@@ -7,6 +10,7 @@ use crate::services::create_rabbitmq_producer;
 extern crate rocket;
 
 mod config;
+mod guards;
 mod routes;
 mod services;
 mod types;
@@ -41,11 +45,25 @@ async fn main() -> Result<(), lib::errors::AppError> {
 
     let rabbitmq_producer = create_rabbitmq_producer(&config, "ghwebhook", 5).await?;
 
-    let state = types::AppState { rabbitmq_producer };
+    let run_store = RunStore::open(Path::new(&config.database_path))?;
+
+    let state = types::AppState {
+        rabbitmq_producer,
+        webhook_secrets: config.webhook_secrets,
+        run_store,
+        admin_token: config.admin_token,
+    };
 
-    let rocket = rocket::build()
-        .attach(cors)
-        .mount("/", routes![routes::webhook]);
+    let rocket = rocket::build().attach(cors).mount(
+        "/",
+        routes![
+            routes::webhook,
+            routes::list_runs,
+            routes::get_run,
+            routes::approve_run,
+            routes::dispatch_workflow
+        ],
+    );
 
     rocket.manage(state).launch().await?;
 
@@ -1,22 +1,99 @@
+use crate::guards::{AdminToken, GithubDeliveryId, GithubEventName, SignedPayload};
 use crate::types::AppState;
-use lib::types::githubevent::GithubEvent;
+use lib::errors::AppError;
+use lib::store::{JobRecord, WorkflowRunRecord};
+use lib::types::approval::{APPROVAL_EVENT, ApprovalRequest};
+use lib::types::dispatch::{DISPATCH_EVENT, DispatchRequest};
+use lib::types::runstatus::RunStatus;
+use lib::types::{envelope::QueueEnvelope, webhookevent::WebhookEvent};
 use rabbitmq_stream_client::types::Message;
-use rocket::{State, http::Status, serde::json::Json};
+use rocket::serde::json::Json;
+use rocket::{State, http::Status};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAX_RUNS: u32 = 100;
+
+#[derive(Serialize)]
+pub struct RunResponse {
+    #[serde(flatten)]
+    run: WorkflowRunRecord,
+    jobs: Vec<JobRecord>,
+}
+
+/// The body of a dispatch, as for GitHub's endpoint.
+#[derive(Deserialize)]
+pub struct DispatchBody {
+    // a branch, or a full ref such as refs/tags/v1
+    #[serde(rename = "ref")]
+    git_ref: String,
+    #[serde(default)]
+    inputs: HashMap<String, serde_json::Value>,
+}
 
 #[post("/webhook", data = "<payload>", format = "application/json")]
-pub async fn webhook(payload: Json<GithubEvent>, state: &State<AppState>) -> Status {
-    let payload_bytes = match serde_json::to_vec(&payload.into_inner()) {
+pub async fn webhook(
+    event_name: GithubEventName,
+    delivery_id: GithubDeliveryId,
+    payload: SignedPayload,
+    state: &State<AppState>,
+) -> Status {
+    let payload: serde_json::Value = match serde_json::from_slice(&payload.0) {
+        Ok(payload) => payload,
+        Err(err) => {
+            eprintln!("Failed to parse payload: {}", err);
+            return Status::UnprocessableEntity;
+        }
+    };
+
+    let webhook_event = match WebhookEvent::from_value(&event_name.0, payload.clone()) {
+        Ok(webhook_event) => webhook_event,
+        Err(AppError::UnsupportedWebhookEvent(event_name)) => {
+            println!("Ignoring unsupported {} event", event_name);
+            return Status::Accepted;
+        }
+        Err(err) => {
+            eprintln!("Failed to parse payload: {}", err);
+            return Status::UnprocessableEntity;
+        }
+    };
+
+    if let WebhookEvent::Ping(ping) = &webhook_event {
+        println!("Received ping: {}", ping.zen.as_deref().unwrap_or_default());
+        return Status::Ok;
+    }
+
+    let envelope = QueueEnvelope::new(
+        webhook_event.name(),
+        &delivery_id.0,
+        webhook_event.installation_id(),
+        payload,
+    );
+    let envelope_bytes = match envelope.to_vec() {
         Ok(bytes) => bytes,
         Err(err) => {
-            eprintln!("Failed to serialize payload: {}", err);
+            eprintln!("Failed to serialize envelope: {}", err);
             return Status::InternalServerError;
         }
     };
-    let confirmation_status = match state
-        .rabbitmq_producer
-        .send_with_confirm(Message::builder().body(payload_bytes).build())
-        .await
-    {
+
+    publish(state, envelope_bytes).await
+}
+
+// deliveries that don't come from GitHub, unique so the worker doesn't skip them as
+// duplicates
+fn internal_delivery_id(kind: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or_default();
+    format!("{}-{}", kind, nanos)
+}
+
+async fn publish(state: &State<AppState>, body: Vec<u8>) -> Status {
+    let message = Message::builder().body(body).build();
+    let confirmation_status = match state.rabbitmq_producer.send_with_confirm(message).await {
         Ok(status) => status,
         Err(err) => {
             eprintln!("Failed to send message to RabbitMQ: {}", err);
@@ -34,3 +111,200 @@ pub async fn webhook(payload: Json<GithubEvent>, state: &State<AppState>) -> Sta
 
     Status::Ok
 }
+
+#[get("/runs?<repository>&<limit>")]
+pub fn list_runs(
+    repository: Option<&str>,
+    limit: Option<u32>,
+    _admin: AdminToken,
+    state: &State<AppState>,
+) -> Result<Json<Vec<WorkflowRunRecord>>, Status> {
+    let limit = limit.unwrap_or(20).min(MAX_RUNS);
+    match state.run_store.list_runs(repository, limit) {
+        Ok(runs) => Ok(Json(runs)),
+        Err(err) => {
+            eprintln!("Failed to list runs: {}", err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[get("/runs/<run_id>")]
+pub fn get_run(
+    run_id: i64,
+    _admin: AdminToken,
+    state: &State<AppState>,
+) -> Result<Json<RunResponse>, Status> {
+    match state.run_store.get_run(run_id) {
+        Ok(Some((run, jobs))) => Ok(Json(RunResponse { run, jobs })),
+        Ok(None) => Err(Status::NotFound),
+        Err(err) => {
+            eprintln!("Failed to get run {}: {}", run_id, err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// Approves a run waiting for approval, along with the other runs held for the same
+/// delivery. The worker submits them once it handles the approval.
+#[post("/runs/<run_id>/approve")]
+pub async fn approve_run(run_id: i64, _admin: AdminToken, state: &State<AppState>) -> Status {
+    let run = match state.run_store.get_run(run_id) {
+        Ok(Some((run, _))) => run,
+        Ok(None) => return Status::NotFound,
+        Err(err) => {
+            eprintln!("Failed to get run {}: {}", run_id, err);
+            return Status::InternalServerError;
+        }
+    };
+    let Some(approval_id) = run.approval_id else {
+        return Status::Conflict;
+    };
+    if run.status != RunStatus::WaitingForApproval.as_str() {
+        return Status::Conflict;
+    }
+
+    let request = ApprovalRequest {
+        approval_id,
+        approved_by: "api".to_string(),
+    };
+    let payload = match serde_json::to_value(&request) {
+        Ok(payload) => payload,
+        Err(err) => {
+            eprintln!("Failed to serialize approval: {}", err);
+            return Status::InternalServerError;
+        }
+    };
+    let envelope = QueueEnvelope::new(
+        APPROVAL_EVENT,
+        &internal_delivery_id("approval"),
+        None,
+        payload,
+    );
+    let envelope_bytes = match envelope.to_vec() {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("Failed to serialize envelope: {}", err);
+            return Status::InternalServerError;
+        }
+    };
+
+    let status = publish(state, envelope_bytes).await;
+    if status == Status::Ok {
+        Status::Accepted
+    } else {
+        status
+    }
+}
+
+// a name GitHub allows for an owner, repository or file, which can't climb out of the
+// directory it names something in
+fn is_path_segment(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment != "."
+        && !segment.contains("..")
+        && segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+/// Starts a `workflow_dispatch` workflow like GitHub's endpoint of the same path. Only the
+/// worker reads the workflow, so inputs are checked against its declarations there and a
+/// dispatch with invalid inputs is recorded as a failed run. Inputs that can't be the value
+/// of any input are rejected here.
+#[post(
+    "/repos/<owner>/<repo>/workflows/<file>/dispatches",
+    data = "<body>",
+    format = "application/json"
+)]
+pub async fn dispatch_workflow(
+    owner: &str,
+    repo: &str,
+    file: &str,
+    body: Json<DispatchBody>,
+    _admin: AdminToken,
+    state: &State<AppState>,
+) -> Status {
+    if ![owner, repo, file].into_iter().all(is_path_segment)
+        || !(file.ends_with(".yml") || file.ends_with(".yaml"))
+        || body.git_ref.is_empty()
+        || body
+            .inputs
+            .values()
+            .any(|value| value.is_object() || value.is_array())
+    {
+        return Status::UnprocessableEntity;
+    }
+
+    let body = body.into_inner();
+    let git_ref = if body.git_ref.starts_with("refs/") {
+        body.git_ref
+    } else {
+        format!("refs/heads/{}", body.git_ref)
+    };
+    let request = DispatchRequest {
+        repository: format!("{}/{}", owner, repo),
+        workflow: format!(".github/workflows/{}", file),
+        git_ref,
+        inputs: body.inputs,
+        sender: "api".to_string(),
+    };
+    let payload = match serde_json::to_value(&request) {
+        Ok(payload) => payload,
+        Err(err) => {
+            eprintln!("Failed to serialize dispatch: {}", err);
+            return Status::InternalServerError;
+        }
+    };
+    let envelope = QueueEnvelope::new(
+        DISPATCH_EVENT,
+        &internal_delivery_id("dispatch"),
+        None,
+        payload,
+    );
+    let envelope_bytes = match envelope.to_vec() {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("Failed to serialize envelope: {}", err);
+            return Status::InternalServerError;
+        }
+    };
+
+    let status = publish(state, envelope_bytes).await;
+    if status == Status::Ok {
+        Status::NoContent
+    } else {
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_path_segments() {
+        for segment in ["octo", "repo.js", "my_repo-2", "ci.yml", ".github"] {
+            assert!(is_path_segment(segment), "{:?}", segment);
+        }
+    }
+
+    #[test]
+    fn traversals_and_separators_are_not_path_segments() {
+        for segment in [
+            "",
+            ".",
+            "..",
+            "..yml",
+            "ci..yml",
+            "a/b",
+            "a\\b",
+            "ci.yml\n",
+            "ci yml",
+            "%2e%2e",
+            "ci.yml?ref=main",
+        ] {
+            assert!(!is_path_segment(segment), "{:?}", segment);
+        }
+    }
+}
@@ -14,7 +14,7 @@ pub async fn create_rabbitmq_producer(
         .port(config.rabbitmq_port)
         .build()
         .await
-        .map_err(|err| lib::errors::AppError::RabbitMQClientError(err))?;
+        .map_err(lib::errors::AppError::RabbitMQClientError)?;
 
     let create_response = environment
         .stream_creator()
@@ -22,23 +22,23 @@ pub async fn create_rabbitmq_producer(
         .create(stream)
         .await;
 
-    if let Err(e) = create_response {
-        if let StreamCreateError::Create { stream, status } = e {
-            match status {
-                // we can ignore this error because the stream already exists
-                ResponseCode::StreamAlreadyExists => {
-                    println!("Stream {} already exists, skipping create.", stream);
-                }
-                err => {
-                    println!("Error creating stream: {:?} {:?}", stream, err);
-                }
+    if let Err(e) = create_response
+        && let StreamCreateError::Create { stream, status } = e
+    {
+        match status {
+            // we can ignore this error because the stream already exists
+            ResponseCode::StreamAlreadyExists => {
+                println!("Stream {} already exists, skipping create.", stream);
+            }
+            err => {
+                println!("Error creating stream: {:?} {:?}", stream, err);
             }
         }
     }
 
-    Ok(environment
+    environment
         .producer()
         .build(stream)
         .await
-        .map_err(|err| lib::errors::AppError::RabbitMQProducerCreateError(err))?)
+        .map_err(lib::errors::AppError::RabbitMQProducerCreateError)
 }
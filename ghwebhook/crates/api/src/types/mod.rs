@@ -1,5 +1,9 @@
+use lib::store::RunStore;
 use rabbitmq_stream_client::{NoDedup, Producer};
 
 pub struct AppState {
     pub rabbitmq_producer: Producer<NoDedup>,
+    pub webhook_secrets: Vec<String>,
+    pub run_store: RunStore,
+    pub admin_token: Option<String>,
 }
@@ -3,7 +3,7 @@ use thiserror::Error;
 #[derive(Error, Debug)]
 pub enum AppError {
     #[error("Error launching rocket: {0}")]
-    RocketError(rocket::Error),
+    RocketError(Box<rocket::Error>),
     #[error("Error parsing configuration: {0}")]
     ConfigError(config::ConfigError),
     #[error("RabbitMQ client error: {0}")]
@@ -12,18 +12,52 @@ pub enum AppError {
     RabbitMQProducerCreateError(rabbitmq_stream_client::error::ProducerCreateError),
     #[error("Error cloning git repository: {0}")]
     GitCloneError(String),
+    #[error("Error checking out commit: {0}")]
+    GitCheckoutError(String),
     #[error("Error creating temporary directory: {0}")]
     TempDirCreationError(String),
+    #[error("Error publishing message: {0}")]
+    RabbitMQProducerPublishError(String),
     #[error("Error creating consumer: {0}")]
     RabbitMQConsumerCreateError(rabbitmq_stream_client::error::ConsumerCreateError),
     #[error("Error closing consumer: {0}")]
     RabbitMQConsumerCloseError(rabbitmq_stream_client::error::ConsumerCloseError),
     #[error("Error consuming message: {0}")]
     RabbitMQConsumerConsumeError(String),
+    #[error("Invalid webhook signature: {0}")]
+    WebhookSignatureError(String),
+    #[error("Unsupported webhook event: {0}")]
+    UnsupportedWebhookEvent(String),
+    #[error("Error parsing webhook payload: {0}")]
+    WebhookPayloadError(String),
+    #[error("Error authenticating as github app: {0}")]
+    GithubAppAuthError(String),
+    #[error("Github API error: {0}")]
+    GithubApiError(String),
+    #[error("Error authenticating to slurmrestd: {0}")]
+    SlurmAuthError(String),
+    #[error("slurmrestd error: {0}")]
+    SlurmRestdError(String),
+    #[error("Slurm is temporarily unavailable: {0}")]
+    SlurmRetryableError(String),
+    #[error("Slurm rejected the job: {0}")]
+    SlurmPermanentError(String),
+    #[error("Error decoding queue envelope: {0}")]
+    EnvelopeError(String),
+    #[error("Run database error: {0}")]
+    StoreError(String),
+    #[error("Secrets error: {0}")]
+    SecretError(String),
+    #[error("Invalid workflow input: {0}")]
+    WorkflowInputError(String),
+    #[error("Error rendering batch script: {0}")]
+    ScriptError(String),
+    #[error("Unauthorized api request: {0}")]
+    ApiAuthError(String),
 }
 
 impl From<rocket::Error> for AppError {
     fn from(err: rocket::Error) -> Self {
-        AppError::RocketError(err)
+        AppError::RocketError(Box::new(err))
     }
 }
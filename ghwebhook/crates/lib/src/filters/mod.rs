@@ -0,0 +1,479 @@
+//! Branch, tag and path filters of workflow triggers, with GitHub's pattern syntax:
+//! `*` matches anything but `/`, `**` matches anything, `?` and `+` make the character
+//! before them optional or repeatable, `[]` matches one character of a set or range, `\`
+//! escapes and a leading `!` excludes what earlier patterns matched.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Atom {
+    Char(char),
+    Class(Vec<(char, char)>),
+    // `*`
+    Segment,
+    // `**`
+    Anything,
+    // `**/`, any number of leading directories, including none
+    Directories,
+}
+
+impl Atom {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Atom::Char(expected) => *expected == c,
+            Atom::Class(ranges) => ranges.iter().any(|(from, to)| (*from..=*to).contains(&c)),
+            Atom::Segment => c != '/',
+            Atom::Anything => true,
+            Atom::Directories => unreachable!("directories are matched as a whole"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    atom: Atom,
+    min: usize,
+    max: usize,
+}
+
+/// A compiled filter pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+    negated: bool,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Self {
+        let (negated, pattern) = match pattern.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+
+        let mut tokens: Vec<Token> = Vec::new();
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            let atom = match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        Atom::Directories
+                    } else {
+                        Atom::Anything
+                    }
+                }
+                '*' => Atom::Segment,
+                '?' | '+' => match tokens.last_mut() {
+                    Some(token)
+                        if token.min == 1 && token.max == 1 && !is_wildcard(&token.atom) =>
+                    {
+                        if c == '?' {
+                            token.min = 0;
+                        } else {
+                            token.max = usize::MAX;
+                        }
+                        continue;
+                    }
+                    _ => Atom::Char(c),
+                },
+                '[' => match class(&mut chars) {
+                    Some(ranges) => Atom::Class(ranges),
+                    None => Atom::Char('['),
+                },
+                '\\' => Atom::Char(chars.next().unwrap_or('\\')),
+                c => Atom::Char(c),
+            };
+
+            let (min, max) = if is_wildcard(&atom) {
+                (0, usize::MAX)
+            } else {
+                (1, 1)
+            };
+            tokens.push(Token { atom, min, max });
+        }
+
+        Self { tokens, negated }
+    }
+
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    /// Whether `name` matches the pattern, ignoring a leading `!`.
+    pub fn matches(&self, name: &str) -> bool {
+        let name = name.chars().collect::<Vec<_>>();
+        let mut failed = vec![false; (self.tokens.len() + 1) * (name.len() + 1)];
+        self.match_from(0, 0, &name, &mut failed)
+    }
+
+    // `failed` remembers positions already known not to match, so patterns with many
+    // wildcards stay linear in practice
+    fn match_from(
+        &self,
+        token: usize,
+        position: usize,
+        name: &[char],
+        failed: &mut [bool],
+    ) -> bool {
+        let key = token * (name.len() + 1) + position;
+        if failed[key] {
+            return false;
+        }
+
+        let matched = match self.tokens.get(token) {
+            None => position == name.len(),
+            Some(Token {
+                atom: Atom::Directories,
+                ..
+            }) => (position..=name.len())
+                .filter(|&end| end == position || name[end - 1] == '/')
+                .any(|end| self.match_from(token + 1, end, name, failed)),
+            Some(Token { atom, min, max }) => {
+                let mut matched = false;
+                let mut count = 0;
+                loop {
+                    if count >= *min && self.match_from(token + 1, position + count, name, failed) {
+                        matched = true;
+                        break;
+                    }
+                    if count == *max
+                        || position + count == name.len()
+                        || !atom.matches(name[position + count])
+                    {
+                        break;
+                    }
+                    count += 1;
+                }
+                matched
+            }
+        };
+
+        if !matched {
+            failed[key] = true;
+        }
+        matched
+    }
+}
+
+fn is_wildcard(atom: &Atom) -> bool {
+    matches!(atom, Atom::Segment | Atom::Anything | Atom::Directories)
+}
+
+// the ranges of a `[...]` set, with the opening bracket already read
+fn class(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<Vec<(char, char)>> {
+    let mut lookahead = chars.clone();
+    let mut ranges = Vec::new();
+    loop {
+        let from = match lookahead.next()? {
+            ']' if !ranges.is_empty() => break,
+            '\\' => lookahead.next()?,
+            c => c,
+        };
+        let to = if lookahead.peek() == Some(&'-') {
+            let mut range = lookahead.clone();
+            range.next();
+            match range.next()? {
+                ']' => from,
+                to => {
+                    lookahead = range;
+                    to
+                }
+            }
+        } else {
+            from
+        };
+        ranges.push((from, to));
+    }
+
+    *chars = lookahead;
+    Some(ranges)
+}
+
+/// Whether `name` passes `patterns`. Patterns apply in order and the last one that matches
+/// wins, so `['releases/**', '!releases/**-alpha']` matches `releases/1.0` but not
+/// `releases/1.0-alpha`.
+pub fn matches_any(patterns: &[String], name: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns.iter().map(|pattern| Pattern::new(pattern)) {
+        if pattern.matches(name) {
+            matched = !pattern.is_negated();
+        }
+    }
+    matched
+}
+
+/// Whether `name` passes an include and an ignore filter, where a filter that isn't set
+/// doesn't restrict anything.
+pub fn ref_matches(name: &str, include: Option<&[String]>, ignore: Option<&[String]>) -> bool {
+    include.is_none_or(|patterns| matches_any(patterns, name))
+        && ignore.is_none_or(|patterns| !matches_any(patterns, name))
+}
+
+/// Whether changed files pass the `paths` and `paths-ignore` filters: `paths` needs one of
+/// them to match, `paths-ignore` needs one of them not to.
+pub fn paths_match(
+    files: &[String],
+    include: Option<&[String]>,
+    ignore: Option<&[String]>,
+) -> bool {
+    include.is_none_or(|patterns| files.iter().any(|file| matches_any(patterns, file)))
+        && ignore.is_none_or(|patterns| files.iter().any(|file| !matches_any(patterns, file)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    // (patterns, names that pass, names that don't), mostly the examples of GitHub's
+    // filter pattern cheat sheet
+    const REFS: &[(&[&str], &[&str], &[&str])] = &[
+        (
+            &["feature/*"],
+            &["feature/my-branch", "feature/your-branch"],
+            &["feature/beta-a/my-branch", "feature", "features/x"],
+        ),
+        (
+            &["feature/**"],
+            &[
+                "feature/beta-a/my-branch",
+                "feature/your-branch",
+                "feature/mona/the/octocat",
+            ],
+            &["feature", "other/feature/x"],
+        ),
+        (
+            &["main", "releases/mona-the-octocat"],
+            &["main", "releases/mona-the-octocat"],
+            &["main2", "releases/mona", "mainline"],
+        ),
+        (&["*"], &["main", "releases"], &["releases/v1"]),
+        (&["**"], &["all/the/branches", "every/tag", "main"], &[]),
+        (
+            &["*feature"],
+            &["mona-feature", "feature", "ver-10-feature"],
+            &["feature-x", "a/feature"],
+        ),
+        (&["v2*"], &["v2", "v2.0", "v2.9"], &["v1", "v3.2", "v2/x"]),
+        (
+            &["v[12].[0-9]+.[0-9]+"],
+            &["v1.10.1", "v2.0.0", "v1.0.10"],
+            &["v3.0.0", "v1.0", "v1.a.0", "v12.0.0"],
+        ),
+        (&["colou?r"], &["color", "colour"], &["colouur", "colr"]),
+        (&["v1+"], &["v1", "v111"], &["v", "v12"]),
+        (
+            &["[a-c]x[d]"],
+            &["axd", "bxd", "cxd"],
+            &["dxd", "axe", "a-xd"],
+        ),
+        (&["[!-]"], &["!", "-"], &["a"]),
+        (&["release["], &["release["], &["release"]),
+        (&["v\\*"], &["v*"], &["v1"]),
+        (&["a\\?b"], &["a?b"], &["ab", "b"]),
+        (&["\\[x]"], &["[x]"], &["x"]),
+        (
+            &["releases/**", "!releases/**-alpha"],
+            &["releases/10", "releases/beta/mona"],
+            &["releases/10-alpha", "releases/beta/3-alpha", "main"],
+        ),
+        // a later pattern takes back what an exclusion removed
+        (
+            &["releases/**", "!releases/**-alpha", "releases/keep-alpha"],
+            &["releases/keep-alpha"],
+            &["releases/drop-alpha"],
+        ),
+        // an exclusion first excludes nothing
+        (&["!main", "*"], &["main", "dev"], &[]),
+        (&["!main"], &[], &["main", "dev"]),
+    ];
+
+    const PATHS: &[(&[&str], &[&str], &[&str])] = &[
+        (&["*"], &["README.md", "server.rb"], &["docs/README.md"]),
+        (
+            &["*.jsx?"],
+            &["page.js", "page.jsx"],
+            &["page.jsxx", "src/page.js"],
+        ),
+        (&["**"], &["all/the/files.md", "README.md"], &[]),
+        (
+            &["*.js"],
+            &["app.js", "index.js"],
+            &["js/index.js", "app.jsx"],
+        ),
+        (
+            &["**.js"],
+            &["index.js", "js/index.js", "src/js/app.js"],
+            &["index.jsx", "js/index.ts"],
+        ),
+        (
+            &["docs/*"],
+            &["docs/README.md", "docs/file.txt"],
+            &["docs/mona/octocat.txt", "README.md"],
+        ),
+        (
+            &["docs/**"],
+            &["docs/README.md", "docs/mona/octocat.txt"],
+            &["README.md", "src/docs/x"],
+        ),
+        (
+            &["docs/**/*.md"],
+            &[
+                "docs/README.md",
+                "docs/mona/hello-world.md",
+                "docs/a/markdown/file.md",
+            ],
+            &["docs/hello.txt", "README.md", "docsmd/x.md"],
+        ),
+        (
+            &["**/docs/**"],
+            &[
+                "docs/hello.md",
+                "dir/docs/my-file.txt",
+                "space/docs/plan/space.doc",
+            ],
+            &["mydocs/hello.md", "docs"],
+        ),
+        (
+            &["**/README.md"],
+            &["README.md", "js/README.md", "a/b/c/README.md"],
+            &["README.mdx", "js/NOT-README.md"],
+        ),
+        (
+            &["**/*src/**"],
+            &["a/src/app.js", "my-src/code/js/app.js"],
+            &["src", "a/source/app.js"],
+        ),
+        (
+            &["**/*-post.md"],
+            &["my-post.md", "path/their-post.md"],
+            &["post.md", "path/post.md"],
+        ),
+        (
+            &["**/migrate-*.sql"],
+            &[
+                "migrate-10909.sql",
+                "db/migrate-v1.0.sql",
+                "db/sept/migrate-v1.sql",
+            ],
+            &["migrate.sql", "db/migrate-v1/x.sql"],
+        ),
+        (
+            &["*.md", "!README.md"],
+            &["hello.md"],
+            &["README.md", "docs/hello.md"],
+        ),
+        (
+            &["*.md", "!README.md", "README*"],
+            &["hello.md", "README.md", "README.doc"],
+            &["docs/hello.md"],
+        ),
+    ];
+
+    fn check(table: &[(&[&str], &[&str], &[&str])]) {
+        for (patterns, passing, failing) in table {
+            let patterns = strings(patterns);
+            for name in *passing {
+                assert!(
+                    matches_any(&patterns, name),
+                    "{:?} should match {}",
+                    patterns,
+                    name
+                );
+            }
+            for name in *failing {
+                assert!(
+                    !matches_any(&patterns, name),
+                    "{:?} shouldn't match {}",
+                    patterns,
+                    name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ref_patterns() {
+        check(REFS);
+    }
+
+    #[test]
+    fn path_patterns() {
+        check(PATHS);
+    }
+
+    #[test]
+    fn quantifiers_after_wildcards_are_literal() {
+        assert!(Pattern::new("*?").matches("abc?"));
+        assert!(!Pattern::new("*?").matches("abc"));
+        assert!(Pattern::new("+").matches("+"));
+    }
+
+    #[test]
+    fn long_names_with_many_wildcards_match_quickly() {
+        let pattern = Pattern::new("**a**a**a**a**a**a**a**a**b");
+        let name = "a".repeat(2000);
+        assert!(!pattern.matches(&name));
+        assert!(pattern.matches(&format!("{}b", name)));
+    }
+
+    #[test]
+    fn ref_filters() {
+        let branches = strings(&["main", "releases/**"]);
+        let ignored = strings(&["releases/**-alpha"]);
+
+        assert!(ref_matches("main", None, None));
+        assert!(ref_matches("main", Some(&branches), None));
+        assert!(!ref_matches("dev", Some(&branches), None));
+        assert!(ref_matches("releases/1.0", Some(&branches), Some(&ignored)));
+        assert!(!ref_matches(
+            "releases/1.0-alpha",
+            Some(&branches),
+            Some(&ignored)
+        ));
+        assert!(ref_matches("dev", None, Some(&ignored)));
+        assert!(!ref_matches("main", Some(&[]), None));
+    }
+
+    #[test]
+    fn path_filters() {
+        let sources = strings(&["src/**", "Cargo.toml"]);
+        let docs = strings(&["docs/**", "**.md"]);
+        let files = |files: &[&str]| strings(files);
+
+        assert!(paths_match(&files(&["README.md"]), None, None));
+
+        assert!(paths_match(&files(&["src/main.rs"]), Some(&sources), None));
+        assert!(paths_match(
+            &files(&["docs/a.md", "Cargo.toml"]),
+            Some(&sources),
+            None
+        ));
+        assert!(!paths_match(&files(&["docs/a.md"]), Some(&sources), None));
+
+        // ignored only when every changed file is
+        assert!(!paths_match(
+            &files(&["docs/a.md", "README.md"]),
+            None,
+            Some(&docs)
+        ));
+        assert!(paths_match(
+            &files(&["docs/a.md", "src/main.rs"]),
+            None,
+            Some(&docs)
+        ));
+
+        assert!(paths_match(
+            &files(&["src/lib.rs", "src/README.md"]),
+            Some(&sources),
+            Some(&docs)
+        ));
+        assert!(!paths_match(
+            &files(&["src/README.md"]),
+            Some(&sources),
+            Some(&docs)
+        ));
+    }
+}
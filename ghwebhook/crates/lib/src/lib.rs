@@ -1,26 +1,410 @@
+use std::collections::BTreeSet;
 use std::path::Path;
 
-use crate::types::{githubevent::GithubEvent, workflow::GithubWorkflow};
+use crate::types::{
+    githubevent::GithubEvent,
+    workflow::{GithubWorkflow, GithubWorkflowEventTrigger},
+    workflowevent::WorkflowEvent,
+};
 
 pub mod errors;
+pub mod filters;
+pub mod script;
+pub mod secrets;
+pub mod store;
 pub mod types;
 
-pub fn clone_git_repo(repo_url: &str, dest: &Path) -> Result<git2::Repository, errors::AppError> {
-    let repo = git2::Repository::clone(repo_url, dest)
+/// Fetches `git_ref` from `repo_url` into `dest` and checks out `sha` with a detached HEAD,
+/// so the workflows that are read belong to the exact commit that triggered the event.
+/// Without a `sha` the commit `git_ref` points to is checked out, `HEAD` tells which.
+/// `token` is a github access token used for private repositories.
+pub fn clone_git_repo(
+    repo_url: &str,
+    dest: &Path,
+    git_ref: &str,
+    sha: Option<&str>,
+    token: Option<&str>,
+) -> Result<git2::Repository, errors::AppError> {
+    let repo = git2::Repository::init(dest)
         .map_err(|err| errors::AppError::GitCloneError(err.to_string()))?;
 
+    {
+        let mut remote = repo
+            .remote("origin", repo_url)
+            .map_err(|err| errors::AppError::GitCloneError(err.to_string()))?;
+
+        remote
+            .fetch(
+                &[format!("+{git_ref}:{git_ref}")],
+                Some(&mut fetch_options(token)),
+                None,
+            )
+            .map_err(|err| errors::AppError::GitCloneError(err.to_string()))?;
+
+        let oid = match sha {
+            Some(sha) => git2::Oid::from_str(sha).map_err(|err| {
+                errors::AppError::GitCheckoutError(format!("invalid sha {sha}: {err}"))
+            })?,
+            None => repo.refname_to_id(git_ref).map_err(|err| {
+                errors::AppError::GitCheckoutError(format!("{git_ref} not found: {err}"))
+            })?,
+        };
+
+        // the ref may have been force pushed past the commit already, try fetching it directly
+        if let Some(sha) = sha
+            && repo.find_commit(oid).is_err()
+        {
+            remote
+                .fetch(&[sha], Some(&mut fetch_options(token)), None)
+                .map_err(|err| {
+                    errors::AppError::GitCheckoutError(format!(
+                        "commit {sha} not found on {git_ref}: {err}"
+                    ))
+                })?;
+        }
+
+        let commit = repo.find_commit(oid).map_err(|err| {
+            errors::AppError::GitCheckoutError(format!(
+                "commit {oid} not found on {git_ref}: {err}"
+            ))
+        })?;
+
+        repo.checkout_tree(
+            commit.as_object(),
+            Some(git2::build::CheckoutBuilder::new().force()),
+        )
+        .map_err(|err| errors::AppError::GitCheckoutError(err.to_string()))?;
+
+        repo.set_head_detached(oid)
+            .map_err(|err| errors::AppError::GitCheckoutError(err.to_string()))?;
+    }
+
     Ok(repo)
 }
 
-pub fn should_trigger_workflow(workflow: GithubWorkflow, github_event: &GithubEvent) -> bool {
-    let ref_split = github_event.ref_.split('/').collect::<Vec<&str>>();
-    if ref_split[1] == "heads" {
-        if let Some(push_trigger) = workflow.on.push {
-            if push_trigger.branches.contains(&ref_split[2].to_string()) {
-                return true;
-            }
+fn fetch_options(token: Option<&str>) -> git2::FetchOptions<'_> {
+    let mut callbacks = git2::RemoteCallbacks::new();
+    if let Some(token) = token {
+        callbacks.credentials(move |_url, _username, _allowed| {
+            git2::Cred::userpass_plaintext("x-access-token", token)
+        });
+    }
+
+    let mut options = git2::FetchOptions::new();
+    options.remote_callbacks(callbacks);
+    options
+}
+
+// push payloads list at most this many commits
+const MAX_PUSH_COMMITS: usize = 2048;
+
+// `before` of a push that created its ref
+const NULL_SHA: &str = "0000000000000000000000000000000000000000";
+
+/// Files changed by an event, for its `paths` filters. Pushes list them in the commits of
+/// their payload, unless there are more commits than the payload lists, then they come from
+/// a diff of `before..after` in `repo`. A pull request changes what its head changed since
+/// it branched off its base. Missing commits are fetched with `token`.
+pub fn changed_files(
+    repo: &git2::Repository,
+    event: &WorkflowEvent,
+    token: Option<&str>,
+) -> Result<Vec<String>, errors::AppError> {
+    match event {
+        WorkflowEvent::Push(push) => push_changed_files(repo, push, token),
+        WorkflowEvent::PullRequest(pull_request)
+        | WorkflowEvent::PullRequestTarget(pull_request) => {
+            let base = &pull_request.pull_request.base.sha;
+            let head = &pull_request.pull_request.head.sha;
+            let head_ref = format!("refs/pull/{}/head", pull_request.number);
+            let diff_error = |err: git2::Error| {
+                errors::AppError::GitCheckoutError(format!(
+                    "failed to diff {}...{}: {}",
+                    base, head, err
+                ))
+            };
+
+            let base = find_or_fetch_commit(repo, base, token).map_err(diff_error)?;
+            let head = find_or_fetch_commit(repo, &head_ref, token)
+                .and_then(|_| find_or_fetch_commit(repo, head, token))
+                .map_err(diff_error)?;
+            let merge_base = repo
+                .merge_base(base.id(), head.id())
+                .and_then(|oid| repo.find_commit(oid))
+                .map_err(diff_error)?;
+            diff_files(repo, &merge_base, &head).map_err(diff_error)
+        }
+        // workflow_dispatch has no paths filters
+        WorkflowEvent::WorkflowDispatch(_) => Ok(Vec::new()),
+    }
+}
+
+// `name` is a sha, or a ref that is fetched to the same name
+fn find_or_fetch_commit<'r>(
+    repo: &'r git2::Repository,
+    name: &str,
+    token: Option<&str>,
+) -> Result<git2::Commit<'r>, git2::Error> {
+    let find = |repo: &'r git2::Repository| match git2::Oid::from_str(name) {
+        Ok(oid) if !name.starts_with("refs/") => repo.find_commit(oid),
+        _ => repo
+            .refname_to_id(name)
+            .and_then(|oid| repo.find_commit(oid)),
+    };
+    if let Ok(commit) = find(repo) {
+        return Ok(commit);
+    }
+
+    let refspec = if name.starts_with("refs/") {
+        format!("+{name}:{name}")
+    } else {
+        name.to_string()
+    };
+    repo.find_remote("origin")?
+        .fetch(&[refspec], Some(&mut fetch_options(token)), None)?;
+    find(repo)
+}
+
+fn diff_files(
+    repo: &git2::Repository,
+    from: &git2::Commit,
+    to: &git2::Commit,
+) -> Result<Vec<String>, git2::Error> {
+    let diff = repo.diff_tree_to_tree(Some(&from.tree()?), Some(&to.tree()?), None)?;
+    Ok(diff
+        .deltas()
+        .flat_map(|delta| [delta.old_file().path(), delta.new_file().path()])
+        .flatten()
+        .filter_map(|path| path.to_str().map(str::to_string))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect())
+}
+
+fn push_changed_files(
+    repo: &git2::Repository,
+    event: &GithubEvent,
+    token: Option<&str>,
+) -> Result<Vec<String>, errors::AppError> {
+    let before = event.before.as_deref().filter(|before| *before != NULL_SHA);
+    let Some(before) = before.filter(|_| event.commits.len() >= MAX_PUSH_COMMITS) else {
+        return Ok(event
+            .commits
+            .iter()
+            .flat_map(|commit| [&commit.added, &commit.modified, &commit.removed])
+            .flatten()
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect());
+    };
+
+    let diff_error = |err: git2::Error| {
+        errors::AppError::GitCheckoutError(format!(
+            "failed to diff {}..{}: {}",
+            before, event.after, err
+        ))
+    };
+    let before = find_or_fetch_commit(repo, before, token).map_err(diff_error)?;
+    let after = find_or_fetch_commit(repo, &event.after, token).map_err(diff_error)?;
+    diff_files(repo, &before, &after).map_err(diff_error)
+}
+
+/// Whether `workflow` runs on `event`, going by the event's filters in `on:`.
+/// `changed_files` are the files the event changed, only `paths` filters look at them.
+pub fn should_trigger_workflow(
+    workflow: &GithubWorkflow,
+    event: &WorkflowEvent,
+    changed_files: &[String],
+) -> bool {
+    let Some(trigger) = workflow.on.event(event.name()) else {
+        return false;
+    };
+
+    match event {
+        WorkflowEvent::Push(push) => push_matches(trigger, &push.ref_, changed_files),
+        // branches are the base branch of the pull request
+        WorkflowEvent::PullRequest(pull_request)
+        | WorkflowEvent::PullRequestTarget(pull_request) => {
+            event.matches_types(trigger.types.as_deref())
+                && filters::ref_matches(
+                    &pull_request.pull_request.base.ref_,
+                    trigger.branches.as_deref(),
+                    trigger.branches_ignore.as_deref(),
+                )
+                && filters::paths_match(
+                    changed_files,
+                    trigger.paths.as_deref(),
+                    trigger.paths_ignore.as_deref(),
+                )
         }
+        // the event names the workflow, which only has to accept it
+        WorkflowEvent::WorkflowDispatch(_) => true,
     }
+}
+
+/// Whether a workflow filters the files an event changed, which then have to be looked up.
+pub fn filters_paths(workflow: &GithubWorkflow, event: &WorkflowEvent) -> bool {
+    workflow
+        .on
+        .event(event.name())
+        .is_some_and(|trigger| trigger.paths.is_some() || trigger.paths_ignore.is_some())
+}
 
-    return false;
+// A workflow that only filters branches doesn't run for tags and vice versa, one without
+// branch or tag filters runs for both. Paths aren't filtered for tags.
+fn push_matches(
+    trigger: &GithubWorkflowEventTrigger,
+    git_ref: &str,
+    changed_files: &[String],
+) -> bool {
+    let filters_branches = trigger.branches.is_some() || trigger.branches_ignore.is_some();
+    let filters_tags = trigger.tags.is_some() || trigger.tags_ignore.is_some();
+
+    if let Some(branch) = git_ref.strip_prefix("refs/heads/") {
+        if !filters_branches && filters_tags {
+            return false;
+        }
+        filters::ref_matches(
+            branch,
+            trigger.branches.as_deref(),
+            trigger.branches_ignore.as_deref(),
+        ) && filters::paths_match(
+            changed_files,
+            trigger.paths.as_deref(),
+            trigger.paths_ignore.as_deref(),
+        )
+    } else if let Some(tag) = git_ref.strip_prefix("refs/tags/") {
+        if !filters_tags {
+            return !filters_branches;
+        }
+        filters::ref_matches(tag, trigger.tags.as_deref(), trigger.tags_ignore.as_deref())
+    } else {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempdir::TempDir;
+
+    // commits `content` as `file` on top of `parent`, without moving any ref
+    fn commit(repo: &git2::Repository, parent: Option<git2::Oid>, content: &str) -> git2::Oid {
+        let blob = repo.blob(content.as_bytes()).unwrap();
+        let mut tree = repo.treebuilder(None).unwrap();
+        tree.insert("file", blob, 0o100644).unwrap();
+        let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+        let signature = git2::Signature::now("octo", "octo@example.com").unwrap();
+        let parents = parent
+            .map(|oid| repo.find_commit(oid).unwrap())
+            .into_iter()
+            .collect::<Vec<_>>();
+        repo.commit(
+            None,
+            &signature,
+            &signature,
+            content,
+            &tree,
+            &parents.iter().collect::<Vec<_>>(),
+        )
+        .unwrap()
+    }
+
+    fn origin() -> (TempDir, git2::Repository) {
+        let dir = TempDir::new("origin").unwrap();
+        let repo = git2::Repository::init_bare(dir.path()).unwrap();
+        (dir, repo)
+    }
+
+    fn read_file(repo: &git2::Repository) -> String {
+        std::fs::read_to_string(repo.workdir().unwrap().join("file")).unwrap()
+    }
+
+    #[test]
+    fn feature_branch_commits_are_checked_out() {
+        let (origin_dir, origin) = origin();
+        let main = commit(&origin, None, "main");
+        let feature = commit(&origin, Some(main), "feature");
+        origin.reference("refs/heads/main", main, true, "").unwrap();
+        origin
+            .reference("refs/heads/feature", feature, true, "")
+            .unwrap();
+
+        let dest = TempDir::new("clone").unwrap();
+        let repo = clone_git_repo(
+            origin_dir.path().to_str().unwrap(),
+            dest.path(),
+            "refs/heads/feature",
+            Some(&feature.to_string()),
+            None,
+        )
+        .unwrap();
+        assert_eq!(repo.head().unwrap().target(), Some(feature));
+        assert!(repo.head_detached().unwrap());
+        assert_eq!(read_file(&repo), "feature");
+    }
+
+    #[test]
+    fn commits_the_ref_was_force_pushed_past_are_fetched_directly() {
+        let (origin_dir, origin) = origin();
+        let main = commit(&origin, None, "main");
+        let pushed = commit(&origin, Some(main), "pushed");
+        let force_pushed = commit(&origin, Some(main), "force pushed");
+        origin
+            .reference("refs/heads/feature", force_pushed, true, "")
+            .unwrap();
+        // github keeps serving commits that no branch points to anymore
+        origin
+            .reference("refs/keep/pushed", pushed, true, "")
+            .unwrap();
+
+        let dest = TempDir::new("clone").unwrap();
+        let repo = clone_git_repo(
+            origin_dir.path().to_str().unwrap(),
+            dest.path(),
+            "refs/heads/feature",
+            Some(&pushed.to_string()),
+            None,
+        )
+        .unwrap();
+        assert_eq!(repo.head().unwrap().target(), Some(pushed));
+        assert_eq!(read_file(&repo), "pushed");
+    }
+
+    #[test]
+    fn missing_commits_fail_the_checkout() {
+        let (origin_dir, origin) = origin();
+        let main = commit(&origin, None, "main");
+        origin.reference("refs/heads/main", main, true, "").unwrap();
+
+        let dest = TempDir::new("clone").unwrap();
+        let result = clone_git_repo(
+            origin_dir.path().to_str().unwrap(),
+            dest.path(),
+            "refs/heads/main",
+            Some("3f786850e387550fdab836ed7e6dc881de23001b"),
+            None,
+        );
+        assert!(matches!(result, Err(errors::AppError::GitCheckoutError(_))));
+    }
+
+    #[test]
+    fn without_a_sha_the_ref_is_checked_out() {
+        let (origin_dir, origin) = origin();
+        let main = commit(&origin, None, "main");
+        origin.reference("refs/heads/main", main, true, "").unwrap();
+
+        let dest = TempDir::new("clone").unwrap();
+        let repo = clone_git_repo(
+            origin_dir.path().to_str().unwrap(),
+            dest.path(),
+            "refs/heads/main",
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(repo.head().unwrap().target(), Some(main));
+    }
 }
@@ -0,0 +1,103 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::types::workflow::GithubWorkflowJob;
+
+/// A piece of a workflow value: literal text, or a `${{ ... }}` expression the worker
+/// understands. Other expressions are kept as literal text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment<'a> {
+    Literal(&'a str),
+    /// `${{ secrets.NAME }}`, with the name upper-cased
+    Secret(String),
+    /// `${{ inputs.name }}` or `${{ github.event.inputs.name }}`, an input of a
+    /// `workflow_dispatch` event
+    Input(String),
+}
+
+/// Splits `text` into literal text and expressions.
+pub fn parse(text: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${{") {
+        let Some(length) = rest[start..].find("}}") else {
+            break;
+        };
+        let end = start + length + 2;
+
+        match expression(rest[start + 3..start + length].trim()) {
+            Some(segment) => {
+                if start > 0 {
+                    segments.push(Segment::Literal(&rest[..start]));
+                }
+                segments.push(segment);
+            }
+            None => segments.push(Segment::Literal(&rest[..end])),
+        }
+        rest = &rest[end..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Literal(rest));
+    }
+    segments
+}
+
+fn expression(expression: &str) -> Option<Segment<'static>> {
+    if let Some(name) = expression.strip_prefix("secrets.") {
+        let valid = !name.is_empty() && name.chars().all(|c| c == '_' || c.is_ascii_alphanumeric());
+        return valid.then(|| Segment::Secret(name.to_ascii_uppercase()));
+    }
+
+    let name = expression
+        .strip_prefix("inputs.")
+        .or_else(|| expression.strip_prefix("github.event.inputs."))?;
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c == '_' || c == '-' || c.is_ascii_alphanumeric());
+    valid.then(|| Segment::Input(name.to_string()))
+}
+
+/// The value of the input `name`, which like every context is case insensitive. Inputs the
+/// workflow doesn't declare are empty.
+pub fn input_value<'a>(inputs: &'a BTreeMap<String, String>, name: &str) -> &'a str {
+    inputs
+        .iter()
+        .find(|(input, _)| input.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+        .unwrap_or_default()
+}
+
+/// `text` with the inputs it refers to filled in. Other expressions are kept as written.
+pub fn render_inputs(text: &str, inputs: &BTreeMap<String, String>) -> String {
+    parse(text)
+        .into_iter()
+        .map(|segment| match segment {
+            Segment::Literal(text) => text.to_string(),
+            Segment::Secret(name) => format!("${{{{ secrets.{} }}}}", name),
+            Segment::Input(name) => input_value(inputs, &name).to_string(),
+        })
+        .collect()
+}
+
+pub fn has_secrets(text: &str) -> bool {
+    parse(text)
+        .iter()
+        .any(|segment| matches!(segment, Segment::Secret(_)))
+}
+
+/// Names of the secrets the steps of `job` refer to, in `run:` and `with:`.
+pub fn job_secret_names(job: &GithubWorkflowJob) -> BTreeSet<String> {
+    let values = job.steps.iter().flat_map(|step| {
+        step.run
+            .iter()
+            .chain(step.with.iter().flat_map(|with| with.values()))
+    });
+
+    values
+        .flat_map(|value| parse(value))
+        .filter_map(|segment| match segment {
+            Segment::Secret(name) => Some(name),
+            Segment::Literal(_) | Segment::Input(_) => None,
+        })
+        .collect()
+}
@@ -0,0 +1,864 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::errors::AppError;
+use crate::types::workflow::{GithubWorkflow, GithubWorkflowJob, GithubWorkflowJobStep};
+
+use expressions::Segment;
+
+pub mod expressions;
+
+/// The event a job runs for, as seen by its steps.
+#[derive(Debug, Clone)]
+pub struct EventContext {
+    // `owner/name`
+    pub repository: String,
+    pub repository_name: String,
+    // the repository the job's commit is fetched from
+    pub clone_url: String,
+    pub git_ref: String,
+    pub sha: String,
+    // `push`, `pull_request`, ...
+    pub event_name: String,
+    // the branches of a pull request
+    pub head_ref: Option<String>,
+    pub base_ref: Option<String>,
+    // inputs of a workflow_dispatch event, validated and with their defaults
+    pub inputs: BTreeMap<String, String>,
+}
+
+/// Name of the variable in the job's environment that holds the GitHub token. Scripts only
+/// refer to it, slurmctld keeps submitted scripts where every cluster user can read them.
+pub const GITHUB_TOKEN_VARIABLE: &str = "GITHUB_TOKEN";
+
+// passed on to the steps by name, so their values never appear on a command line either
+const SECRET_VARIABLES: &[&str] = &[GITHUB_TOKEN_VARIABLE, "INPUT_TOKEN"];
+
+/// Name of the variable in the job's environment that holds the secret `name`.
+pub fn secret_variable(name: &str) -> String {
+    if name == GITHUB_TOKEN_VARIABLE {
+        return GITHUB_TOKEN_VARIABLE.to_string();
+    }
+    format!("SLURM_ACTIONS_SECRET_{}", name)
+}
+
+/// Where things live on the compute nodes.
+#[derive(Debug, Clone)]
+pub struct ScriptConfig {
+    // PATH of the steps
+    pub path: String,
+    pub node: String,
+    pub temp_dir: String,
+}
+
+impl Default for ScriptConfig {
+    fn default() -> Self {
+        Self {
+            path: "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin:/snap/bin"
+                .to_string(),
+            node: "/usr/bin/node".to_string(),
+            temp_dir: "/tmp".to_string(),
+        }
+    }
+}
+
+/// A step of a job, resolved from its workflow definition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptStep {
+    /// `uses:` a JavaScript action, run from its clone in the actions cache.
+    Action {
+        name: String,
+        // `owner/name`, the ref is ignored for now
+        repository: String,
+        inputs: Vec<(String, String)>,
+    },
+    /// `run:` a bash command.
+    Run { name: String, command: String },
+    /// A step with neither, which only logs its name.
+    Empty { name: String },
+}
+
+impl ScriptStep {
+    pub fn from_workflow(step: &GithubWorkflowJobStep) -> Self {
+        // TODO: set a default step name
+        let name = step.name.clone().unwrap_or_default();
+        if let Some(uses) = &step.uses {
+            let mut inputs = step
+                .with
+                .iter()
+                .flatten()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect::<Vec<_>>();
+            // `with:` is a map, sorted so the script doesn't change between runs
+            inputs.sort();
+            ScriptStep::Action {
+                name,
+                repository: action_repository(uses).to_string(),
+                inputs,
+            }
+        } else if let Some(run) = &step.run {
+            ScriptStep::Run {
+                name,
+                command: run.clone(),
+            }
+        } else {
+            ScriptStep::Empty { name }
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            ScriptStep::Action { name, .. }
+            | ScriptStep::Run { name, .. }
+            | ScriptStep::Empty { name } => name,
+        }
+    }
+}
+
+/// The partition `job` runs on, `runs-on:` with the inputs it refers to filled in.
+pub fn job_partition(job: &GithubWorkflowJob, context: &EventContext) -> String {
+    expressions::render_inputs(&job.runs_on, &context.inputs)
+}
+
+fn action_repository(uses: &str) -> &str {
+    uses.split('@').next().unwrap_or(uses)
+}
+
+/// Sections of an sbatch script, rendered in order: `#SBATCH` directives, exported
+/// variables, a cleanup trap, setup commands and the steps.
+#[derive(Debug, Default, Clone)]
+pub struct BatchScript {
+    directives: Vec<(String, String)>,
+    environment: Vec<(String, String)>,
+    cleanup: Vec<String>,
+    setup: Vec<String>,
+    steps: Vec<(String, String)>,
+}
+
+impl BatchScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn directive(mut self, option: &str, value: impl Into<String>) -> Self {
+        self.directives.push((option.to_string(), value.into()));
+        self
+    }
+
+    /// Exports `key`. The value is a shell word, so it may refer to earlier variables.
+    pub fn env(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.environment.push((key.to_string(), value.into()));
+        self
+    }
+
+    /// Adds a command run on exit, whether the job succeeded or not.
+    pub fn cleanup(mut self, command: impl Into<String>) -> Self {
+        self.cleanup.push(command.into());
+        self
+    }
+
+    pub fn setup(mut self, command: impl Into<String>) -> Self {
+        self.setup.push(command.into());
+        self
+    }
+
+    pub fn step(mut self, name: &str, command: impl Into<String>) -> Self {
+        self.steps.push((name.to_string(), command.into()));
+        self
+    }
+
+    /// Fails when a directive value is empty or contains whitespace, which would end the
+    /// directive or, for a line break, start a command.
+    pub fn render(&self) -> Result<String, AppError> {
+        let mut script = String::from("#!/bin/bash\n");
+        for (option, value) in &self.directives {
+            if value.is_empty() || value.chars().any(|c| c.is_whitespace() || c.is_control()) {
+                return Err(AppError::ScriptError(format!(
+                    "invalid value for --{}: {:?}",
+                    option, value
+                )));
+            }
+            script.push_str(&format!("#SBATCH --{}={}\n", option, value));
+        }
+
+        script.push_str("\nset -e\n\n");
+        for (key, value) in &self.environment {
+            script.push_str(&format!("export {}={}\n", key, value));
+        }
+
+        // the job's exit code is kept, the cleanup commands' are not
+        script.push_str("\ncleanup() {\n    local exit_code=$?\n");
+        for command in &self.cleanup {
+            script.push_str(&format!("    {}\n", command));
+        }
+        script.push_str("    exit $exit_code\n}\n\ntrap cleanup EXIT\n");
+
+        for command in &self.setup {
+            script.push_str(&format!("\n{}\n", command));
+        }
+
+        for (name, command) in &self.steps {
+            script.push_str(&format!(
+                "\necho {}\n{}\n",
+                shell_quote(&format!("Running step: {}", name)),
+                command
+            ));
+        }
+
+        Ok(script)
+    }
+}
+
+/// Renders the batch script of `job`, one of the jobs of `workflow`. Fails when `runs-on:`,
+/// with its inputs filled in, or the job's name isn't a valid directive value.
+pub fn render_job(
+    workflow: &GithubWorkflow,
+    job_name: &str,
+    job: &GithubWorkflowJob,
+    context: &EventContext,
+    config: &ScriptConfig,
+) -> Result<String, AppError> {
+    let job_prefix = format!(
+        "{}/{}_{}",
+        config.temp_dir, context.repository_name, job_name
+    );
+    let actions_cache_dir = format!("{}/actions_cache", job_prefix);
+
+    // actions of every job in the workflow are cloned
+    let actions = workflow
+        .jobs
+        .values()
+        .flat_map(|job| job.steps.iter())
+        .filter_map(|step| step.uses.as_deref())
+        .map(|uses| shell_quote(action_repository(uses)))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    let mut secret_variables = SECRET_VARIABLES
+        .iter()
+        .map(|variable| variable.to_string())
+        .collect::<Vec<_>>();
+    for name in expressions::job_secret_names(job) {
+        let variable = secret_variable(&name);
+        if !secret_variables.contains(&variable) {
+            secret_variables.push(variable);
+        }
+    }
+
+    let mut script = BatchScript::new()
+        .directive("job-name", job_name)
+        .directive("ntasks", "1")
+        .directive("partition", job_partition(job, context))
+        .directive("nodes", "1")
+        .directive(
+            "output",
+            format!("{}_{}_%j.log", context.repository_name, job_name),
+        )
+        .directive(
+            "error",
+            format!("{}_{}_%j.err", context.repository_name, job_name),
+        )
+        .env(
+            "WORK_DIR",
+            format!("{}_\"${{SLURM_JOB_ID}}\"", shell_quote(&job_prefix)),
+        )
+        .env("STEPS_DIR", "\"${WORK_DIR}_steps\"")
+        .env("REPOS", format!("({})", actions.join(" ")))
+        .env("ACTIONS_CACHE_DIR", shell_quote(&actions_cache_dir))
+        .env("NUM_TASKS", "${#REPOS[@]}")
+        .env("REPOS_STR", "\"${REPOS[*]}\"")
+        // actions/checkout fails without INPUT_TOKEN
+        .env("INPUT_TOKEN", format!("\"${}\"", GITHUB_TOKEN_VARIABLE))
+        .cleanup("echo \"\"")
+        .cleanup("echo \"Cleanup\"")
+        .cleanup("cd /")
+        .cleanup("rm -rf \"$WORK_DIR\"")
+        .cleanup("rm -rf \"$STEPS_DIR\"")
+        .cleanup("rm -rf \"$ACTIONS_CACHE_DIR\"")
+        .cleanup("echo \"\"")
+        .cleanup("echo \"==========================================\"")
+        .cleanup("echo \"Workflow completed at: $(date)\"")
+        .cleanup("echo \"==========================================\"")
+        // lets the masks flush the output before the job ends
+        .cleanup("exec >&- 2>&-")
+        .cleanup("wait \"${MASK_PIDS[@]}\" || true")
+        .setup(mask_secrets(&secret_variables))
+        .setup("srun [ -d \"$WORK_DIR\" ] || mkdir -p \"$WORK_DIR\"")
+        .setup(checkout_command(context, config))
+        .setup("mkdir -p \"$STEPS_DIR\"")
+        .setup("echo \"Setting up third party actions\"")
+        .setup("srun [ -d \"$ACTIONS_CACHE_DIR\" ] || mkdir -p \"$ACTIONS_CACHE_DIR\"")
+        .setup(CLONE_ACTIONS);
+
+    for (position, step) in job.steps.iter().map(ScriptStep::from_workflow).enumerate() {
+        let command = step_command(
+            position,
+            &step,
+            &actions_cache_dir,
+            &secret_variables,
+            context,
+            config,
+        );
+        script = script.step(step.name(), command);
+    }
+
+    script.render()
+}
+
+// clones every action into the actions cache, one task for all of them
+const CLONE_ACTIONS: &str = r#"# Setup third party actions
+srun --ntasks=1  bash -c '
+    cd "$ACTIONS_CACHE_DIR"
+    IFS=" " read -r -a REPOS <<< "$REPOS_STR"
+
+    for i in "${REPOS[@]}"; do
+echo "Setting up third party action: $i"
+
+[ -d "$i" ] || mkdir -p "$i"
+pushd "$i"
+
+git clone "https://github.com/$i" .
+
+echo "Setup third party action: $i"
+
+popd
+    done
+'"#;
+
+// fetches GITHUB_SHA into the work dir, the ref it was read from when the server doesn't
+// serve single commits. The token is handed to git by a credential helper reading it from
+// the environment, so it isn't part of the script or any command line.
+const CHECKOUT: &str = r#"git init -q .
+fetch() {
+    git -c credential.helper= \
+        -c credential.helper='!f() { echo username=x-access-token; echo "password=$GITHUB_TOKEN"; }; f' \
+        fetch -q --no-tags "$CLONE_URL" "$@"
+}
+fetch --depth=1 "$GITHUB_SHA" || fetch "$GITHUB_REF"
+git checkout -q --detach "$GITHUB_SHA""#;
+
+fn checkout_command(context: &EventContext, config: &ScriptConfig) -> String {
+    format!(
+        "echo \"Checking out {}\"\nsrun --chdir=\"$WORK_DIR\" --export={} /usr/bin/env \\\n    {} \\\n    {} \\\n    {} \\\n    {} \\\n    bash --noprofile --norc -ec {}",
+        context.sha,
+        GITHUB_TOKEN_VARIABLE,
+        shell_quote(&format!("CLONE_URL={}", context.clone_url)),
+        shell_quote(&format!("GITHUB_REF={}", context.git_ref)),
+        shell_quote(&format!("GITHUB_SHA={}", context.sha)),
+        shell_quote(&format!("PATH={}", config.path)),
+        shell_quote(CHECKOUT)
+    )
+}
+
+// Step bodies and values from the workflow never pass through the shell: run steps are
+// written to a file through a quoted heredoc, and every variable is a single quoted
+// argument of `env`, which keeps commas, quotes and newlines intact. srun passes on only
+// the secrets, everything else the step sees is set here. Secrets referenced by a step
+// are filled in by a launcher inside the task, so their values stay off every command
+// line and out of the submitted script.
+fn step_command(
+    position: usize,
+    step: &ScriptStep,
+    actions_cache_dir: &str,
+    secret_variables: &[String],
+    context: &EventContext,
+    config: &ScriptConfig,
+) -> String {
+    let mut environment = vec![
+        "\"GITHUB_WORKSPACE=$WORK_DIR\"".to_string(),
+        shell_quote(&format!("GITHUB_REPOSITORY={}", context.repository)),
+        shell_quote(&format!("GITHUB_REF={}", context.git_ref)),
+        shell_quote(&format!("GITHUB_SHA={}", context.sha)),
+        shell_quote(&format!("GITHUB_EVENT_NAME={}", context.event_name)),
+        shell_quote(&format!("PATH={}", config.path)),
+        shell_quote(&format!("RUNNER_TEMP={}", config.temp_dir)),
+    ];
+    if let Some(head_ref) = &context.head_ref {
+        environment.push(shell_quote(&format!("GITHUB_HEAD_REF={}", head_ref)));
+    }
+    if let Some(base_ref) = &context.base_ref {
+        environment.push(shell_quote(&format!("GITHUB_BASE_REF={}", base_ref)));
+    }
+
+    let (prelude, command) = match step {
+        ScriptStep::Action {
+            repository, inputs, ..
+        } => {
+            let entrypoint = format!(
+                "{} {}",
+                shell_quote(&config.node),
+                shell_quote(&format!(
+                    "{}/{}/dist/index.js",
+                    actions_cache_dir, repository
+                ))
+            );
+
+            let mut exports = Vec::new();
+            let mut assignments = Vec::new();
+            for (key, value) in inputs {
+                // the name the actions toolkit reads the input from
+                let name = format!("INPUT_{}", key.replace(' ', "_").to_uppercase());
+                if !expressions::has_secrets(value) {
+                    let value = expressions::render_inputs(value, &context.inputs);
+                    environment.push(shell_quote(&format!("{}={}", name, value)));
+                } else if is_identifier(&name) {
+                    exports.push(format!("export {}={}", name, shell_word(value, context)));
+                } else {
+                    // bash can't export names like INPUT_FETCH-DEPTH, env can
+                    assignments.push(format!(
+                        "{}{}",
+                        shell_quote(&format!("{}=", name)),
+                        shell_word(value, context)
+                    ));
+                }
+            }
+
+            let command = if exports.is_empty() && assignments.is_empty() {
+                entrypoint
+            } else {
+                exports.push(format!(
+                    "exec /usr/bin/env {} \"$@\"",
+                    assignments.join(" ")
+                ));
+                format!(
+                    "bash --noprofile --norc -c {} step {}",
+                    shell_quote(&exports.join("\n")),
+                    entrypoint
+                )
+            };
+            (String::new(), command)
+        }
+        ScriptStep::Run { command, .. } => {
+            let path = format!("\"$STEPS_DIR/{}.sh\"", position);
+            let segments = expressions::parse(command);
+
+            // expressions are written in one spelling, so the launcher can find them
+            let mut body = String::new();
+            let mut secrets = BTreeSet::new();
+            for segment in segments {
+                match segment {
+                    Segment::Literal(text) => body.push_str(text),
+                    Segment::Secret(name) => {
+                        body.push_str(&secret_expression(&name));
+                        secrets.insert(name);
+                    }
+                    Segment::Input(name) => {
+                        body.push_str(expressions::input_value(&context.inputs, &name))
+                    }
+                }
+            }
+            if !body.ends_with('\n') {
+                body.push('\n');
+            }
+            let delimiter = heredoc_delimiter(&format!("SLURM_ACTIONS_STEP_{}", position), &body);
+            let prelude = format!("cat > {} <<'{}'\n{}{}\n", path, delimiter, body, delimiter);
+
+            // the shell GitHub runs `run:` steps with
+            let command = if secrets.is_empty() {
+                format!("bash --noprofile --norc -eo pipefail {}", path)
+            } else {
+                let mut launcher = vec!["umask 077".to_string(), "body=$(<\"$1\")".to_string()];
+                for name in &secrets {
+                    launcher.push(format!(
+                        "body=${{body//{}/\"${}\"}}",
+                        shell_quote(&secret_expression(name)),
+                        secret_variable(name)
+                    ));
+                }
+                launcher.push("printf '%s\\n' \"$body\" > \"$1.resolved\"".to_string());
+                launcher
+                    .push("exec bash --noprofile --norc -eo pipefail \"$1.resolved\"".to_string());
+                format!(
+                    "bash --noprofile --norc -c {} step {}",
+                    shell_quote(&launcher.join("\n")),
+                    path
+                )
+            };
+            (prelude, command)
+        }
+        ScriptStep::Empty { .. } => return String::new(),
+    };
+
+    format!(
+        "{}srun --chdir=\"$WORK_DIR\" --export={} /usr/bin/env \\\n    {} \\\n    {}",
+        prelude,
+        secret_variables.join(","),
+        environment.join(" \\\n    "),
+        command
+    )
+}
+
+fn secret_expression(name: &str) -> String {
+    format!("${{{{ secrets.{} }}}}", name)
+}
+
+// a shell word for `value` in which secrets expand to the variables holding them
+fn shell_word(value: &str, context: &EventContext) -> String {
+    let word = expressions::parse(value)
+        .into_iter()
+        .map(|segment| match segment {
+            Segment::Literal(text) => shell_quote(text),
+            Segment::Secret(name) => format!("\"${}\"", secret_variable(&name)),
+            Segment::Input(name) => shell_quote(expressions::input_value(&context.inputs, &name)),
+        })
+        .collect::<String>();
+    if word.is_empty() {
+        "''".to_string()
+    } else {
+        word
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|first| first == '_' || first.is_ascii_alphabetic())
+        && name.chars().all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+// Replaces every line of every secret with *** in the job's output, the way GitHub masks
+// secrets in logs
+fn mask_secrets(secret_variables: &[String]) -> String {
+    let secrets = secret_variables
+        .iter()
+        .map(|variable| format!("\"${}\"", variable))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    format!(
+        r#"SECRET_MASKS=()
+for secret in {secrets}; do
+    while IFS= read -r line; do
+        [ -n "$line" ] && SECRET_MASKS+=("$line")
+    done <<< "$secret"
+done
+mask() {{
+    while IFS= read -r line || [ -n "$line" ]; do
+        for secret in "${{SECRET_MASKS[@]}}"; do
+            line=${{line//"$secret"/***}}
+        done
+        printf '%s\n' "$line"
+    done
+}}
+exec > >(mask)
+MASK_PIDS=($!)
+exec 2> >(mask >&2)
+MASK_PIDS+=($!)"#
+    )
+}
+
+/// Returns a delimiter starting with `base` for a heredoc of `body`. A heredoc ends at the
+/// first line that equals its delimiter, so it must not be a line of the body.
+pub fn heredoc_delimiter(base: &str, body: &str) -> String {
+    let mut delimiter = base.to_string();
+    while body.lines().any(|line| line == delimiter) {
+        delimiter.push('_');
+    }
+    delimiter
+}
+
+/// Quotes `word` so the shell passes it on verbatim.
+pub fn shell_quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process::Command;
+
+    fn context(inputs: &[(&str, &str)]) -> EventContext {
+        EventContext {
+            repository: "octo/repo".to_string(),
+            repository_name: "repo".to_string(),
+            clone_url: "https://github.com/octo/repo.git".to_string(),
+            git_ref: "refs/heads/main".to_string(),
+            sha: "3f786850e387550fdab836ed7e6dc881de23001b".to_string(),
+            event_name: "workflow_dispatch".to_string(),
+            head_ref: None,
+            base_ref: None,
+            inputs: inputs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    fn render(workflow: &str, context: &EventContext) -> Result<String, AppError> {
+        let workflow: GithubWorkflow = serde_yaml::from_str(workflow).unwrap();
+        let (job_name, job) = workflow.jobs.iter().next().unwrap();
+        render_job(&workflow, job_name, job, context, &ScriptConfig::default())
+    }
+
+    // what bash prints for `script`
+    fn bash(script: &str) -> String {
+        let output = Command::new("bash").arg("-c").arg(script).output().unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    const NASTY: &[&str] = &[
+        "",
+        "it's",
+        "'",
+        r"'\''",
+        "\"double\" quotes",
+        "$(touch pwned)",
+        "`touch pwned`",
+        "${HOME} $PATH",
+        "first\nsecond\n",
+        "back\\slash",
+        "; rm -rf / #",
+    ];
+
+    #[test]
+    fn quoted_words_reach_commands_verbatim() {
+        for word in NASTY {
+            assert_eq!(
+                bash(&format!("printf %s {}", shell_quote(word))),
+                *word,
+                "{:?}",
+                word
+            );
+        }
+    }
+
+    #[test]
+    fn jobs_check_out_their_commit() {
+        let origin = tempdir::TempDir::new("origin").unwrap();
+        let work_dir = tempdir::TempDir::new("work").unwrap();
+        let origin_path = shell_quote(origin.path().to_str().unwrap());
+        let sha = bash(&format!(
+            "cd {origin_path}
+            git init -q
+            git -c user.name=octo -c user.email=octo@example.com commit -q --allow-empty -m first
+            git -c user.name=octo -c user.email=octo@example.com commit -q --allow-empty -m second
+            git rev-parse HEAD~1"
+        ));
+
+        let mut context = context(&[]);
+        context.clone_url = origin.path().to_str().unwrap().to_string();
+        context.git_ref = "HEAD".to_string();
+        context.sha = sha.trim().to_string();
+        let command = checkout_command(&context, &ScriptConfig::default());
+        assert!(!command.contains("hunter2"));
+
+        let head = bash(&format!(
+            "srun() {{ cd \"${{1#--chdir=}}\"; shift 2; \"$@\"; }}
+            export WORK_DIR={} GITHUB_TOKEN=hunter2
+            {{
+{command}
+}} >/dev/null
+            git -C \"$WORK_DIR\" rev-parse HEAD",
+            shell_quote(work_dir.path().to_str().unwrap())
+        ));
+        assert_eq!(head, sha);
+    }
+
+    #[test]
+    fn secrets_are_masked_line_by_line_in_stdout_and_stderr() {
+        let script = format!(
+            "{}
+            echo \"before first line after\"
+            printf 'second line\\n' >&2
+            printf '%s\\n' \"$SECRET\"
+            echo \"$OTHER\"
+            exec >&- 2>&-
+            wait \"${{MASK_PIDS[@]}}\"",
+            mask_secrets(&["SECRET".to_string(), "EMPTY".to_string()])
+        );
+        let output = Command::new("bash")
+            .arg("-c")
+            .arg(script)
+            .env("SECRET", "first line\n\nsecond line")
+            .env("EMPTY", "")
+            .env("OTHER", "not a secret")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "before *** after\n***\n\n***\nnot a secret\n"
+        );
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "***\n");
+    }
+
+    #[test]
+    fn heredocs_end_after_the_body() {
+        for body in [
+            "EOF\n",
+            "EOF\nEOF_\n",
+            "echo 'EOF'\nEOF \n EOF\nEOF\n",
+            "$(touch pwned) `id` ${HOME}\n",
+        ] {
+            let delimiter = heredoc_delimiter("EOF", body);
+            assert!(!body.lines().any(|line| line == delimiter));
+            let script = format!("cat <<'{}'\n{}{}\necho after", delimiter, body, delimiter);
+            assert_eq!(bash(&script), format!("{}after\n", body), "{:?}", body);
+        }
+    }
+
+    #[test]
+    fn run_steps_containing_the_delimiter_get_another_one() {
+        let script = render(
+            r#"
+on: workflow_dispatch
+jobs:
+  build:
+    runs-on: debug
+    steps:
+      - run: |
+          echo before
+          SLURM_ACTIONS_STEP_0
+          echo after
+"#,
+            &context(&[]),
+        )
+        .unwrap();
+
+        assert!(script.contains(
+            "<<'SLURM_ACTIONS_STEP_0_'\necho before\nSLURM_ACTIONS_STEP_0\necho after\nSLURM_ACTIONS_STEP_0_\n"
+        ));
+    }
+
+    #[test]
+    fn step_names_are_quoted() {
+        let script = render(
+            r#"
+on: workflow_dispatch
+jobs:
+  build:
+    runs-on: debug
+    steps:
+      - name: "$(touch pwned) 'quoted'\nsecond line"
+        run: "true"
+"#,
+            &context(&[]),
+        )
+        .unwrap();
+
+        let echo = format!(
+            "echo {}\n",
+            shell_quote("Running step: $(touch pwned) 'quoted'\nsecond line")
+        );
+        assert!(script.contains(&echo));
+    }
+
+    #[test]
+    fn action_inputs_are_single_arguments() {
+        let script = render(
+            r#"
+on: workflow_dispatch
+jobs:
+  build:
+    runs-on: debug
+    steps:
+      - uses: octo/action@v1
+        with:
+          path: "a b\n'c' $(touch pwned) ${{ inputs.target }}"
+"#,
+            &context(&[("target", "`id`; \"x\"")]),
+        )
+        .unwrap();
+
+        assert!(script.contains(&shell_quote(
+            "INPUT_PATH=a b\n'c' $(touch pwned) `id`; \"x\""
+        )));
+    }
+
+    #[test]
+    fn action_input_names_are_upper_cased() {
+        let script = render(
+            r#"
+on: workflow_dispatch
+jobs:
+  build:
+    runs-on: debug
+    steps:
+      - uses: actions/checkout@v4
+        with:
+          fetch-depth: 0
+          token: ${{ secrets.DEPLOY_TOKEN }}
+          ssh-key: ${{ secrets.DEPLOY_KEY }}
+          clean up: "false"
+"#,
+            &context(&[]),
+        )
+        .unwrap();
+
+        assert!(script.contains("'INPUT_FETCH-DEPTH=0'"));
+        assert!(script.contains("'INPUT_CLEAN_UP=false'"));
+        assert!(script.contains(r#"export INPUT_TOKEN="$SLURM_ACTIONS_SECRET_DEPLOY_TOKEN""#));
+        assert!(script.contains(r#"'\''INPUT_SSH-KEY='\''"$SLURM_ACTIONS_SECRET_DEPLOY_KEY""#));
+    }
+
+    #[test]
+    fn secret_references_stay_out_of_the_script() {
+        let script = render(
+            r#"
+on: workflow_dispatch
+jobs:
+  build:
+    runs-on: debug
+    steps:
+      - run: echo "${{ secrets.API_KEY }}" '${{ secrets.API_KEY }}'
+"#,
+            &context(&[]),
+        )
+        .unwrap();
+
+        assert!(script.contains("--export=GITHUB_TOKEN,INPUT_TOKEN,SLURM_ACTIONS_SECRET_API_KEY "));
+        assert!(script.contains(r#"/"$SLURM_ACTIONS_SECRET_API_KEY"}"#));
+    }
+
+    #[test]
+    fn partitions_from_inputs_must_be_one_word() {
+        let workflow = r#"
+on: workflow_dispatch
+jobs:
+  build:
+    runs-on: ${{ inputs.partition }}
+    steps:
+      - run: "true"
+"#;
+        assert!(
+            render(workflow, &context(&[("partition", "debug")]))
+                .unwrap()
+                .contains("#SBATCH --partition=debug\n")
+        );
+        for partition in [
+            "",
+            "debug --wrap=id",
+            "debug\n#SBATCH --uid=0",
+            "debug\rtouch pwned",
+            "debug\t--exclusive",
+        ] {
+            assert!(
+                matches!(
+                    render(workflow, &context(&[("partition", partition)])),
+                    Err(AppError::ScriptError(_))
+                ),
+                "{:?}",
+                partition
+            );
+        }
+    }
+
+    #[test]
+    fn job_names_must_be_one_word() {
+        let workflow = r#"
+on: workflow_dispatch
+jobs:
+  "build it":
+    runs-on: debug
+    steps:
+      - run: "true"
+"#;
+        assert!(matches!(
+            render(workflow, &context(&[])),
+            Err(AppError::ScriptError(_))
+        ));
+    }
+}
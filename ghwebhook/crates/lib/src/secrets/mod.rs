@@ -0,0 +1,243 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+use crate::errors::AppError;
+
+/// A secret value encrypted with the master key.
+#[derive(Debug, Clone)]
+pub struct SealedSecret {
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+/// Encrypts secret values at rest with AES-256-GCM. The installation, repository and name
+/// are authenticated along with the value, so a ciphertext copied to another secret's row
+/// doesn't decrypt.
+pub struct SecretCipher {
+    cipher: Aes256Gcm,
+}
+
+impl SecretCipher {
+    /// `key` is 32 random bytes, base64 encoded, e.g. from `openssl rand -base64 32`.
+    pub fn from_base64_key(key: &str) -> Result<Self, AppError> {
+        let key = STANDARD
+            .decode(key.trim())
+            .map_err(|err| AppError::SecretError(format!("invalid master key: {}", err)))?;
+        if key.len() != 32 {
+            return Err(AppError::SecretError(format!(
+                "master key must be 32 bytes, got {}",
+                key.len()
+            )));
+        }
+
+        Ok(Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+        })
+    }
+
+    pub fn seal(
+        &self,
+        installation_id: u64,
+        repository: &str,
+        name: &str,
+        value: &str,
+    ) -> Result<SealedSecret, AppError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = associated_data(installation_id, repository, name);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: value.as_bytes(),
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| AppError::SecretError(format!("failed to encrypt {}", name)))?;
+
+        Ok(SealedSecret {
+            nonce: nonce.to_vec(),
+            ciphertext,
+        })
+    }
+
+    pub fn open(
+        &self,
+        installation_id: u64,
+        repository: &str,
+        name: &str,
+        sealed: &SealedSecret,
+    ) -> Result<String, AppError> {
+        if sealed.nonce.len() != 12 {
+            return Err(AppError::SecretError(format!(
+                "{} has an invalid nonce",
+                name
+            )));
+        }
+
+        let aad = associated_data(installation_id, repository, name);
+        let value = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&sealed.nonce),
+                Payload {
+                    msg: &sealed.ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| {
+                AppError::SecretError(format!(
+                    "failed to decrypt {} of {}, was it sealed with another master key?",
+                    name, repository
+                ))
+            })?;
+
+        String::from_utf8(value)
+            .map_err(|_| AppError::SecretError(format!("{} is not valid UTF-8", name)))
+    }
+}
+
+fn associated_data(installation_id: u64, repository: &str, name: &str) -> Vec<u8> {
+    format!("{}\n{}\n{}", installation_id, repository, name).into_bytes()
+}
+
+/// Validates a secret name the way GitHub does and returns it upper-cased, secret names
+/// are case insensitive.
+pub fn normalize_name(name: &str) -> Result<String, AppError> {
+    let name = name.to_ascii_uppercase();
+    let valid = name
+        .chars()
+        .next()
+        .is_some_and(|first| first == '_' || first.is_ascii_alphabetic())
+        && name.chars().all(|c| c == '_' || c.is_ascii_alphanumeric());
+
+    if !valid {
+        return Err(AppError::SecretError(format!(
+            "{} is not a valid secret name, use letters, digits and underscores",
+            name
+        )));
+    }
+    if name.starts_with("GITHUB_") {
+        return Err(AppError::SecretError(format!(
+            "{} is reserved, secret names must not start with GITHUB_",
+            name
+        )));
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher(byte: u8) -> SecretCipher {
+        SecretCipher::from_base64_key(&STANDARD.encode([byte; 32])).unwrap()
+    }
+
+    #[test]
+    fn sealed_secrets_open_again() {
+        let cipher = cipher(1);
+        let value = "multi\nline 'value' ✓";
+        let sealed = cipher.seal(42, "octo/repo", "TOKEN", value).unwrap();
+
+        assert_eq!(sealed.nonce.len(), 12);
+        assert_ne!(sealed.ciphertext, value.as_bytes());
+        assert_eq!(
+            cipher.open(42, "octo/repo", "TOKEN", &sealed).unwrap(),
+            value
+        );
+
+        // every seal has a nonce of its own
+        let again = cipher.seal(42, "octo/repo", "TOKEN", value).unwrap();
+        assert_ne!(again.nonce, sealed.nonce);
+    }
+
+    #[test]
+    fn secrets_sealed_with_another_key_do_not_open() {
+        let sealed = cipher(1).seal(42, "octo/repo", "TOKEN", "value").unwrap();
+        assert!(matches!(
+            cipher(2).open(42, "octo/repo", "TOKEN", &sealed),
+            Err(AppError::SecretError(_))
+        ));
+    }
+
+    #[test]
+    fn secrets_only_open_for_the_secret_they_were_sealed_for() {
+        let cipher = cipher(1);
+        let sealed = cipher.seal(42, "octo/repo", "TOKEN", "value").unwrap();
+
+        for (installation_id, repository, name) in [
+            (43, "octo/repo", "TOKEN"),
+            (42, "octo/other", "TOKEN"),
+            (42, "octo/repo", "OTHER"),
+        ] {
+            assert!(
+                matches!(
+                    cipher.open(installation_id, repository, name, &sealed),
+                    Err(AppError::SecretError(_))
+                ),
+                "{} {} {}",
+                installation_id,
+                repository,
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn tampered_secrets_do_not_open() {
+        let cipher = cipher(1);
+        let mut sealed = cipher.seal(42, "octo/repo", "TOKEN", "value").unwrap();
+        sealed.ciphertext[0] ^= 1;
+        assert!(cipher.open(42, "octo/repo", "TOKEN", &sealed).is_err());
+
+        sealed.nonce.pop();
+        assert!(cipher.open(42, "octo/repo", "TOKEN", &sealed).is_err());
+    }
+
+    #[test]
+    fn master_keys_are_32_base64_encoded_bytes() {
+        assert!(SecretCipher::from_base64_key(&format!("{}\n", STANDARD.encode([0; 32]))).is_ok());
+        for key in [
+            STANDARD.encode([0; 16]),
+            "not base64!".to_string(),
+            String::new(),
+        ] {
+            assert!(
+                matches!(
+                    SecretCipher::from_base64_key(&key),
+                    Err(AppError::SecretError(_))
+                ),
+                "{:?}",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn secret_names_are_upper_cased() {
+        assert_eq!(normalize_name("deploy_key").unwrap(), "DEPLOY_KEY");
+        assert_eq!(normalize_name("_Token2").unwrap(), "_TOKEN2");
+    }
+
+    #[test]
+    fn invalid_and_reserved_names_are_rejected() {
+        for name in [
+            "",
+            "2FA",
+            "DEPLOY-KEY",
+            "DEPLOY KEY",
+            "KEY=VALUE",
+            "GITHUB_TOKEN",
+            "github_foo",
+        ] {
+            assert!(
+                matches!(normalize_name(name), Err(AppError::SecretError(_))),
+                "{:?}",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,1123 @@
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
+
+use crate::errors::AppError;
+use crate::secrets::SealedSecret;
+use crate::types::envelope::{QueueEnvelope, unix_timestamp};
+use crate::types::runstatus::RunStatus;
+
+// applied in order, the number of applied migrations is kept in PRAGMA user_version
+const MIGRATIONS: &[&str] = &[
+    r#"
+CREATE TABLE deliveries (
+    id TEXT PRIMARY KEY,
+    event TEXT NOT NULL,
+    installation_id INTEGER,
+    received_at INTEGER NOT NULL,
+    processed_at INTEGER
+);
+
+CREATE TABLE workflow_runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    delivery_id TEXT REFERENCES deliveries (id),
+    repository TEXT NOT NULL,
+    workflow TEXT NOT NULL,
+    event TEXT NOT NULL,
+    git_ref TEXT NOT NULL,
+    sha TEXT NOT NULL,
+    status TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+CREATE INDEX workflow_runs_repository ON workflow_runs (repository, created_at);
+CREATE INDEX workflow_runs_sha ON workflow_runs (sha);
+
+CREATE TABLE jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    run_id INTEGER NOT NULL REFERENCES workflow_runs (id),
+    name TEXT NOT NULL,
+    partition TEXT NOT NULL,
+    slurm_job_id INTEGER,
+    status TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+CREATE INDEX jobs_run_id ON jobs (run_id);
+CREATE INDEX jobs_slurm_job_id ON jobs (slurm_job_id);
+
+CREATE TABLE steps (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    job_id INTEGER NOT NULL REFERENCES jobs (id),
+    position INTEGER NOT NULL,
+    name TEXT,
+    uses TEXT,
+    run TEXT
+);
+CREATE INDEX steps_job_id ON steps (job_id);
+
+CREATE TABLE status_transitions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    job_id INTEGER NOT NULL REFERENCES jobs (id),
+    from_status TEXT,
+    to_status TEXT NOT NULL,
+    slurm_state TEXT,
+    at INTEGER NOT NULL
+);
+CREATE INDEX status_transitions_job_id ON status_transitions (job_id);
+"#,
+    r#"
+CREATE TABLE secrets (
+    installation_id INTEGER NOT NULL,
+    repository TEXT NOT NULL,
+    name TEXT NOT NULL,
+    nonce BLOB NOT NULL,
+    ciphertext BLOB NOT NULL,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (installation_id, repository, name)
+);
+"#,
+    r#"
+CREATE TABLE approvals (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    repository TEXT NOT NULL,
+    pull_request INTEGER NOT NULL,
+    head_sha TEXT NOT NULL,
+    author TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    envelope TEXT NOT NULL,
+    approved_by TEXT,
+    created_at INTEGER NOT NULL,
+    approved_at INTEGER
+);
+CREATE INDEX approvals_pull_request ON approvals (repository, pull_request);
+
+ALTER TABLE workflow_runs ADD COLUMN approval_id INTEGER REFERENCES approvals (id);
+"#,
+    r#"
+ALTER TABLE jobs ADD COLUMN installation_id INTEGER;
+ALTER TABLE jobs ADD COLUMN check_run_id INTEGER;
+"#,
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkflowRunRecord {
+    pub id: i64,
+    pub delivery_id: Option<String>,
+    pub repository: String,
+    pub workflow: String,
+    pub event: String,
+    pub git_ref: String,
+    pub sha: String,
+    pub status: String,
+    // the approval the run was held for, if it was
+    pub approval_id: Option<i64>,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobRecord {
+    pub id: i64,
+    pub run_id: i64,
+    pub name: String,
+    pub partition: String,
+    pub slurm_job_id: Option<u32>,
+    pub status: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub steps: Vec<StepRecord>,
+    pub transitions: Vec<TransitionRecord>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StepRecord {
+    pub position: u32,
+    pub name: Option<String>,
+    pub uses: Option<String>,
+    pub run: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TransitionRecord {
+    pub from_status: Option<String>,
+    pub to_status: String,
+    pub slurm_state: Option<String>,
+    pub at: u64,
+}
+
+/// A stored secret, without its value.
+#[derive(Debug, Clone, Serialize)]
+pub struct SecretRecord {
+    pub name: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+/// A pull request delivery whose workflow runs wait for a maintainer's approval.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApprovalRecord {
+    pub id: i64,
+    pub repository: String,
+    pub pull_request: u64,
+    pub head_sha: String,
+    pub author: String,
+    // why the runs need approval, e.g. that the author is a first-time contributor
+    pub reason: String,
+    // the held delivery, replayed once it is approved
+    #[serde(skip)]
+    pub envelope: String,
+    pub approved_by: Option<String>,
+    pub created_at: u64,
+    pub approved_at: Option<u64>,
+}
+
+/// A submitted job that hadn't finished when it was last polled, with what it takes to
+/// follow it again after a restart.
+#[derive(Debug, Clone)]
+pub struct UnfinishedJob {
+    pub job_id: i64,
+    pub slurm_job_id: u32,
+    pub repository: String,
+    pub workflow: String,
+    pub name: String,
+    pub partition: String,
+    pub status: RunStatus,
+    // the check run reporting the job, if there is one
+    pub installation_id: Option<u64>,
+    pub check_run_id: Option<u64>,
+}
+
+pub struct NewRun<'a> {
+    pub delivery_id: Option<&'a str>,
+    pub repository: &'a str,
+    pub workflow: &'a str,
+    pub event: &'a str,
+    pub git_ref: &'a str,
+    pub sha: &'a str,
+    // runs held for an approval wait for it instead of being queued
+    pub approval_id: Option<i64>,
+}
+
+pub struct NewApproval<'a> {
+    pub repository: &'a str,
+    pub pull_request: u64,
+    pub head_sha: &'a str,
+    pub author: &'a str,
+    pub reason: &'a str,
+    pub envelope: &'a str,
+}
+
+/// Embedded SQLite database of deliveries, workflow runs, their jobs and the Slurm job
+/// each of them was submitted as. Shared by the worker, which writes to it, and the api,
+/// which reads from it.
+pub struct RunStore {
+    conn: Mutex<Connection>,
+}
+
+const APPROVAL_SELECT: &str = "SELECT id, repository, pull_request, head_sha, author, reason, envelope, approved_by, created_at, approved_at FROM approvals";
+
+fn store_error(err: rusqlite::Error) -> AppError {
+    AppError::StoreError(err.to_string())
+}
+
+impl RunStore {
+    pub fn open(path: &Path) -> Result<Self, AppError> {
+        let mut conn = Connection::open(path).map_err(store_error)?;
+        conn.busy_timeout(std::time::Duration::from_secs(5))
+            .map_err(store_error)?;
+        // the worker and the api open the same file
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .map_err(store_error)?;
+        conn.pragma_update(None, "foreign_keys", true)
+            .map_err(store_error)?;
+
+        migrate(&mut conn)?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        // a panic while holding the lock can't leave a transaction open, the connection
+        // rolls it back when the transaction is dropped
+        self.conn.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Records a delivery, returning `false` if it has already been processed.
+    pub fn record_delivery(&self, envelope: &QueueEnvelope) -> Result<bool, AppError> {
+        let Some(delivery_id) = &envelope.delivery_id else {
+            return Ok(true);
+        };
+
+        let conn = self.conn();
+        conn.execute(
+            "INSERT OR IGNORE INTO deliveries (id, event, installation_id, received_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                delivery_id,
+                envelope.event,
+                envelope.installation_id,
+                envelope.received_at
+            ],
+        )
+        .map_err(store_error)?;
+
+        let processed_at: Option<u64> = conn
+            .query_row(
+                "SELECT processed_at FROM deliveries WHERE id = ?1",
+                params![delivery_id],
+                |row| row.get(0),
+            )
+            .map_err(store_error)?;
+
+        Ok(processed_at.is_none())
+    }
+
+    pub fn mark_delivery_processed(&self, delivery_id: &str) -> Result<(), AppError> {
+        self.conn()
+            .execute(
+                "UPDATE deliveries SET processed_at = ?1 WHERE id = ?2",
+                params![unix_timestamp(), delivery_id],
+            )
+            .map_err(store_error)?;
+        Ok(())
+    }
+
+    pub fn create_run(&self, run: &NewRun) -> Result<i64, AppError> {
+        let now = unix_timestamp();
+        let status = match run.approval_id {
+            Some(_) => RunStatus::WaitingForApproval,
+            None => RunStatus::Queued,
+        };
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO workflow_runs
+                (delivery_id, repository, workflow, event, git_ref, sha, status, approval_id, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?9)",
+            params![
+                run.delivery_id,
+                run.repository,
+                run.workflow,
+                run.event,
+                run.git_ref,
+                run.sha,
+                status.as_str(),
+                run.approval_id,
+                now
+            ],
+        )
+        .map_err(store_error)?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Queues a run that was waiting for approval, once its jobs are about to be submitted.
+    pub fn release_run(&self, run_id: i64) -> Result<(), AppError> {
+        self.conn()
+            .execute(
+                "UPDATE workflow_runs SET status = ?1, updated_at = ?2 WHERE id = ?3",
+                params![RunStatus::Queued.as_str(), unix_timestamp(), run_id],
+            )
+            .map_err(store_error)?;
+        Ok(())
+    }
+
+    /// Fails a run that never got to submit jobs.
+    pub fn fail_run(&self, run_id: i64) -> Result<(), AppError> {
+        self.conn()
+            .execute(
+                "UPDATE workflow_runs SET status = ?1, updated_at = ?2 WHERE id = ?3",
+                params![RunStatus::Failure.as_str(), unix_timestamp(), run_id],
+            )
+            .map_err(store_error)?;
+        Ok(())
+    }
+
+    pub fn create_job(
+        &self,
+        run_id: i64,
+        name: &str,
+        partition: &str,
+        steps: &[StepRecord],
+    ) -> Result<i64, AppError> {
+        let now = unix_timestamp();
+        let mut conn = self.conn();
+        let tx = conn.transaction().map_err(store_error)?;
+
+        tx.execute(
+            "INSERT INTO jobs (run_id, name, partition, status, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?5)",
+            params![run_id, name, partition, RunStatus::Queued.as_str(), now],
+        )
+        .map_err(store_error)?;
+        let job_id = tx.last_insert_rowid();
+
+        for step in steps {
+            tx.execute(
+                "INSERT INTO steps (job_id, position, name, uses, run) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![job_id, step.position, step.name, step.uses, step.run],
+            )
+            .map_err(store_error)?;
+        }
+
+        tx.commit().map_err(store_error)?;
+        Ok(job_id)
+    }
+
+    pub fn set_slurm_job_id(&self, job_id: i64, slurm_job_id: u32) -> Result<(), AppError> {
+        self.conn()
+            .execute(
+                "UPDATE jobs SET slurm_job_id = ?1, updated_at = ?2 WHERE id = ?3",
+                params![slurm_job_id, unix_timestamp(), job_id],
+            )
+            .map_err(store_error)?;
+        Ok(())
+    }
+
+    pub fn set_check_run(
+        &self,
+        job_id: i64,
+        installation_id: u64,
+        check_run_id: u64,
+    ) -> Result<(), AppError> {
+        self.conn()
+            .execute(
+                "UPDATE jobs SET installation_id = ?1, check_run_id = ?2 WHERE id = ?3",
+                params![installation_id, check_run_id, job_id],
+            )
+            .map_err(store_error)?;
+        Ok(())
+    }
+
+    /// Jobs submitted to Slurm that are still queued or running, oldest first.
+    pub fn unfinished_jobs(&self) -> Result<Vec<UnfinishedJob>, AppError> {
+        let conn = self.conn();
+        let mut statement = conn
+            .prepare(
+                "SELECT jobs.id, jobs.slurm_job_id, workflow_runs.repository, workflow_runs.workflow,
+                        jobs.name, jobs.partition, jobs.status, jobs.installation_id, jobs.check_run_id
+                 FROM jobs JOIN workflow_runs ON workflow_runs.id = jobs.run_id
+                 WHERE jobs.slurm_job_id IS NOT NULL AND jobs.status IN (?1, ?2)
+                 ORDER BY jobs.id",
+            )
+            .map_err(store_error)?;
+
+        statement
+            .query_map(
+                params![RunStatus::Queued.as_str(), RunStatus::InProgress.as_str()],
+                |row| {
+                    let status: String = row.get(6)?;
+                    Ok(UnfinishedJob {
+                        job_id: row.get(0)?,
+                        slurm_job_id: row.get(1)?,
+                        repository: row.get(2)?,
+                        workflow: row.get(3)?,
+                        name: row.get(4)?,
+                        partition: row.get(5)?,
+                        status: if status == RunStatus::InProgress.as_str() {
+                            RunStatus::InProgress
+                        } else {
+                            RunStatus::Queued
+                        },
+                        installation_id: row.get(7)?,
+                        check_run_id: row.get(8)?,
+                    })
+                },
+            )
+            .map_err(store_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(store_error)
+    }
+
+    /// Moves a job to `status`, records the transition and recomputes the status of the
+    /// workflow run it belongs to.
+    pub fn transition_job(
+        &self,
+        job_id: i64,
+        status: RunStatus,
+        slurm_state: Option<&str>,
+    ) -> Result<(), AppError> {
+        let now = unix_timestamp();
+        let mut conn = self.conn();
+        let tx = conn.transaction().map_err(store_error)?;
+
+        let (run_id, from_status): (i64, String) = tx
+            .query_row(
+                "SELECT run_id, status FROM jobs WHERE id = ?1",
+                params![job_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map_err(store_error)?;
+
+        tx.execute(
+            "UPDATE jobs SET status = ?1, updated_at = ?2 WHERE id = ?3",
+            params![status.as_str(), now, job_id],
+        )
+        .map_err(store_error)?;
+        tx.execute(
+            "INSERT INTO status_transitions (job_id, from_status, to_status, slurm_state, at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![job_id, from_status, status.as_str(), slurm_state, now],
+        )
+        .map_err(store_error)?;
+
+        let job_statuses = {
+            let mut statement = tx
+                .prepare("SELECT status FROM jobs WHERE run_id = ?1")
+                .map_err(store_error)?;
+            statement
+                .query_map(params![run_id], |row| row.get::<_, String>(0))
+                .map_err(store_error)?
+                .collect::<Result<Vec<String>, _>>()
+                .map_err(store_error)?
+        };
+        tx.execute(
+            "UPDATE workflow_runs SET status = ?1, updated_at = ?2 WHERE id = ?3",
+            params![run_status(&job_statuses), now, run_id],
+        )
+        .map_err(store_error)?;
+
+        tx.commit().map_err(store_error)?;
+        Ok(())
+    }
+
+    pub fn list_runs(
+        &self,
+        repository: Option<&str>,
+        limit: u32,
+    ) -> Result<Vec<WorkflowRunRecord>, AppError> {
+        let conn = self.conn();
+        let mut statement = conn
+            .prepare(
+                "SELECT id, delivery_id, repository, workflow, event, git_ref, sha, status, approval_id, created_at, updated_at
+                 FROM workflow_runs
+                 WHERE ?1 IS NULL OR repository = ?1
+                 ORDER BY id DESC
+                 LIMIT ?2",
+            )
+            .map_err(store_error)?;
+
+        statement
+            .query_map(params![repository, limit], run_from_row)
+            .map_err(store_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(store_error)
+    }
+
+    pub fn get_run(
+        &self,
+        run_id: i64,
+    ) -> Result<Option<(WorkflowRunRecord, Vec<JobRecord>)>, AppError> {
+        let conn = self.conn();
+
+        let Some(run) = conn
+            .query_row(
+                "SELECT id, delivery_id, repository, workflow, event, git_ref, sha, status, approval_id, created_at, updated_at
+                 FROM workflow_runs WHERE id = ?1",
+                params![run_id],
+                run_from_row,
+            )
+            .optional()
+            .map_err(store_error)?
+        else {
+            return Ok(None);
+        };
+
+        let mut jobs = {
+            let mut statement = conn
+                .prepare(
+                    "SELECT id, run_id, name, partition, slurm_job_id, status, created_at, updated_at
+                     FROM jobs WHERE run_id = ?1 ORDER BY id",
+                )
+                .map_err(store_error)?;
+            statement
+                .query_map(params![run_id], |row| {
+                    Ok(JobRecord {
+                        id: row.get(0)?,
+                        run_id: row.get(1)?,
+                        name: row.get(2)?,
+                        partition: row.get(3)?,
+                        slurm_job_id: row.get(4)?,
+                        status: row.get(5)?,
+                        created_at: row.get(6)?,
+                        updated_at: row.get(7)?,
+                        steps: Vec::new(),
+                        transitions: Vec::new(),
+                    })
+                })
+                .map_err(store_error)?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(store_error)?
+        };
+
+        let mut steps_statement = conn
+            .prepare(
+                "SELECT position, name, uses, run FROM steps WHERE job_id = ?1 ORDER BY position",
+            )
+            .map_err(store_error)?;
+        let mut transitions_statement = conn
+            .prepare(
+                "SELECT from_status, to_status, slurm_state, at
+                 FROM status_transitions WHERE job_id = ?1 ORDER BY id",
+            )
+            .map_err(store_error)?;
+
+        for job in &mut jobs {
+            job.steps = steps_statement
+                .query_map(params![job.id], |row| {
+                    Ok(StepRecord {
+                        position: row.get(0)?,
+                        name: row.get(1)?,
+                        uses: row.get(2)?,
+                        run: row.get(3)?,
+                    })
+                })
+                .map_err(store_error)?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(store_error)?;
+            job.transitions = transitions_statement
+                .query_map(params![job.id], |row| {
+                    Ok(TransitionRecord {
+                        from_status: row.get(0)?,
+                        to_status: row.get(1)?,
+                        slurm_state: row.get(2)?,
+                        at: row.get(3)?,
+                    })
+                })
+                .map_err(store_error)?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(store_error)?;
+        }
+
+        Ok(Some((run, jobs)))
+    }
+
+    pub fn create_approval(&self, approval: &NewApproval) -> Result<i64, AppError> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO approvals
+                (repository, pull_request, head_sha, author, reason, envelope, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                approval.repository,
+                approval.pull_request,
+                approval.head_sha,
+                approval.author,
+                approval.reason,
+                approval.envelope,
+                unix_timestamp()
+            ],
+        )
+        .map_err(store_error)?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn get_approval(&self, approval_id: i64) -> Result<Option<ApprovalRecord>, AppError> {
+        self.conn()
+            .query_row(
+                &format!("{} WHERE id = ?1", APPROVAL_SELECT),
+                params![approval_id],
+                approval_from_row,
+            )
+            .optional()
+            .map_err(store_error)
+    }
+
+    /// The approvals of a pull request nobody has given yet, oldest first.
+    pub fn waiting_approvals(
+        &self,
+        repository: &str,
+        pull_request: u64,
+    ) -> Result<Vec<ApprovalRecord>, AppError> {
+        let conn = self.conn();
+        let mut statement = conn
+            .prepare(&format!(
+                "{} WHERE repository = ?1 AND pull_request = ?2 AND approved_at IS NULL ORDER BY id",
+                APPROVAL_SELECT
+            ))
+            .map_err(store_error)?;
+
+        statement
+            .query_map(params![repository, pull_request], approval_from_row)
+            .map_err(store_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(store_error)
+    }
+
+    /// Records who approved the runs of an approval, returning `false` if somebody already
+    /// had.
+    pub fn approve(&self, approval_id: i64, approved_by: &str) -> Result<bool, AppError> {
+        let updated = self
+            .conn()
+            .execute(
+                "UPDATE approvals SET approved_by = ?1, approved_at = ?2
+                 WHERE id = ?3 AND approved_at IS NULL",
+                params![approved_by, unix_timestamp(), approval_id],
+            )
+            .map_err(store_error)?;
+        Ok(updated > 0)
+    }
+
+    /// The runs held for an approval that are still waiting for it.
+    pub fn held_runs(&self, approval_id: i64) -> Result<Vec<WorkflowRunRecord>, AppError> {
+        let conn = self.conn();
+        let mut statement = conn
+            .prepare(
+                "SELECT id, delivery_id, repository, workflow, event, git_ref, sha, status, approval_id, created_at, updated_at
+                 FROM workflow_runs
+                 WHERE approval_id = ?1 AND status = ?2
+                 ORDER BY id",
+            )
+            .map_err(store_error)?;
+
+        statement
+            .query_map(
+                params![approval_id, RunStatus::WaitingForApproval.as_str()],
+                run_from_row,
+            )
+            .map_err(store_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(store_error)
+    }
+
+    /// Creates or replaces the secret `name` of a repository.
+    pub fn put_secret(
+        &self,
+        installation_id: u64,
+        repository: &str,
+        name: &str,
+        sealed: &SealedSecret,
+    ) -> Result<(), AppError> {
+        self.conn()
+            .execute(
+                "INSERT INTO secrets
+                    (installation_id, repository, name, nonce, ciphertext, created_at, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)
+                 ON CONFLICT (installation_id, repository, name) DO UPDATE SET
+                    nonce = excluded.nonce,
+                    ciphertext = excluded.ciphertext,
+                    updated_at = excluded.updated_at",
+                params![
+                    installation_id,
+                    repository,
+                    name,
+                    sealed.nonce,
+                    sealed.ciphertext,
+                    unix_timestamp()
+                ],
+            )
+            .map_err(store_error)?;
+        Ok(())
+    }
+
+    /// Deletes a secret, returning `false` if there was none.
+    pub fn delete_secret(
+        &self,
+        installation_id: u64,
+        repository: &str,
+        name: &str,
+    ) -> Result<bool, AppError> {
+        let deleted = self
+            .conn()
+            .execute(
+                "DELETE FROM secrets WHERE installation_id = ?1 AND repository = ?2 AND name = ?3",
+                params![installation_id, repository, name],
+            )
+            .map_err(store_error)?;
+        Ok(deleted > 0)
+    }
+
+    pub fn list_secrets(
+        &self,
+        installation_id: u64,
+        repository: &str,
+    ) -> Result<Vec<SecretRecord>, AppError> {
+        let conn = self.conn();
+        let mut statement = conn
+            .prepare(
+                "SELECT name, created_at, updated_at FROM secrets
+                 WHERE installation_id = ?1 AND repository = ?2 ORDER BY name",
+            )
+            .map_err(store_error)?;
+
+        statement
+            .query_map(params![installation_id, repository], |row| {
+                Ok(SecretRecord {
+                    name: row.get(0)?,
+                    created_at: row.get(1)?,
+                    updated_at: row.get(2)?,
+                })
+            })
+            .map_err(store_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(store_error)
+    }
+
+    /// Returns the secrets of `names` that exist, still encrypted.
+    pub fn sealed_secrets(
+        &self,
+        installation_id: u64,
+        repository: &str,
+        names: &[String],
+    ) -> Result<Vec<(String, SealedSecret)>, AppError> {
+        let conn = self.conn();
+        let mut statement = conn
+            .prepare(
+                "SELECT nonce, ciphertext FROM secrets
+                 WHERE installation_id = ?1 AND repository = ?2 AND name = ?3",
+            )
+            .map_err(store_error)?;
+
+        let mut secrets = Vec::new();
+        for name in names {
+            let sealed = statement
+                .query_row(params![installation_id, repository, name], |row| {
+                    Ok(SealedSecret {
+                        nonce: row.get(0)?,
+                        ciphertext: row.get(1)?,
+                    })
+                })
+                .optional()
+                .map_err(store_error)?;
+            if let Some(sealed) = sealed {
+                secrets.push((name.clone(), sealed));
+            }
+        }
+        Ok(secrets)
+    }
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), AppError> {
+    let tx = conn
+        .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)
+        .map_err(store_error)?;
+
+    let applied: usize = tx
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(store_error)?;
+
+    for migration in MIGRATIONS.iter().skip(applied) {
+        tx.execute_batch(migration).map_err(store_error)?;
+    }
+
+    tx.pragma_update(None, "user_version", MIGRATIONS.len())
+        .map_err(store_error)?;
+    tx.commit().map_err(store_error)
+}
+
+fn run_from_row(row: &rusqlite::Row) -> rusqlite::Result<WorkflowRunRecord> {
+    Ok(WorkflowRunRecord {
+        id: row.get(0)?,
+        delivery_id: row.get(1)?,
+        repository: row.get(2)?,
+        workflow: row.get(3)?,
+        event: row.get(4)?,
+        git_ref: row.get(5)?,
+        sha: row.get(6)?,
+        status: row.get(7)?,
+        approval_id: row.get(8)?,
+        created_at: row.get(9)?,
+        updated_at: row.get(10)?,
+    })
+}
+
+fn approval_from_row(row: &rusqlite::Row) -> rusqlite::Result<ApprovalRecord> {
+    Ok(ApprovalRecord {
+        id: row.get(0)?,
+        repository: row.get(1)?,
+        pull_request: row.get(2)?,
+        head_sha: row.get(3)?,
+        author: row.get(4)?,
+        reason: row.get(5)?,
+        envelope: row.get(6)?,
+        approved_by: row.get(7)?,
+        created_at: row.get(8)?,
+        approved_at: row.get(9)?,
+    })
+}
+
+// a run is in progress while any job is, and otherwise takes the worst status of its jobs
+// once all of them have finished
+fn run_status(job_statuses: &[String]) -> &'static str {
+    let any = |status: RunStatus| job_statuses.iter().any(|s| s == status.as_str());
+
+    if any(RunStatus::InProgress) {
+        RunStatus::InProgress.as_str()
+    } else if any(RunStatus::Queued) {
+        if job_statuses.iter().all(|s| s == RunStatus::Queued.as_str()) {
+            RunStatus::Queued.as_str()
+        } else {
+            RunStatus::InProgress.as_str()
+        }
+    } else if any(RunStatus::Failure) {
+        RunStatus::Failure.as_str()
+    } else if any(RunStatus::TimedOut) {
+        RunStatus::TimedOut.as_str()
+    } else if any(RunStatus::Cancelled) {
+        RunStatus::Cancelled.as_str()
+    } else {
+        RunStatus::Success.as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempdir::TempDir;
+
+    fn open_store() -> (TempDir, RunStore) {
+        let dir = TempDir::new("run-store").unwrap();
+        let store = RunStore::open(&dir.path().join("runs.db")).unwrap();
+        (dir, store)
+    }
+
+    fn envelope(delivery_id: &str) -> QueueEnvelope {
+        QueueEnvelope::new("push", delivery_id, Some(7), serde_json::json!({}))
+    }
+
+    fn new_run<'a>(delivery_id: Option<&'a str>, approval_id: Option<i64>) -> NewRun<'a> {
+        NewRun {
+            delivery_id,
+            repository: "octo/repo",
+            workflow: "CI",
+            event: "push",
+            git_ref: "refs/heads/main",
+            sha: "abc123",
+            approval_id,
+        }
+    }
+
+    fn user_version(store: &RunStore) -> usize {
+        store
+            .conn()
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn a_new_database_gets_every_migration() {
+        let (_dir, store) = open_store();
+
+        assert_eq!(user_version(&store), MIGRATIONS.len());
+        let tables = store
+            .conn()
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap()
+            .query_map([], |row| row.get::<_, String>(0))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        for table in [
+            "approvals",
+            "deliveries",
+            "jobs",
+            "secrets",
+            "status_transitions",
+            "steps",
+            "workflow_runs",
+        ] {
+            assert!(tables.iter().any(|name| name == table), "{}", table);
+        }
+    }
+
+    #[test]
+    fn an_older_database_gets_the_missing_migrations() {
+        let dir = TempDir::new("run-store").unwrap();
+        let path = dir.path().join("runs.db");
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(MIGRATIONS[0]).unwrap();
+            conn.pragma_update(None, "user_version", 1).unwrap();
+            conn.execute(
+                "INSERT INTO workflow_runs
+                    (repository, workflow, event, git_ref, sha, status, created_at, updated_at)
+                 VALUES ('octo/repo', 'CI', 'push', 'refs/heads/main', 'abc123', 'success', 1, 1)",
+                [],
+            )
+            .unwrap();
+        }
+
+        let store = RunStore::open(&path).unwrap();
+        assert_eq!(user_version(&store), MIGRATIONS.len());
+        let runs = store.list_runs(None, 10).unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].approval_id, None);
+    }
+
+    #[test]
+    fn reopening_keeps_the_data_and_applies_nothing_twice() {
+        let dir = TempDir::new("run-store").unwrap();
+        let path = dir.path().join("runs.db");
+
+        let run_id = {
+            let store = RunStore::open(&path).unwrap();
+            store.record_delivery(&envelope("delivery-1")).unwrap();
+            store.mark_delivery_processed("delivery-1").unwrap();
+            store
+                .create_run(&new_run(Some("delivery-1"), None))
+                .unwrap()
+        };
+
+        let store = RunStore::open(&path).unwrap();
+        assert_eq!(user_version(&store), MIGRATIONS.len());
+        assert!(!store.record_delivery(&envelope("delivery-1")).unwrap());
+        let (run, _) = store.get_run(run_id).unwrap().unwrap();
+        assert_eq!(run.delivery_id.as_deref(), Some("delivery-1"));
+    }
+
+    #[test]
+    fn deliveries_are_skipped_once_processed() {
+        let (_dir, store) = open_store();
+
+        assert!(store.record_delivery(&envelope("delivery-1")).unwrap());
+        // recorded but not processed yet, e.g. the worker stopped half way
+        assert!(store.record_delivery(&envelope("delivery-1")).unwrap());
+
+        store.mark_delivery_processed("delivery-1").unwrap();
+        assert!(!store.record_delivery(&envelope("delivery-1")).unwrap());
+        assert!(store.record_delivery(&envelope("delivery-2")).unwrap());
+
+        let mut legacy = envelope("unused");
+        legacy.delivery_id = None;
+        assert!(store.record_delivery(&legacy).unwrap());
+        assert!(store.record_delivery(&legacy).unwrap());
+    }
+
+    #[test]
+    fn job_transitions_move_the_run_along() {
+        let (_dir, store) = open_store();
+        let run_id = store.create_run(&new_run(None, None)).unwrap();
+        let steps = [
+            StepRecord {
+                position: 0,
+                name: Some("checkout".to_string()),
+                uses: Some("actions/checkout@v4".to_string()),
+                run: None,
+            },
+            StepRecord {
+                position: 1,
+                name: None,
+                uses: None,
+                run: Some("make test".to_string()),
+            },
+        ];
+        let build = store.create_job(run_id, "build", "debug", &steps).unwrap();
+        let lint = store.create_job(run_id, "lint", "debug", &[]).unwrap();
+        store.set_slurm_job_id(build, 4242).unwrap();
+
+        let run_status = |store: &RunStore| store.get_run(run_id).unwrap().unwrap().0.status;
+        assert_eq!(run_status(&store), "queued");
+
+        store
+            .transition_job(build, RunStatus::InProgress, Some("RUNNING"))
+            .unwrap();
+        assert_eq!(run_status(&store), "in_progress");
+
+        store
+            .transition_job(build, RunStatus::Success, Some("COMPLETED"))
+            .unwrap();
+        // lint is still queued while build has finished
+        assert_eq!(run_status(&store), "in_progress");
+
+        store
+            .transition_job(lint, RunStatus::Failure, Some("FAILED"))
+            .unwrap();
+        assert_eq!(run_status(&store), "failure");
+
+        let (_, jobs) = store.get_run(run_id).unwrap().unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].slurm_job_id, Some(4242));
+        assert_eq!(jobs[0].steps.len(), 2);
+        assert_eq!(jobs[0].steps[1].run.as_deref(), Some("make test"));
+        let transitions = jobs[0]
+            .transitions
+            .iter()
+            .map(|t| (t.from_status.as_deref(), t.to_status.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            transitions,
+            [
+                (Some("queued"), "in_progress"),
+                (Some("in_progress"), "success")
+            ]
+        );
+        assert_eq!(
+            jobs[1].transitions[0].slurm_state.as_deref(),
+            Some("FAILED")
+        );
+    }
+
+    #[test]
+    fn runs_can_fail_without_jobs() {
+        let (_dir, store) = open_store();
+        let run_id = store.create_run(&new_run(None, None)).unwrap();
+
+        store.fail_run(run_id).unwrap();
+        let (run, jobs) = store.get_run(run_id).unwrap().unwrap();
+        assert_eq!(run.status, "failure");
+        assert!(jobs.is_empty());
+    }
+
+    #[test]
+    fn unfinished_jobs_are_the_submitted_ones_still_queued_or_running() {
+        let (_dir, store) = open_store();
+        let run_id = store.create_run(&new_run(None, None)).unwrap();
+        let running = store.create_job(run_id, "running", "debug", &[]).unwrap();
+        let finished = store.create_job(run_id, "finished", "debug", &[]).unwrap();
+        // never submitted
+        store.create_job(run_id, "rejected", "debug", &[]).unwrap();
+
+        store.set_slurm_job_id(running, 1).unwrap();
+        store.set_check_run(running, 7, 77).unwrap();
+        store
+            .transition_job(running, RunStatus::InProgress, Some("RUNNING"))
+            .unwrap();
+        store.set_slurm_job_id(finished, 2).unwrap();
+        store
+            .transition_job(finished, RunStatus::Success, Some("COMPLETED"))
+            .unwrap();
+
+        let jobs = store.unfinished_jobs().unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].job_id, running);
+        assert_eq!(jobs[0].slurm_job_id, 1);
+        assert_eq!(jobs[0].status, RunStatus::InProgress);
+        assert_eq!(jobs[0].repository, "octo/repo");
+        assert_eq!(jobs[0].check_run_id, Some(77));
+    }
+
+    #[test]
+    fn held_runs_are_released_by_their_approval() {
+        let (_dir, store) = open_store();
+        let approval_id = store
+            .create_approval(&NewApproval {
+                repository: "octo/repo",
+                pull_request: 5,
+                head_sha: "abc123",
+                author: "newcomer",
+                reason: "first time contributor",
+                envelope: "{}",
+            })
+            .unwrap();
+        let run_id = store.create_run(&new_run(None, Some(approval_id))).unwrap();
+
+        let (run, _) = store.get_run(run_id).unwrap().unwrap();
+        assert_eq!(run.status, "waiting_for_approval");
+        assert_eq!(store.waiting_approvals("octo/repo", 5).unwrap().len(), 1);
+        assert_eq!(store.held_runs(approval_id).unwrap().len(), 1);
+
+        assert!(store.approve(approval_id, "maintainer").unwrap());
+        assert!(!store.approve(approval_id, "someone-else").unwrap());
+        assert!(store.waiting_approvals("octo/repo", 5).unwrap().is_empty());
+
+        store.release_run(run_id).unwrap();
+        assert!(store.held_runs(approval_id).unwrap().is_empty());
+        let approval = store.get_approval(approval_id).unwrap().unwrap();
+        assert_eq!(approval.approved_by.as_deref(), Some("maintainer"));
+    }
+}
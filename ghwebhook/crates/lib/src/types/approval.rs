@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+/// Event name of the envelopes the api publishes when a maintainer approves held runs. It
+/// isn't a GitHub event, so the worker handles it before parsing webhooks.
+pub const APPROVAL_EVENT: &str = "slurm_actions_approval";
+
+/// The payload of an [`APPROVAL_EVENT`] envelope.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApprovalRequest {
+    pub approval_id: i64,
+    pub approved_by: String,
+}
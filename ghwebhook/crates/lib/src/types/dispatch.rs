@@ -0,0 +1,21 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Event name of the envelopes the api publishes to start a `workflow_dispatch` workflow
+/// without going through GitHub. The worker looks up the rest of the event on GitHub.
+pub const DISPATCH_EVENT: &str = "slurm_actions_dispatch";
+
+/// The payload of a [`DISPATCH_EVENT`] envelope.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DispatchRequest {
+    // `owner/name`
+    pub repository: String,
+    // path of the workflow file, e.g. `.github/workflows/bench.yml`
+    pub workflow: String,
+    #[serde(rename = "ref")]
+    pub git_ref: String,
+    #[serde(default)]
+    pub inputs: HashMap<String, serde_json::Value>,
+    pub sender: String,
+}
@@ -0,0 +1,188 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::AppError;
+use crate::types::webhookevent::WebhookEvent;
+
+pub const ENVELOPE_SCHEMA_VERSION: u32 = 1;
+
+/// The body of every message on the `ghwebhook` stream.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueEnvelope {
+    pub schema_version: u32,
+    pub event: String,
+    pub delivery_id: Option<String>,
+    // seconds since the unix epoch
+    pub received_at: u64,
+    pub installation_id: Option<u64>,
+    pub payload: serde_json::Value,
+}
+
+impl QueueEnvelope {
+    pub fn new(
+        event: &str,
+        delivery_id: &str,
+        installation_id: Option<u64>,
+        payload: serde_json::Value,
+    ) -> Self {
+        Self {
+            schema_version: ENVELOPE_SCHEMA_VERSION,
+            event: event.to_string(),
+            delivery_id: Some(delivery_id.to_string()),
+            received_at: unix_timestamp(),
+            installation_id,
+            payload,
+        }
+    }
+
+    /// Decodes a message body. Bodies without a `schema_version` predate the envelope and
+    /// are the bare webhook payload, with the event name and delivery id carried in the
+    /// message's application properties if at all.
+    pub fn decode(
+        body: &[u8],
+        legacy_event: Option<String>,
+        legacy_delivery_id: Option<String>,
+    ) -> Result<Self, AppError> {
+        let value: serde_json::Value =
+            serde_json::from_slice(body).map_err(|err| AppError::EnvelopeError(err.to_string()))?;
+
+        if value.get("schema_version").is_some() {
+            let envelope: QueueEnvelope = serde_json::from_value(value)
+                .map_err(|err| AppError::EnvelopeError(err.to_string()))?;
+            if envelope.schema_version > ENVELOPE_SCHEMA_VERSION {
+                return Err(AppError::EnvelopeError(format!(
+                    "unsupported schema version {}",
+                    envelope.schema_version
+                )));
+            }
+            return Ok(envelope);
+        }
+
+        let installation_id = value
+            .pointer("/installation/id")
+            .and_then(serde_json::Value::as_u64);
+
+        Ok(Self {
+            schema_version: 0,
+            // the api only published push events before it recorded the event name
+            event: legacy_event.unwrap_or("push".to_string()),
+            delivery_id: legacy_delivery_id,
+            received_at: unix_timestamp(),
+            installation_id,
+            payload: value,
+        })
+    }
+
+    pub fn to_vec(&self) -> Result<Vec<u8>, AppError> {
+        serde_json::to_vec(self).map_err(|err| AppError::EnvelopeError(err.to_string()))
+    }
+
+    pub fn webhook_event(&self) -> Result<WebhookEvent, AppError> {
+        WebhookEvent::from_value(&self.event, self.payload.clone())
+    }
+}
+
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUSH: &str = include_str!("../../tests/fixtures/webhooks/push.json");
+    const PULL_REQUEST: &str = include_str!("../../tests/fixtures/webhooks/pull_request.json");
+
+    #[test]
+    fn envelopes_round_trip() {
+        let payload: serde_json::Value = serde_json::from_str(PULL_REQUEST).unwrap();
+        let envelope = QueueEnvelope::new("pull_request", "delivery-1", Some(42), payload.clone());
+
+        let decoded = QueueEnvelope::decode(
+            &envelope.to_vec().unwrap(),
+            Some("push".to_string()),
+            Some("ignored".to_string()),
+        )
+        .unwrap();
+        assert_eq!(decoded.schema_version, ENVELOPE_SCHEMA_VERSION);
+        assert_eq!(decoded.event, "pull_request");
+        assert_eq!(decoded.delivery_id.as_deref(), Some("delivery-1"));
+        assert_eq!(decoded.received_at, envelope.received_at);
+        assert_eq!(decoded.installation_id, Some(42));
+        assert_eq!(decoded.payload, payload);
+        assert!(matches!(
+            decoded.webhook_event().unwrap(),
+            WebhookEvent::PullRequest(_)
+        ));
+    }
+
+    #[test]
+    fn bare_payloads_take_the_event_from_the_properties() {
+        let decoded = QueueEnvelope::decode(
+            PULL_REQUEST.as_bytes(),
+            Some("pull_request".to_string()),
+            Some("delivery-2".to_string()),
+        )
+        .unwrap();
+        assert_eq!(decoded.schema_version, 0);
+        assert_eq!(decoded.event, "pull_request");
+        assert_eq!(decoded.delivery_id.as_deref(), Some("delivery-2"));
+        assert_eq!(decoded.installation_id, Some(42));
+        assert!(matches!(
+            decoded.webhook_event().unwrap(),
+            WebhookEvent::PullRequest(_)
+        ));
+    }
+
+    #[test]
+    fn bare_payloads_without_properties_are_pushes() {
+        let decoded = QueueEnvelope::decode(PUSH.as_bytes(), None, None).unwrap();
+        assert_eq!(decoded.schema_version, 0);
+        assert_eq!(decoded.event, "push");
+        assert_eq!(decoded.delivery_id, None);
+        assert_eq!(decoded.installation_id, Some(42));
+        assert!(matches!(
+            decoded.webhook_event().unwrap(),
+            WebhookEvent::Push(_)
+        ));
+    }
+
+    #[test]
+    fn unknown_schema_versions_are_rejected() {
+        let mut envelope = QueueEnvelope::new("push", "delivery-3", None, serde_json::json!({}));
+        envelope.schema_version = ENVELOPE_SCHEMA_VERSION + 1;
+        assert!(matches!(
+            QueueEnvelope::decode(&envelope.to_vec().unwrap(), None, None),
+            Err(AppError::EnvelopeError(_))
+        ));
+    }
+
+    // approvals store the envelope they held, legacy ones included
+    #[test]
+    fn stored_legacy_envelopes_decode_again() {
+        let legacy = QueueEnvelope::decode(PUSH.as_bytes(), None, Some("d4".to_string())).unwrap();
+        let decoded = QueueEnvelope::decode(&legacy.to_vec().unwrap(), None, None).unwrap();
+        assert_eq!(decoded.schema_version, 0);
+        assert_eq!(decoded.delivery_id.as_deref(), Some("d4"));
+        assert!(matches!(
+            decoded.webhook_event().unwrap(),
+            WebhookEvent::Push(_)
+        ));
+    }
+
+    #[test]
+    fn bodies_that_are_not_json_are_rejected() {
+        assert!(matches!(
+            QueueEnvelope::decode(b"event=push", None, None),
+            Err(AppError::EnvelopeError(_))
+        ));
+        assert!(matches!(
+            QueueEnvelope::decode(br#"{"schema_version": 1, "event": "push"}"#, None, None),
+            Err(AppError::EnvelopeError(_))
+        ));
+    }
+}
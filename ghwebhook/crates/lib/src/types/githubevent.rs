@@ -10,8 +10,8 @@ pub struct GithubEvent {
     pub created: bool,
     pub deleted: bool,
     pub forced: bool,
-    pub head_commit: Commit,
-    pub installation: Installation,
+    pub head_commit: Option<Commit>,
+    pub installation: Option<Installation>,
     pub pusher: CommitPusher,
     #[serde(rename = "ref")]
     pub ref_: String,
@@ -38,7 +38,7 @@ pub struct Commit {
 pub struct CommitAuthor {
     pub email: String,
     pub name: String,
-    pub username: String,
+    pub username: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -1,2 +1,8 @@
+pub mod approval;
+pub mod dispatch;
+pub mod envelope;
 pub mod githubevent;
+pub mod runstatus;
+pub mod webhookevent;
 pub mod workflow;
+pub mod workflowevent;
@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+/// Status of a workflow job, normalized from the Slurm job state, or of a run that hasn't
+/// been submitted yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    // held until a maintainer approves the run, see the worker's approval policy
+    WaitingForApproval,
+    Queued,
+    InProgress,
+    Success,
+    Failure,
+    Cancelled,
+    TimedOut,
+}
+
+impl RunStatus {
+    /// Maps a Slurm job state such as `RUNNING` or `NODE_FAIL`. Unknown states are treated
+    /// as failures so a job never stays pending forever.
+    pub fn from_slurm_state(state: &str) -> Self {
+        match state {
+            "PENDING" | "CONFIGURING" | "REQUEUED" | "REQUEUE_HOLD" | "REQUEUE_FED"
+            | "RESV_DEL_HOLD" | "SUSPENDED" => RunStatus::Queued,
+            "RUNNING" | "COMPLETING" | "STAGE_OUT" | "SIGNALING" | "RESIZING" => {
+                RunStatus::InProgress
+            }
+            "COMPLETED" => RunStatus::Success,
+            "CANCELLED" | "REVOKED" => RunStatus::Cancelled,
+            "TIMEOUT" | "DEADLINE" => RunStatus::TimedOut,
+            // FAILED, PREEMPTED, NODE_FAIL, BOOT_FAIL, OUT_OF_MEMORY, ...
+            _ => RunStatus::Failure,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !matches!(
+            self,
+            RunStatus::WaitingForApproval | RunStatus::Queued | RunStatus::InProgress
+        )
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunStatus::WaitingForApproval => "waiting_for_approval",
+            RunStatus::Queued => "queued",
+            RunStatus::InProgress => "in_progress",
+            RunStatus::Success => "success",
+            RunStatus::Failure => "failure",
+            RunStatus::Cancelled => "cancelled",
+            RunStatus::TimedOut => "timed_out",
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::AppError;
+use crate::types::githubevent::{GithubEvent, Installation};
+
+/// A webhook delivery, keyed by the `X-GitHub-Event` header it arrived with.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum WebhookEvent {
+    Ping(PingEvent),
+    Push(GithubEvent),
+    PullRequest(PullRequestEvent),
+    IssueComment(IssueCommentEvent),
+    WorkflowDispatch(WorkflowDispatchEvent),
+    Create(CreateEvent),
+    Delete(DeleteEvent),
+    Installation(InstallationEvent),
+}
+
+impl WebhookEvent {
+    pub fn from_value(event_name: &str, payload: serde_json::Value) -> Result<Self, AppError> {
+        let parse_error = |err: serde_json::Error| {
+            AppError::WebhookPayloadError(format!("{} event: {}", event_name, err))
+        };
+
+        match event_name {
+            "ping" => serde_json::from_value(payload)
+                .map(WebhookEvent::Ping)
+                .map_err(parse_error),
+            "push" => serde_json::from_value(payload)
+                .map(WebhookEvent::Push)
+                .map_err(parse_error),
+            "pull_request" => serde_json::from_value(payload)
+                .map(WebhookEvent::PullRequest)
+                .map_err(parse_error),
+            "issue_comment" => serde_json::from_value(payload)
+                .map(WebhookEvent::IssueComment)
+                .map_err(parse_error),
+            "workflow_dispatch" => serde_json::from_value(payload)
+                .map(WebhookEvent::WorkflowDispatch)
+                .map_err(parse_error),
+            "create" => serde_json::from_value(payload)
+                .map(WebhookEvent::Create)
+                .map_err(parse_error),
+            "delete" => serde_json::from_value(payload)
+                .map(WebhookEvent::Delete)
+                .map_err(parse_error),
+            "installation" => serde_json::from_value(payload)
+                .map(WebhookEvent::Installation)
+                .map_err(parse_error),
+            other => Err(AppError::UnsupportedWebhookEvent(other.to_string())),
+        }
+    }
+
+    /// The `X-GitHub-Event` name of this event.
+    pub fn name(&self) -> &'static str {
+        match self {
+            WebhookEvent::Ping(_) => "ping",
+            WebhookEvent::Push(_) => "push",
+            WebhookEvent::PullRequest(_) => "pull_request",
+            WebhookEvent::IssueComment(_) => "issue_comment",
+            WebhookEvent::WorkflowDispatch(_) => "workflow_dispatch",
+            WebhookEvent::Create(_) => "create",
+            WebhookEvent::Delete(_) => "delete",
+            WebhookEvent::Installation(_) => "installation",
+        }
+    }
+
+    pub fn installation_id(&self) -> Option<u64> {
+        match self {
+            WebhookEvent::Ping(_) => None,
+            WebhookEvent::Push(event) => event.installation.as_ref().map(|i| i.id),
+            WebhookEvent::PullRequest(event) => event.installation.as_ref().map(|i| i.id),
+            WebhookEvent::IssueComment(event) => event.installation.as_ref().map(|i| i.id),
+            WebhookEvent::WorkflowDispatch(event) => event.installation.as_ref().map(|i| i.id),
+            WebhookEvent::Create(event) => event.installation.as_ref().map(|i| i.id),
+            WebhookEvent::Delete(event) => event.installation.as_ref().map(|i| i.id),
+            WebhookEvent::Installation(event) => Some(event.installation.id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PingEvent {
+    pub zen: Option<String>,
+    pub hook_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PullRequestEvent {
+    pub action: String,
+    pub number: u64,
+    pub pull_request: PullRequest,
+    pub repository: EventRepository,
+    pub installation: Option<Installation>,
+    pub sender: User,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PullRequest {
+    pub id: u64,
+    pub number: u64,
+    pub state: String,
+    pub title: String,
+    pub user: User,
+    pub head: PullRequestRef,
+    pub base: PullRequestRef,
+    pub merge_commit_sha: Option<String>,
+    // false when the pull request has conflicts, null while GitHub hasn't checked yet
+    pub mergeable: Option<bool>,
+    pub author_association: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PullRequestRef {
+    pub label: Option<String>,
+    #[serde(rename = "ref")]
+    pub ref_: String,
+    pub sha: String,
+    pub repo: Option<EventRepository>,
+}
+
+/// A comment on an issue or, when `issue.pull_request` is set, on a pull request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IssueCommentEvent {
+    pub action: String,
+    pub issue: Issue,
+    pub comment: Comment,
+    pub repository: EventRepository,
+    pub installation: Option<Installation>,
+    pub sender: User,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Issue {
+    pub number: u64,
+    pub pull_request: Option<IssuePullRequest>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IssuePullRequest {
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Comment {
+    pub id: u64,
+    pub body: String,
+    pub user: User,
+    pub author_association: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowDispatchEvent {
+    #[serde(default)]
+    pub inputs: Option<HashMap<String, serde_json::Value>>,
+    #[serde(rename = "ref")]
+    pub ref_: String,
+    pub repository: EventRepository,
+    pub installation: Option<Installation>,
+    pub workflow: String,
+    pub sender: User,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateEvent {
+    #[serde(rename = "ref")]
+    pub ref_: String,
+    pub ref_type: String,
+    pub master_branch: Option<String>,
+    pub repository: EventRepository,
+    pub installation: Option<Installation>,
+    pub sender: User,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteEvent {
+    #[serde(rename = "ref")]
+    pub ref_: String,
+    pub ref_type: String,
+    pub repository: EventRepository,
+    pub installation: Option<Installation>,
+    pub sender: User,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallationEvent {
+    pub action: String,
+    pub installation: InstallationDetails,
+    pub repositories: Option<Vec<InstallationRepository>>,
+    pub sender: User,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallationDetails {
+    pub id: u64,
+    pub account: User,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallationRepository {
+    pub id: u64,
+    pub name: String,
+    pub full_name: String,
+    pub private: bool,
+}
+
+/// The subset of the repository object shared by every event payload. Push payloads use
+/// [`crate::types::githubevent::Repository`] instead, whose timestamps are encoded differently.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventRepository {
+    pub id: u64,
+    pub name: String,
+    pub full_name: String,
+    pub clone_url: String,
+    pub default_branch: String,
+    pub private: bool,
+    pub owner: User,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub login: String,
+    pub id: u64,
+    #[serde(rename = "type")]
+    pub type_: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(event_name: &str, payload: &str) -> WebhookEvent {
+        let payload = serde_json::from_str(payload).unwrap();
+        let event = WebhookEvent::from_value(event_name, payload).unwrap();
+        assert_eq!(event.name(), event_name);
+        event
+    }
+
+    #[test]
+    fn ping() {
+        let WebhookEvent::Ping(ping) = parse(
+            "ping",
+            include_str!("../../tests/fixtures/webhooks/ping.json"),
+        ) else {
+            panic!("not a ping");
+        };
+        assert_eq!(ping.zen.as_deref(), Some("Keep it logically awesome."));
+        assert_eq!(ping.hook_id, Some(512345678));
+    }
+
+    #[test]
+    fn push() {
+        let event = parse(
+            "push",
+            include_str!("../../tests/fixtures/webhooks/push.json"),
+        );
+        assert_eq!(event.installation_id(), Some(42));
+        let WebhookEvent::Push(push) = event else {
+            panic!("not a push");
+        };
+        assert_eq!(push.ref_, "refs/heads/main");
+        assert_eq!(push.after, "3f786850e387550fdab836ed7e6dc881de23001b");
+        assert_eq!(push.repository.full_name, "octo/repo");
+        assert_eq!(push.commits.len(), 1);
+        assert_eq!(push.head_commit.unwrap().committer.username, None);
+        assert!(!push.deleted);
+    }
+
+    #[test]
+    fn pull_request() {
+        let event = parse(
+            "pull_request",
+            include_str!("../../tests/fixtures/webhooks/pull_request.json"),
+        );
+        assert_eq!(event.installation_id(), Some(42));
+        let WebhookEvent::PullRequest(pull_request) = event else {
+            panic!("not a pull_request");
+        };
+        assert_eq!(pull_request.action, "synchronize");
+        assert_eq!(pull_request.number, 7);
+        let head = &pull_request.pull_request.head;
+        assert_eq!(head.sha, "e5bd3914e2e596debea16f433f57875b5b90bcd6");
+        assert_eq!(head.repo.as_ref().unwrap().full_name, "forker/repo");
+        assert_eq!(pull_request.pull_request.mergeable, None);
+    }
+
+    #[test]
+    fn issue_comment() {
+        let WebhookEvent::IssueComment(comment) = parse(
+            "issue_comment",
+            include_str!("../../tests/fixtures/webhooks/issue_comment.json"),
+        ) else {
+            panic!("not an issue_comment");
+        };
+        assert_eq!(comment.issue.number, 7);
+        assert!(comment.issue.pull_request.is_some());
+        assert_eq!(
+            comment.comment.author_association.as_deref(),
+            Some("MEMBER")
+        );
+    }
+
+    #[test]
+    fn workflow_dispatch() {
+        let event = parse(
+            "workflow_dispatch",
+            include_str!("../../tests/fixtures/webhooks/workflow_dispatch.json"),
+        );
+        assert_eq!(event.installation_id(), Some(42));
+        let WebhookEvent::WorkflowDispatch(dispatch) = event else {
+            panic!("not a workflow_dispatch");
+        };
+        assert_eq!(dispatch.workflow, ".github/workflows/bench.yml");
+        assert_eq!(dispatch.inputs.unwrap()["partition"], "gpu");
+    }
+
+    #[test]
+    fn create_and_delete() {
+        let WebhookEvent::Create(create) = parse(
+            "create",
+            include_str!("../../tests/fixtures/webhooks/create.json"),
+        ) else {
+            panic!("not a create");
+        };
+        assert_eq!(
+            (create.ref_.as_str(), create.ref_type.as_str()),
+            ("v1.2.0", "tag")
+        );
+
+        let WebhookEvent::Delete(delete) = parse(
+            "delete",
+            include_str!("../../tests/fixtures/webhooks/delete.json"),
+        ) else {
+            panic!("not a delete");
+        };
+        assert_eq!(
+            (delete.ref_.as_str(), delete.ref_type.as_str()),
+            ("faster", "branch")
+        );
+    }
+
+    #[test]
+    fn installation() {
+        let event = parse(
+            "installation",
+            include_str!("../../tests/fixtures/webhooks/installation.json"),
+        );
+        assert_eq!(event.installation_id(), Some(42));
+        let WebhookEvent::Installation(installation) = event else {
+            panic!("not an installation");
+        };
+        assert_eq!(installation.action, "created");
+        assert_eq!(installation.repositories.unwrap()[0].full_name, "octo/repo");
+    }
+
+    #[test]
+    fn unknown_events_are_unsupported() {
+        let payload =
+            serde_json::from_str(include_str!("../../tests/fixtures/webhooks/push.json")).unwrap();
+        assert!(matches!(
+            WebhookEvent::from_value("star", payload),
+            Err(AppError::UnsupportedWebhookEvent(name)) if name == "star"
+        ));
+    }
+
+    #[test]
+    fn payloads_of_another_event_are_errors() {
+        let payload =
+            serde_json::from_str(include_str!("../../tests/fixtures/webhooks/ping.json")).unwrap();
+        assert!(matches!(
+            WebhookEvent::from_value("push", payload),
+            Err(AppError::WebhookPayloadError(_))
+        ));
+    }
+}
@@ -1,6 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 
 use serde::Deserialize;
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+
+use crate::errors::AppError;
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct GithubWorkflow {
@@ -9,14 +13,208 @@ pub struct GithubWorkflow {
     pub jobs: HashMap<String, GithubWorkflowJob>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+/// The events a workflow runs on. `on:` is a single event name, a list of them, or a map
+/// from event names to their filters.
+#[derive(Clone, Debug, Default)]
 pub struct GithubWorkflowTrigger {
-    pub push: Option<GithubWorkflowPushTrigger>,
+    pub events: BTreeMap<String, GithubWorkflowEventTrigger>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
-pub struct GithubWorkflowPushTrigger {
-    pub branches: Vec<String>,
+impl GithubWorkflowTrigger {
+    /// The filters of `event`, if the workflow runs on it.
+    pub fn event(&self, event: &str) -> Option<&GithubWorkflowEventTrigger> {
+        self.events.get(event)
+    }
+
+    /// Checks the inputs of a `workflow_dispatch` event against the ones the workflow
+    /// declares and fills in the defaults. Values are strings, as in `github.event.inputs`.
+    pub fn dispatch_inputs(
+        &self,
+        supplied: &HashMap<String, serde_json::Value>,
+    ) -> Result<BTreeMap<String, String>, AppError> {
+        let declared = self
+            .event("workflow_dispatch")
+            .and_then(|trigger| trigger.inputs.as_ref());
+
+        for name in supplied.keys() {
+            if !declared.is_some_and(|declared| declared.contains_key(name)) {
+                return Err(input_error(name, "is not an input of the workflow"));
+            }
+        }
+
+        let mut inputs = BTreeMap::new();
+        for (name, input) in declared.into_iter().flatten() {
+            let value = match supplied.get(name) {
+                Some(value) => scalar_string(value.clone())
+                    .ok_or_else(|| input_error(name, "must be a string, number or boolean"))?,
+                None => String::new(),
+            };
+            let value = match (value.is_empty(), &input.default) {
+                (true, Some(default)) => default.clone(),
+                _ => value,
+            };
+            inputs.insert(name.clone(), input.validate(name, value)?);
+        }
+        Ok(inputs)
+    }
+}
+
+/// Filters of an event. A filter that isn't set doesn't restrict the event, an empty one
+/// matches nothing.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct GithubWorkflowEventTrigger {
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub branches: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub branches_ignore: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub tags: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub tags_ignore: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub paths: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub paths_ignore: Option<Vec<String>>,
+    // activity types, e.g. `opened` for pull_request
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub types: Option<Vec<String>>,
+    // inputs of workflow_dispatch
+    #[serde(default)]
+    pub inputs: Option<BTreeMap<String, GithubWorkflowInput>>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GithubWorkflowInputType {
+    #[default]
+    String,
+    Boolean,
+    Choice,
+    Number,
+    Environment,
+}
+
+/// An input of a `workflow_dispatch` workflow.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct GithubWorkflowInput {
+    pub description: Option<String>,
+    pub required: bool,
+    #[serde(deserialize_with = "optional_scalar")]
+    pub default: Option<String>,
+    #[serde(rename = "type")]
+    pub type_: GithubWorkflowInputType,
+    // the values of a choice
+    #[serde(deserialize_with = "scalar_list")]
+    pub options: Vec<String>,
+}
+
+impl GithubWorkflowInput {
+    // `value` is empty when neither the event nor the workflow set it
+    fn validate(&self, name: &str, value: String) -> Result<String, AppError> {
+        if value.is_empty() {
+            if self.required {
+                return Err(input_error(name, "is required"));
+            }
+            // an unset boolean is false, like on GitHub
+            if self.type_ == GithubWorkflowInputType::Boolean {
+                return Ok("false".to_string());
+            }
+            return Ok(value);
+        }
+
+        match self.type_ {
+            GithubWorkflowInputType::Boolean => match value.to_ascii_lowercase().as_str() {
+                "true" | "false" => Ok(value.to_ascii_lowercase()),
+                _ => Err(input_error(name, "must be true or false")),
+            },
+            GithubWorkflowInputType::Number => match value.trim().parse::<f64>() {
+                Ok(number) if number.is_finite() => Ok(value.trim().to_string()),
+                _ => Err(input_error(name, "must be a number")),
+            },
+            GithubWorkflowInputType::Choice => {
+                if self.options.contains(&value) {
+                    Ok(value)
+                } else {
+                    Err(input_error(
+                        name,
+                        &format!("must be one of {}", self.options.join(", ")),
+                    ))
+                }
+            }
+            GithubWorkflowInputType::String | GithubWorkflowInputType::Environment => Ok(value),
+        }
+    }
+}
+
+fn input_error(name: &str, problem: &str) -> AppError {
+    AppError::WorkflowInputError(format!("{} {}", name, problem))
+}
+
+impl<'de> Deserialize<'de> for GithubWorkflowTrigger {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(TriggerVisitor)
+    }
+}
+
+struct TriggerVisitor;
+
+impl<'de> Visitor<'de> for TriggerVisitor {
+    type Value = GithubWorkflowTrigger;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an event name, a list of event names or a map of events")
+    }
+
+    fn visit_str<E: de::Error>(self, event: &str) -> Result<Self::Value, E> {
+        Ok(GithubWorkflowTrigger {
+            events: BTreeMap::from([(event.to_string(), GithubWorkflowEventTrigger::default())]),
+        })
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut events = BTreeMap::new();
+        while let Some(event) = seq.next_element::<String>()? {
+            events.insert(event, GithubWorkflowEventTrigger::default());
+        }
+        Ok(GithubWorkflowTrigger { events })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut events = BTreeMap::new();
+        while let Some((event, value)) = map.next_entry::<String, serde_json::Value>()? {
+            // `push:` without filters is null, `schedule:` is a list of crons
+            let trigger = match value {
+                serde_json::Value::Object(_) => serde_json::from_value(value)
+                    .map_err(|err| de::Error::custom(format!("{}: {}", event, err)))?,
+                _ => GithubWorkflowEventTrigger::default(),
+            };
+            events.insert(event, trigger);
+        }
+        Ok(GithubWorkflowTrigger { events })
+    }
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<String>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(
+        match Option::<OneOrMany>::deserialize(deserializer)
+            .map_err(|_| de::Error::custom("expected a string or a list of strings"))?
+        {
+            Some(OneOrMany::One(value)) => Some(vec![value]),
+            Some(OneOrMany::Many(values)) => Some(values),
+            None => None,
+        },
+    )
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -32,6 +230,60 @@ pub struct GithubWorkflowJobStep {
     pub name: Option<String>,
     pub run: Option<String>,
     pub uses: Option<String>,
+    #[serde(default, deserialize_with = "scalar_map")]
     pub with: Option<HashMap<String, String>>,
+    #[serde(default, deserialize_with = "scalar_map")]
     pub env: Option<HashMap<String, String>>,
 }
+
+// `with:` and `env:` values are strings to the steps, but workflows write numbers and
+// booleans unquoted, e.g. `fetch-depth: 0`
+fn scalar_map<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<HashMap<String, String>>, D::Error> {
+    let Some(map) = Option::<HashMap<String, serde_json::Value>>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    map.into_iter()
+        .map(|(key, value)| match scalar_string(value) {
+            Some(value) => Ok((key, value)),
+            None => Err(de::Error::custom(format!(
+                "{} must be a string, number or boolean",
+                key
+            ))),
+        })
+        .collect::<Result<HashMap<_, _>, _>>()
+        .map(Some)
+}
+
+fn optional_scalar<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    match Option::<serde_json::Value>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(value) => scalar_string(value)
+            .map(Some)
+            .ok_or_else(|| de::Error::custom("expected a string, number or boolean")),
+    }
+}
+
+fn scalar_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Option::<Vec<serde_json::Value>>::deserialize(deserializer)?
+        .unwrap_or_default()
+        .into_iter()
+        .map(|value| {
+            scalar_string(value)
+                .ok_or_else(|| de::Error::custom("expected a list of strings, numbers or booleans"))
+        })
+        .collect()
+}
+
+// workflows write numbers and booleans unquoted where GitHub expects strings, null is empty
+fn scalar_string(value: serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(value) => Some(value),
+        serde_json::Value::Bool(value) => Some(value.to_string()),
+        serde_json::Value::Number(value) => Some(value.to_string()),
+        serde_json::Value::Null => Some(String::new()),
+        _ => None,
+    }
+}
@@ -0,0 +1,175 @@
+use crate::types::githubevent::GithubEvent;
+use crate::types::webhookevent::{PullRequestEvent, WebhookEvent, WorkflowDispatchEvent};
+
+// activities pull_request workflows run on when they don't list `types:`
+const DEFAULT_PULL_REQUEST_TYPES: &[&str] = &["opened", "synchronize", "reopened"];
+
+/// An event workflows run on, as named in `on:`. A `pull_request` delivery is both a
+/// `pull_request` and a `pull_request_target` event.
+#[derive(Debug, Clone, Copy)]
+pub enum WorkflowEvent<'a> {
+    Push(&'a GithubEvent),
+    /// Runs the workflows of the merge of the pull request into its base.
+    PullRequest(&'a PullRequestEvent),
+    /// Runs the workflows of the base branch on the head of the pull request.
+    PullRequestTarget(&'a PullRequestEvent),
+    /// Runs one workflow, started by hand.
+    WorkflowDispatch(&'a WorkflowDispatchEvent),
+}
+
+impl<'a> WorkflowEvent<'a> {
+    pub fn from_webhook(event: &'a WebhookEvent) -> Vec<Self> {
+        match event {
+            WebhookEvent::Push(push) => vec![WorkflowEvent::Push(push)],
+            WebhookEvent::PullRequest(pull_request) => vec![
+                WorkflowEvent::PullRequest(pull_request),
+                WorkflowEvent::PullRequestTarget(pull_request),
+            ],
+            WebhookEvent::WorkflowDispatch(dispatch) => {
+                vec![WorkflowEvent::WorkflowDispatch(dispatch)]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// `GITHUB_EVENT_NAME`
+    pub fn name(&self) -> &'static str {
+        match self {
+            WorkflowEvent::Push(_) => "push",
+            WorkflowEvent::PullRequest(_) => "pull_request",
+            WorkflowEvent::PullRequestTarget(_) => "pull_request_target",
+            WorkflowEvent::WorkflowDispatch(_) => "workflow_dispatch",
+        }
+    }
+
+    /// `owner/name` of the repository the workflows belong to.
+    pub fn repository(&self) -> &'a str {
+        match self {
+            WorkflowEvent::Push(push) => &push.repository.full_name,
+            WorkflowEvent::PullRequest(event) | WorkflowEvent::PullRequestTarget(event) => {
+                &event.repository.full_name
+            }
+            WorkflowEvent::WorkflowDispatch(dispatch) => &dispatch.repository.full_name,
+        }
+    }
+
+    pub fn repository_name(&self) -> &'a str {
+        match self {
+            WorkflowEvent::Push(push) => &push.repository.name,
+            WorkflowEvent::PullRequest(event) | WorkflowEvent::PullRequestTarget(event) => {
+                &event.repository.name
+            }
+            WorkflowEvent::WorkflowDispatch(dispatch) => &dispatch.repository.name,
+        }
+    }
+
+    pub fn clone_url(&self) -> &'a str {
+        match self {
+            WorkflowEvent::Push(push) => &push.repository.clone_url,
+            WorkflowEvent::PullRequest(event) | WorkflowEvent::PullRequestTarget(event) => {
+                &event.repository.clone_url
+            }
+            WorkflowEvent::WorkflowDispatch(dispatch) => &dispatch.repository.clone_url,
+        }
+    }
+
+    /// The ref the workflow files are read from, and the commit on it if it is known
+    /// up front. GitHub creates the merge ref of a pull request itself, so its commit is
+    /// whatever the ref points to when it is fetched.
+    pub fn workflow_ref(&self) -> (String, Option<&'a str>) {
+        match self {
+            WorkflowEvent::Push(push) => (push.ref_.clone(), Some(&push.after)),
+            WorkflowEvent::PullRequest(event) => (merge_ref(event), None),
+            // the base branch's workflows, so a pull request can't change what runs
+            WorkflowEvent::PullRequestTarget(event) => (
+                format!("refs/heads/{}", event.pull_request.base.ref_),
+                Some(&event.pull_request.base.sha),
+            ),
+            WorkflowEvent::WorkflowDispatch(dispatch) => (dispatch.ref_.clone(), None),
+        }
+    }
+
+    /// `GITHUB_REF`, and `GITHUB_SHA` when it isn't the commit the workflows are read
+    /// from.
+    pub fn job_ref(&self) -> (String, Option<&'a str>) {
+        match self {
+            WorkflowEvent::Push(push) => (push.ref_.clone(), None),
+            WorkflowEvent::PullRequest(event) => (merge_ref(event), None),
+            WorkflowEvent::PullRequestTarget(event) => (
+                format!("refs/pull/{}/head", event.number),
+                Some(&event.pull_request.head.sha),
+            ),
+            WorkflowEvent::WorkflowDispatch(dispatch) => (dispatch.ref_.clone(), None),
+        }
+    }
+
+    /// The commit check runs are reported on, which for pull requests is their head so
+    /// they show up on the pull request. `None` when it is the commit the workflows are read
+    /// from.
+    pub fn check_sha(&self) -> Option<&'a str> {
+        match self {
+            WorkflowEvent::Push(push) => Some(&push.after),
+            WorkflowEvent::PullRequest(event) | WorkflowEvent::PullRequestTarget(event) => {
+                Some(&event.pull_request.head.sha)
+            }
+            WorkflowEvent::WorkflowDispatch(_) => None,
+        }
+    }
+
+    /// Path of the only workflow file the event runs, e.g. `.github/workflows/bench.yml`.
+    pub fn workflow_file(&self) -> Option<&'a str> {
+        match self {
+            WorkflowEvent::WorkflowDispatch(dispatch) => Some(&dispatch.workflow),
+            _ => None,
+        }
+    }
+
+    /// `GITHUB_HEAD_REF`, only set for pull requests.
+    pub fn head_ref(&self) -> Option<&'a str> {
+        self.pull_request()
+            .map(|event| event.pull_request.head.ref_.as_str())
+    }
+
+    /// `GITHUB_BASE_REF`, only set for pull requests.
+    pub fn base_ref(&self) -> Option<&'a str> {
+        self.pull_request()
+            .map(|event| event.pull_request.base.ref_.as_str())
+    }
+
+    pub fn pull_request(&self) -> Option<&'a PullRequestEvent> {
+        match self {
+            WorkflowEvent::Push(_) | WorkflowEvent::WorkflowDispatch(_) => None,
+            WorkflowEvent::PullRequest(event) | WorkflowEvent::PullRequestTarget(event) => {
+                Some(event)
+            }
+        }
+    }
+
+    /// Whether the code to build comes from a fork, whose pull_request workflows don't get
+    /// the repository's secrets.
+    pub fn is_from_fork(&self) -> bool {
+        self.pull_request().is_some_and(|event| {
+            event
+                .pull_request
+                .head
+                .repo
+                .as_ref()
+                .is_none_or(|repo| repo.full_name != event.repository.full_name)
+        })
+    }
+
+    /// Whether a workflow with these activity `types:` runs on the event.
+    pub fn matches_types(&self, types: Option<&[String]>) -> bool {
+        let Some(event) = self.pull_request() else {
+            return true;
+        };
+        match types {
+            Some(types) => types.contains(&event.action),
+            None => DEFAULT_PULL_REQUEST_TYPES.contains(&event.action.as_str()),
+        }
+    }
+}
+
+fn merge_ref(event: &PullRequestEvent) -> String {
+    format!("refs/pull/{}/merge", event.number)
+}
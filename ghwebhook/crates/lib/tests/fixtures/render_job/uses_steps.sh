@@ -0,0 +1,122 @@
+#!/bin/bash
+#SBATCH --job-name=checkout
+#SBATCH --ntasks=1
+#SBATCH --partition=debug
+#SBATCH --nodes=1
+#SBATCH --output=repo_checkout_%j.log
+#SBATCH --error=repo_checkout_%j.err
+
+set -e
+
+export WORK_DIR='/tmp/repo_checkout'_"${SLURM_JOB_ID}"
+export STEPS_DIR="${WORK_DIR}_steps"
+export REPOS=('actions/checkout' 'actions/setup-node')
+export ACTIONS_CACHE_DIR='/tmp/repo_checkout/actions_cache'
+export NUM_TASKS=${#REPOS[@]}
+export REPOS_STR="${REPOS[*]}"
+export INPUT_TOKEN="$GITHUB_TOKEN"
+
+cleanup() {
+    local exit_code=$?
+    echo ""
+    echo "Cleanup"
+    cd /
+    rm -rf "$WORK_DIR"
+    rm -rf "$STEPS_DIR"
+    rm -rf "$ACTIONS_CACHE_DIR"
+    echo ""
+    echo "=========================================="
+    echo "Workflow completed at: $(date)"
+    echo "=========================================="
+    exec >&- 2>&-
+    wait "${MASK_PIDS[@]}" || true
+    exit $exit_code
+}
+
+trap cleanup EXIT
+
+SECRET_MASKS=()
+for secret in "$GITHUB_TOKEN" "$INPUT_TOKEN"; do
+    while IFS= read -r line; do
+        [ -n "$line" ] && SECRET_MASKS+=("$line")
+    done <<< "$secret"
+done
+mask() {
+    while IFS= read -r line || [ -n "$line" ]; do
+        for secret in "${SECRET_MASKS[@]}"; do
+            line=${line//"$secret"/***}
+        done
+        printf '%s\n' "$line"
+    done
+}
+exec > >(mask)
+MASK_PIDS=($!)
+exec 2> >(mask >&2)
+MASK_PIDS+=($!)
+
+srun [ -d "$WORK_DIR" ] || mkdir -p "$WORK_DIR"
+
+echo "Checking out 3f786850e387550fdab836ed7e6dc881de23001b"
+srun --chdir="$WORK_DIR" --export=GITHUB_TOKEN /usr/bin/env \
+    'CLONE_URL=https://github.com/octo/repo.git' \
+    'GITHUB_REF=refs/heads/main' \
+    'GITHUB_SHA=3f786850e387550fdab836ed7e6dc881de23001b' \
+    'PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin:/snap/bin' \
+    bash --noprofile --norc -ec 'git init -q .
+fetch() {
+    git -c credential.helper= \
+        -c credential.helper='\''!f() { echo username=x-access-token; echo "password=$GITHUB_TOKEN"; }; f'\'' \
+        fetch -q --no-tags "$CLONE_URL" "$@"
+}
+fetch --depth=1 "$GITHUB_SHA" || fetch "$GITHUB_REF"
+git checkout -q --detach "$GITHUB_SHA"'
+
+mkdir -p "$STEPS_DIR"
+
+echo "Setting up third party actions"
+
+srun [ -d "$ACTIONS_CACHE_DIR" ] || mkdir -p "$ACTIONS_CACHE_DIR"
+
+# Setup third party actions
+srun --ntasks=1  bash -c '
+    cd "$ACTIONS_CACHE_DIR"
+    IFS=" " read -r -a REPOS <<< "$REPOS_STR"
+
+    for i in "${REPOS[@]}"; do
+echo "Setting up third party action: $i"
+
+[ -d "$i" ] || mkdir -p "$i"
+pushd "$i"
+
+git clone "https://github.com/$i" .
+
+echo "Setup third party action: $i"
+
+popd
+    done
+'
+
+echo 'Running step: Check out'
+srun --chdir="$WORK_DIR" --export=GITHUB_TOKEN,INPUT_TOKEN /usr/bin/env \
+    "GITHUB_WORKSPACE=$WORK_DIR" \
+    'GITHUB_REPOSITORY=octo/repo' \
+    'GITHUB_REF=refs/heads/main' \
+    'GITHUB_SHA=3f786850e387550fdab836ed7e6dc881de23001b' \
+    'GITHUB_EVENT_NAME=push' \
+    'PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin:/snap/bin' \
+    'RUNNER_TEMP=/tmp' \
+    'INPUT_FETCH-DEPTH=0' \
+    'INPUT_REF=main' \
+    '/usr/bin/node' '/tmp/repo_checkout/actions_cache/actions/checkout/dist/index.js'
+
+echo 'Running step: Set up node'
+srun --chdir="$WORK_DIR" --export=GITHUB_TOKEN,INPUT_TOKEN /usr/bin/env \
+    "GITHUB_WORKSPACE=$WORK_DIR" \
+    'GITHUB_REPOSITORY=octo/repo' \
+    'GITHUB_REF=refs/heads/main' \
+    'GITHUB_SHA=3f786850e387550fdab836ed7e6dc881de23001b' \
+    'GITHUB_EVENT_NAME=push' \
+    'PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin:/snap/bin' \
+    'RUNNER_TEMP=/tmp' \
+    'INPUT_NODE-VERSION=20' \
+    '/usr/bin/node' '/tmp/repo_checkout/actions_cache/actions/setup-node/dist/index.js'
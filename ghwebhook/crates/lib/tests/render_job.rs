@@ -0,0 +1,71 @@
+//! Renders the workflows in `tests/fixtures/render_job` and compares the scripts with the
+//! `.sh` file next to each. Run with `UPDATE_FIXTURES=1` to write the scripts instead, and
+//! review the diff.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use lib::script::{EventContext, ScriptConfig, render_job};
+use lib::types::workflow::GithubWorkflow;
+
+fn context(event_name: &str, inputs: BTreeMap<String, String>) -> EventContext {
+    EventContext {
+        repository: "octo/repo".to_string(),
+        repository_name: "repo".to_string(),
+        clone_url: "https://github.com/octo/repo.git".to_string(),
+        git_ref: "refs/heads/main".to_string(),
+        sha: "3f786850e387550fdab836ed7e6dc881de23001b".to_string(),
+        event_name: event_name.to_string(),
+        head_ref: None,
+        base_ref: None,
+        inputs,
+    }
+}
+
+fn assert_renders(fixture: &str, context: &EventContext) {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/render_job");
+    let workflow_path = dir.join(format!("{}.yml", fixture));
+    let script_path = dir.join(format!("{}.sh", fixture));
+
+    let workflow: GithubWorkflow =
+        serde_yaml::from_str(&std::fs::read_to_string(&workflow_path).unwrap()).unwrap();
+    let (job_name, job) = workflow.jobs.iter().next().unwrap();
+    let script = render_job(&workflow, job_name, job, context, &ScriptConfig::default()).unwrap();
+
+    if std::env::var_os("UPDATE_FIXTURES").is_some() {
+        std::fs::write(&script_path, &script).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&script_path).unwrap();
+    assert!(
+        script == expected,
+        "{} no longer renders as {}:\n{}",
+        workflow_path.display(),
+        script_path.display(),
+        script
+    );
+}
+
+#[test]
+fn run_steps() {
+    assert_renders("run_steps", &context("push", BTreeMap::new()));
+}
+
+#[test]
+fn uses_steps() {
+    assert_renders("uses_steps", &context("push", BTreeMap::new()));
+}
+
+#[test]
+fn secrets() {
+    assert_renders("secrets", &context("push", BTreeMap::new()));
+}
+
+#[test]
+fn inputs() {
+    let inputs = BTreeMap::from([
+        ("iterations".to_string(), "10".to_string()),
+        ("partition".to_string(), "gpu".to_string()),
+    ]);
+    assert_renders("inputs", &context("workflow_dispatch", inputs));
+}
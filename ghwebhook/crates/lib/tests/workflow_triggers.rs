@@ -0,0 +1,238 @@
+//! Parses the workflows in `tests/fixtures/workflows`, which write `on:` in each of the
+//! forms GitHub accepts.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use lib::errors::AppError;
+use lib::types::workflow::{GithubWorkflow, GithubWorkflowInputType};
+
+fn workflow(file: &str) -> GithubWorkflow {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/workflows")
+        .join(file);
+    let workflow = std::fs::read_to_string(&path).unwrap();
+    match serde_yaml::from_str(&workflow) {
+        Ok(workflow) => workflow,
+        Err(err) => panic!("{}: {}", path.display(), err),
+    }
+}
+
+fn events(workflow: &GithubWorkflow) -> Vec<&str> {
+    workflow.on.events.keys().map(String::as_str).collect()
+}
+
+fn strings(values: &[&str]) -> Option<Vec<String>> {
+    Some(values.iter().map(|value| value.to_string()).collect())
+}
+
+#[test]
+fn every_workflow_parses() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/workflows");
+    let mut parsed = 0;
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let file = entry.unwrap().file_name();
+        let workflow = workflow(file.to_str().unwrap());
+        assert!(!workflow.on.events.is_empty(), "{:?}", file);
+        parsed += 1;
+    }
+    assert_eq!(parsed, 8);
+}
+
+#[test]
+fn on_a_single_event() {
+    let workflow = workflow("string.yml");
+    assert_eq!(events(&workflow), ["push"]);
+    assert!(workflow.on.event("push").unwrap().branches.is_none());
+}
+
+#[test]
+fn on_a_list_of_events() {
+    let workflow = workflow("list.yml");
+    assert_eq!(events(&workflow), ["pull_request", "push"]);
+    assert!(workflow.on.event("pull_request").unwrap().types.is_none());
+}
+
+#[test]
+fn on_events_without_filters() {
+    let workflow = workflow("null.yml");
+    assert_eq!(
+        events(&workflow),
+        ["pull_request", "push", "workflow_dispatch"]
+    );
+    let push = workflow.on.event("push").unwrap();
+    assert!(push.branches.is_none() && push.paths.is_none() && push.tags.is_none());
+    assert!(
+        workflow
+            .on
+            .event("workflow_dispatch")
+            .unwrap()
+            .inputs
+            .is_none()
+    );
+}
+
+#[test]
+fn on_events_with_branches() {
+    let workflow = workflow("rust.yml");
+    assert_eq!(events(&workflow), ["pull_request", "push"]);
+    assert_eq!(
+        workflow.on.event("push").unwrap().branches,
+        strings(&["main"])
+    );
+    assert_eq!(
+        workflow.on.event("pull_request").unwrap().branches,
+        strings(&["main"])
+    );
+}
+
+#[test]
+fn on_a_schedule() {
+    let workflow = workflow("schedule.yml");
+    assert_eq!(events(&workflow), ["schedule", "workflow_dispatch"]);
+    assert!(workflow.on.event("schedule").unwrap().branches.is_none());
+}
+
+#[test]
+fn on_filtered_events() {
+    let workflow = workflow("filters.yml");
+    assert_eq!(events(&workflow), ["pull_request", "push", "release"]);
+
+    let push = workflow.on.event("push").unwrap();
+    assert_eq!(push.tags, strings(&["v[0-9]+.[0-9]+.[0-9]+", "!v*-rc*"]));
+    assert_eq!(push.branches_ignore, strings(&["dependabot/**"]));
+    assert_eq!(push.paths_ignore, strings(&["docs/**", "**.md"]));
+    assert!(push.branches.is_none());
+
+    let pull_request = workflow.on.event("pull_request").unwrap();
+    assert_eq!(
+        pull_request.types,
+        strings(&["opened", "synchronize", "reopened", "ready_for_review"])
+    );
+    assert_eq!(pull_request.paths, strings(&["src/**", "Cargo.toml"]));
+
+    assert_eq!(
+        workflow.on.event("release").unwrap().types,
+        strings(&["published"])
+    );
+}
+
+#[test]
+fn on_workflow_dispatch_with_inputs() {
+    let workflow = workflow("dispatch.yml");
+    assert_eq!(events(&workflow), ["workflow_dispatch"]);
+
+    let inputs = workflow
+        .on
+        .event("workflow_dispatch")
+        .unwrap()
+        .inputs
+        .as_ref()
+        .unwrap();
+    let partition = &inputs["partition"];
+    assert_eq!(partition.type_, GithubWorkflowInputType::Choice);
+    assert_eq!(partition.options, ["cpu", "gpu"]);
+    assert!(partition.required);
+    assert_eq!(inputs["iterations"].default.as_deref(), Some("5"));
+    assert_eq!(inputs["verbose"].type_, GithubWorkflowInputType::Boolean);
+    assert_eq!(inputs["verbose"].default.as_deref(), Some("false"));
+    assert_eq!(inputs["note"].type_, GithubWorkflowInputType::String);
+
+    let filled_in = workflow.on.dispatch_inputs(&HashMap::new()).unwrap();
+    assert_eq!(filled_in["partition"], "cpu");
+    assert_eq!(filled_in["iterations"], "5");
+    assert_eq!(filled_in["verbose"], "false");
+    assert_eq!(filled_in["note"], "");
+}
+
+fn dispatch_error(workflow: &GithubWorkflow, supplied: serde_json::Value) -> String {
+    let supplied: HashMap<String, serde_json::Value> = serde_json::from_value(supplied).unwrap();
+    match workflow.on.dispatch_inputs(&supplied) {
+        Err(AppError::WorkflowInputError(message)) => message,
+        other => panic!("{:?} was accepted: {:?}", supplied, other),
+    }
+}
+
+#[test]
+fn on_workflow_dispatch_with_supplied_inputs() {
+    let workflow = workflow("dispatch.yml");
+    let supplied = serde_json::json!({
+        "partition": "gpu",
+        "iterations": 10,
+        "verbose": "TRUE",
+        "note": "it's a 'note'",
+    });
+    let supplied: HashMap<String, serde_json::Value> = serde_json::from_value(supplied).unwrap();
+    let filled_in = workflow.on.dispatch_inputs(&supplied).unwrap();
+    assert_eq!(filled_in["partition"], "gpu");
+    assert_eq!(filled_in["iterations"], "10");
+    assert_eq!(filled_in["verbose"], "true");
+    assert_eq!(filled_in["note"], "it's a 'note'");
+}
+
+#[test]
+fn on_workflow_dispatch_with_invalid_inputs() {
+    let workflow = workflow("dispatch.yml");
+    assert_eq!(
+        dispatch_error(&workflow, serde_json::json!({"verbose": "yes"})),
+        "verbose must be true or false"
+    );
+    assert_eq!(
+        dispatch_error(&workflow, serde_json::json!({"iterations": "many"})),
+        "iterations must be a number"
+    );
+    assert!(
+        dispatch_error(&workflow, serde_json::json!({"partition": "tpu"}))
+            .starts_with("partition must be one of")
+    );
+    assert_eq!(
+        dispatch_error(&workflow, serde_json::json!({"partitions": "cpu"})),
+        "partitions is not an input of the workflow"
+    );
+    assert_eq!(
+        dispatch_error(&workflow, serde_json::json!({"note": ["a", "b"]})),
+        "note must be a string, number or boolean"
+    );
+}
+
+#[test]
+fn on_workflow_dispatch_without_required_inputs() {
+    let workflow: GithubWorkflow = serde_yaml::from_str(
+        "on:
+  workflow_dispatch:
+    inputs:
+      target:
+        required: true
+jobs: {}",
+    )
+    .unwrap();
+    assert_eq!(
+        dispatch_error(&workflow, serde_json::json!({})),
+        "target is required"
+    );
+    assert_eq!(
+        dispatch_error(&workflow, serde_json::json!({"target": ""})),
+        "target is required"
+    );
+
+    // workflows without inputs take none
+    let workflow = workflow_without_inputs();
+    assert_eq!(
+        dispatch_error(&workflow, serde_json::json!({"target": "x"})),
+        "target is not an input of the workflow"
+    );
+}
+
+fn workflow_without_inputs() -> GithubWorkflow {
+    serde_yaml::from_str("on: workflow_dispatch\njobs: {}").unwrap()
+}
+
+#[test]
+fn on_events_this_worker_does_not_run() {
+    let workflow = workflow("reusable.yml");
+    assert_eq!(events(&workflow), ["workflow_call", "workflow_run"]);
+    assert_eq!(
+        workflow.on.event("workflow_run").unwrap().branches,
+        strings(&["main"])
+    );
+}
@@ -0,0 +1,278 @@
+use lib::store::{ApprovalRecord, NewApproval, NewRun};
+use lib::types::{
+    approval::ApprovalRequest, envelope::QueueEnvelope, webhookevent::IssueCommentEvent,
+    workflowevent::WorkflowEvent,
+};
+
+use crate::dlq::{Failure, FailureStage};
+use crate::submission::{TriggeredWorkflows, read_workflows, submit_workflows};
+use crate::worker::Worker;
+
+const APPROVE_COMMAND: &str = "/approve";
+
+/// Records the runs of a pull request as waiting for approval, with the delivery so it can
+/// be run once somebody approves it.
+pub fn hold_workflows(
+    worker: &Worker,
+    envelope: &QueueEnvelope,
+    event: &WorkflowEvent<'_>,
+    reason: &str,
+    triggered: &[TriggeredWorkflows],
+) -> Result<(), Failure> {
+    let Some(pull_request) = event.pull_request() else {
+        return Ok(());
+    };
+    let envelope_json = match serde_json::to_string(envelope) {
+        Ok(envelope_json) => envelope_json,
+        Err(err) => return Err(Failure::new(FailureStage::Store, err)),
+    };
+
+    let approval_id = worker
+        .run_store
+        .create_approval(&NewApproval {
+            repository: event.repository(),
+            pull_request: pull_request.number,
+            head_sha: &pull_request.pull_request.head.sha,
+            author: &pull_request.pull_request.user.login,
+            reason,
+            envelope: &envelope_json,
+        })
+        .map_err(|err| Failure::new(FailureStage::Store, err))?;
+
+    for triggered_workflows in triggered {
+        let event_context = &triggered_workflows.context;
+        for workflow in &triggered_workflows.workflows {
+            worker
+                .run_store
+                .create_run(&NewRun {
+                    delivery_id: envelope.delivery_id.as_deref(),
+                    repository: &event_context.repository,
+                    workflow: &workflow.name.clone().unwrap_or_default(),
+                    event: &event_context.event_name,
+                    git_ref: &event_context.git_ref,
+                    sha: &event_context.sha,
+                    approval_id: Some(approval_id),
+                })
+                .map_err(|err| Failure::new(FailureStage::Store, err))?;
+        }
+    }
+
+    println!(
+        "⏸️ Runs of {}#{} wait for approval {}: {}. Comment {} on the pull request to run them.",
+        event.repository(),
+        pull_request.number,
+        approval_id,
+        reason,
+        APPROVE_COMMAND
+    );
+    Ok(())
+}
+
+/// Runs what an approval published by the api held. The api names the approval, so it is
+/// released whatever commit the pull request is at now.
+pub async fn run_approval(worker: &Worker, envelope: &QueueEnvelope) -> Result<(), Failure> {
+    let request: ApprovalRequest = match serde_json::from_value(envelope.payload.clone()) {
+        Ok(request) => request,
+        Err(err) => return Err(Failure::new(FailureStage::Parse, err)),
+    };
+
+    match worker.run_store.get_approval(request.approval_id) {
+        Ok(Some(approval)) => release_approval(worker, &approval, &request.approved_by).await,
+        Ok(None) => {
+            eprintln!("No approval {}, skipping.", request.approval_id);
+            Ok(())
+        }
+        Err(err) => Err(Failure::new(FailureStage::Store, err)),
+    }
+}
+
+/// Approves the held runs of a pull request when somebody allowed to comments `/approve` on
+/// it. Only runs held for the commit the pull request is at are approved, the comment
+/// can't approve commits its author may not have seen.
+pub async fn approve_from_comment(
+    worker: &Worker,
+    event: &IssueCommentEvent,
+) -> Result<(), Failure> {
+    if event.action != "created"
+        || event.issue.pull_request.is_none()
+        || !event
+            .comment
+            .body
+            .lines()
+            .any(|line| line.trim() == APPROVE_COMMAND)
+    {
+        return Ok(());
+    }
+
+    let repository = &event.repository.full_name;
+    let login = &event.comment.user.login;
+    if !worker.approval_policy.can_approve(
+        repository,
+        login,
+        event.comment.author_association.as_deref(),
+    ) {
+        println!(
+            "{} can't approve runs of {}, ignoring {}.",
+            login, repository, APPROVE_COMMAND
+        );
+        return Ok(());
+    }
+
+    let approvals = worker
+        .run_store
+        .waiting_approvals(repository, event.issue.number)
+        .map_err(|err| Failure::new(FailureStage::Store, err))?;
+    if approvals.is_empty() {
+        println!(
+            "No runs of {}#{} wait for approval.",
+            repository, event.issue.number
+        );
+        return Ok(());
+    }
+
+    let installation_id = event
+        .installation
+        .as_ref()
+        .map(|installation| installation.id);
+    let github_token = match worker.github_auth.token(installation_id).await {
+        Ok(github_token) => github_token,
+        Err(err) => return Err(Failure::new(FailureStage::Auth, err)),
+    };
+    let head_sha = match worker
+        .github_api
+        .pull_request_head(&github_token, repository, event.issue.number)
+        .await
+    {
+        Ok(head_sha) => head_sha,
+        Err(err) => return Err(Failure::new(FailureStage::Checkout, err)),
+    };
+
+    let (current, outdated) = split_by_head(&approvals, &head_sha);
+    for approval in outdated {
+        println!(
+            "Approval {} of {}#{} holds {}, not the head {}, leaving it waiting.",
+            approval.id, repository, event.issue.number, approval.head_sha, head_sha
+        );
+    }
+    for approval in current {
+        release_approval(worker, approval, login).await?;
+    }
+    Ok(())
+}
+
+// the approvals held for `head_sha`, and the others
+fn split_by_head<'a>(
+    approvals: &'a [ApprovalRecord],
+    head_sha: &str,
+) -> (Vec<&'a ApprovalRecord>, Vec<&'a ApprovalRecord>) {
+    approvals
+        .iter()
+        .partition(|approval| approval.head_sha == head_sha)
+}
+
+/// Reads the workflows of a held delivery again and submits them as the runs that waited
+/// for it.
+async fn release_approval(
+    worker: &Worker,
+    approval: &ApprovalRecord,
+    approved_by: &str,
+) -> Result<(), Failure> {
+    if let Some(previous) = &approval.approved_by {
+        println!(
+            "Approval {} was already given by {}, skipping.",
+            approval.id, previous
+        );
+        return Ok(());
+    }
+
+    let envelope = match QueueEnvelope::decode(approval.envelope.as_bytes(), None, None) {
+        Ok(envelope) => envelope,
+        Err(err) => return Err(Failure::new(FailureStage::Decode, err)),
+    };
+    let webhook_event = match envelope.webhook_event() {
+        Ok(webhook_event) => webhook_event,
+        Err(err) => return Err(Failure::new(FailureStage::Parse, err)),
+    };
+    let held_runs = worker
+        .run_store
+        .held_runs(approval.id)
+        .map_err(|err| Failure::new(FailureStage::Store, err))?;
+
+    let github_token = match worker.github_auth.token(envelope.installation_id).await {
+        Ok(github_token) => github_token,
+        Err(err) => return Err(Failure::new(FailureStage::Auth, err)),
+    };
+
+    let mut triggered = Vec::new();
+    for workflow_event in &WorkflowEvent::from_webhook(&webhook_event) {
+        // the merge ref moves with both branches, the commit that was held is the one
+        // that was approved
+        let held_sha = held_runs
+            .iter()
+            .find(|run| run.event == workflow_event.name())
+            .map(|run| run.sha.as_str());
+        if let Some(triggered_workflows) =
+            read_workflows(workflow_event, &github_token, held_sha).await?
+        {
+            triggered.push(triggered_workflows);
+        }
+    }
+
+    match worker.run_store.approve(approval.id, approved_by) {
+        Ok(true) => {}
+        Ok(false) => {
+            println!("Approval {} was already given, skipping.", approval.id);
+            return Ok(());
+        }
+        Err(err) => return Err(Failure::new(FailureStage::Store, err)),
+    }
+    println!(
+        "▶️ {} approved the runs of {}#{}",
+        approved_by, approval.repository, approval.pull_request
+    );
+
+    for triggered_workflows in triggered {
+        submit_workflows(worker, &envelope, &triggered_workflows, &held_runs).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approval(id: i64, head_sha: &str) -> ApprovalRecord {
+        ApprovalRecord {
+            id,
+            repository: "octo/repo".to_string(),
+            pull_request: 7,
+            head_sha: head_sha.to_string(),
+            author: "forker".to_string(),
+            reason: "the pull request comes from a fork".to_string(),
+            envelope: String::new(),
+            approved_by: None,
+            created_at: 0,
+            approved_at: None,
+        }
+    }
+
+    fn ids(approvals: &[&ApprovalRecord]) -> Vec<i64> {
+        approvals.iter().map(|approval| approval.id).collect()
+    }
+
+    #[test]
+    fn only_approvals_of_the_head_are_released() {
+        let approvals = [approval(1, "old"), approval(2, "head"), approval(3, "head")];
+        let (current, outdated) = split_by_head(&approvals, "head");
+        assert_eq!(ids(&current), [2, 3]);
+        assert_eq!(ids(&outdated), [1]);
+    }
+
+    #[test]
+    fn nothing_is_released_once_the_pull_request_moved_on() {
+        let approvals = [approval(1, "old")];
+        let (current, outdated) = split_by_head(&approvals, "pushed after the comment");
+        assert!(current.is_empty());
+        assert_eq!(ids(&outdated), [1]);
+    }
+}
@@ -0,0 +1,507 @@
+use std::path::PathBuf;
+use std::process::Stdio;
+
+use async_trait::async_trait;
+use lib::errors::AppError;
+use lib::script::{heredoc_delimiter, shell_quote};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use super::SubmissionBackend;
+use crate::config::AppConfig;
+use crate::slurm::{JobDescription, JobInfo, classify_message};
+
+// ssh exits with 255 when it couldn't connect or authenticate, as opposed to the remote
+// command failing
+const SSH_FAILURE_EXIT_CODE: i32 = 255;
+
+/// Where the Slurm commands run: on the worker host, or on a login node over SSH.
+pub enum CommandRunner {
+    Local,
+    Ssh(SshTarget),
+}
+
+pub struct SshTarget {
+    destination: String,
+    port: u16,
+    key: Option<String>,
+    known_hosts: Option<String>,
+}
+
+struct CommandOutput {
+    code: Option<i32>,
+    stdout: String,
+    stderr: String,
+}
+
+impl CommandOutput {
+    fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+impl CommandRunner {
+    pub fn ssh_from_config(config: &AppConfig) -> Result<Self, AppError> {
+        let host = config.ssh_host.as_ref().ok_or_else(|| {
+            AppError::ConfigError(config::ConfigError::Message(
+                "ssh_host must be set for the ssh submission backend".to_string(),
+            ))
+        })?;
+
+        Ok(CommandRunner::Ssh(SshTarget {
+            destination: match &config.ssh_user {
+                Some(user) => format!("{}@{}", user, host),
+                None => host.clone(),
+            },
+            port: config.ssh_port,
+            key: config.ssh_key.clone(),
+            known_hosts: config.ssh_known_hosts.clone(),
+        }))
+    }
+
+    /// Runs `program` with only `env` in its environment when given, feeding `stdin` to it.
+    async fn run(
+        &self,
+        program: &str,
+        args: &[String],
+        env: Option<&[(String, String)]>,
+        stdin: Option<&str>,
+    ) -> Result<CommandOutput, AppError> {
+        let (mut command, input) = match self {
+            CommandRunner::Local => {
+                // looked up on the worker's PATH, not the one in `env`
+                let mut command = Command::new(find_program(program));
+                command.args(args);
+                if let Some(env) = env {
+                    command.env_clear().envs(env.iter().map(|(k, v)| (k, v)));
+                }
+                (command, stdin.map(str::to_string))
+            }
+            CommandRunner::Ssh(target) => {
+                let mut invocation = shell_quote(program);
+                for arg in args {
+                    invocation.push(' ');
+                    invocation.push_str(&shell_quote(arg));
+                }
+
+                // the environment may hold secrets, so it's sent through stdin rather than
+                // showing up in the login node's process list
+                let (remote, input) = match env {
+                    Some(env) => (
+                        "bash -s".to_string(),
+                        Some(remote_script(program, args, env, stdin)),
+                    ),
+                    None => (invocation, stdin.map(str::to_string)),
+                };
+
+                let mut command = Command::new("ssh");
+                command
+                    .arg("-o")
+                    .arg("BatchMode=yes")
+                    .arg("-p")
+                    .arg(target.port.to_string());
+                if let Some(key) = &target.key {
+                    command.arg("-i").arg(key);
+                }
+                if let Some(known_hosts) = &target.known_hosts {
+                    command
+                        .arg("-o")
+                        .arg(format!("UserKnownHostsFile={}", known_hosts));
+                }
+                command.arg(&target.destination).arg("--").arg(remote);
+                (command, input)
+            }
+        };
+
+        let mut child = command
+            .stdin(if input.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| {
+                AppError::SlurmPermanentError(format!("failed to run {}: {}", program, err))
+            })?;
+
+        if let Some(input) = input
+            && let Some(mut child_stdin) = child.stdin.take()
+        {
+            child_stdin
+                .write_all(input.as_bytes())
+                .await
+                .map_err(|err| {
+                    AppError::SlurmRetryableError(format!(
+                        "failed to write to {}: {}",
+                        program, err
+                    ))
+                })?;
+        }
+
+        let output = child.wait_with_output().await.map_err(|err| {
+            AppError::SlurmRetryableError(format!("failed to wait for {}: {}", program, err))
+        })?;
+
+        Ok(CommandOutput {
+            code: output.status.code(),
+            stdout: String::from_utf8_lossy(&output.stdout).trim().to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        })
+    }
+
+    fn connection_failed(&self, output: &CommandOutput) -> bool {
+        matches!(self, CommandRunner::Ssh(_)) && output.code == Some(SSH_FAILURE_EXIT_CODE)
+    }
+
+    fn error(&self, program: &str, output: &CommandOutput) -> AppError {
+        let message = format!(
+            "{} exited with {}: {}",
+            program,
+            output
+                .code
+                .map(|code| code.to_string())
+                .unwrap_or_else(|| "a signal".to_string()),
+            output.stderr
+        );
+        classify_message(message, self.connection_failed(output))
+    }
+}
+
+// the first `program` on PATH, or `program` itself to let spawning it fail
+fn find_program(program: &str) -> PathBuf {
+    std::env::var_os("PATH")
+        .iter()
+        .flat_map(std::env::split_paths)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from(program))
+}
+
+// The script `bash -s` runs `program` with on the login node, with only `env` in its
+// environment and `stdin` as its input. The program is looked up before the login shell's
+// environment is dropped, so it's found whether `env` has a PATH or not.
+fn remote_script(
+    program: &str,
+    args: &[String],
+    env: &[(String, String)],
+    stdin: Option<&str>,
+) -> String {
+    let mut script = format!(
+        "program=$(command -v {}) || exit 127\n",
+        shell_quote(program)
+    );
+    script.push_str("for name in $(compgen -e); do unset \"$name\" 2>/dev/null; done\n");
+    for (key, value) in env {
+        script.push_str(&format!(
+            "export {}\n",
+            shell_quote(&format!("{key}={value}"))
+        ));
+    }
+
+    let mut invocation = "\"$program\"".to_string();
+    for arg in args {
+        invocation.push(' ');
+        invocation.push_str(&shell_quote(arg));
+    }
+    match stdin {
+        Some(stdin) => {
+            let delimiter = heredoc_delimiter("SLURM_ACTIONS_STDIN", stdin);
+            script.push_str(&format!("exec {} <<'{}'\n{}", invocation, delimiter, stdin));
+            if !stdin.ends_with('\n') {
+                script.push('\n');
+            }
+            script.push_str(&format!("{}\n", delimiter));
+        }
+        None => {
+            script.push_str(&format!("exec {} </dev/null\n", invocation));
+        }
+    }
+    script
+}
+
+/// Submits with `sbatch`, and follows and cancels jobs with `squeue`, `sacct` and `scancel`.
+pub struct CliBackend {
+    runner: CommandRunner,
+}
+
+impl CliBackend {
+    pub fn new(runner: CommandRunner) -> Self {
+        Self { runner }
+    }
+}
+
+#[async_trait]
+impl SubmissionBackend for CliBackend {
+    async fn submit(&self, job: &JobDescription) -> Result<u32, AppError> {
+        let mut args = vec![
+            "--parsable".to_string(),
+            format!("--chdir={}", job.current_working_directory),
+        ];
+        if let Some(name) = &job.name {
+            args.push(format!("--job-name={}", name));
+        }
+        if let Some(partition) = &job.partition {
+            args.push(format!("--partition={}", partition));
+        }
+
+        let env = job
+            .environment
+            .iter()
+            .filter_map(|variable| variable.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<Vec<_>>();
+
+        let output = self
+            .runner
+            .run("sbatch", &args, Some(&env), Some(&job.script))
+            .await?;
+        if !output.success() {
+            return Err(self.runner.error("sbatch", &output));
+        }
+
+        // `<job id>` or `<job id>;<cluster>`
+        output
+            .stdout
+            .split(';')
+            .next()
+            .and_then(|job_id| job_id.trim().parse().ok())
+            .ok_or_else(|| {
+                AppError::SlurmPermanentError(format!(
+                    "sbatch printed no job id: {}",
+                    output.stdout
+                ))
+            })
+    }
+
+    async fn status(&self, job_id: u32) -> Result<Option<JobInfo>, AppError> {
+        let output = self
+            .runner
+            .run(
+                "squeue",
+                &[
+                    "--noheader".to_string(),
+                    format!("--jobs={}", job_id),
+                    "--format=%T".to_string(),
+                ],
+                None,
+                None,
+            )
+            .await?;
+        if self.runner.connection_failed(&output) {
+            return Err(self.runner.error("squeue", &output));
+        }
+        // squeue fails for jobs slurmctld has already forgotten
+        if output.success()
+            && let Some(state) = output.stdout.lines().next()
+            && !state.is_empty()
+        {
+            return Ok(Some(JobInfo {
+                state: state.trim().to_string(),
+                exit_code: None,
+            }));
+        }
+
+        let output = self
+            .runner
+            .run(
+                "sacct",
+                &[
+                    "--noheader".to_string(),
+                    "--allocations".to_string(),
+                    "--parsable2".to_string(),
+                    format!("--jobs={}", job_id),
+                    "--format=State,ExitCode".to_string(),
+                ],
+                None,
+                None,
+            )
+            .await?;
+        if !output.success() {
+            if self.runner.connection_failed(&output) {
+                return Err(self.runner.error("sacct", &output));
+            }
+            return Ok(None);
+        }
+
+        // `CANCELLED by 1000|0:15`
+        Ok(output.stdout.lines().next().and_then(|line| {
+            let (state, exit_code) = line.split_once('|')?;
+            Some(JobInfo {
+                state: state.split_whitespace().next()?.to_string(),
+                exit_code: exit_code
+                    .split(':')
+                    .next()
+                    .and_then(|exit_code| exit_code.parse().ok()),
+            })
+        }))
+    }
+
+    async fn cancel(&self, job_id: u32) -> Result<(), AppError> {
+        let output = self
+            .runner
+            .run("scancel", &[job_id.to_string()], None, None)
+            .await?;
+        if !output.success() {
+            return Err(self.runner.error("scancel", &output));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Once;
+
+    const FAKE_SLURM: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/slurm-cli");
+
+    // puts the fake sbatch, squeue and sacct first on PATH, they act on the job name or id
+    // they're given, so tests running at the same time don't get in each other's way
+    fn backend() -> CliBackend {
+        static FAKE_PATH: Once = Once::new();
+        FAKE_PATH.call_once(|| {
+            let path = format!(
+                "{}:{}",
+                FAKE_SLURM,
+                std::env::var("PATH").unwrap_or_default()
+            );
+            // SAFETY: only these tests read PATH to find programs, and set it to the same
+            // value before running any
+            unsafe { std::env::set_var("PATH", path) };
+        });
+        CliBackend::new(CommandRunner::Local)
+    }
+
+    fn job(name: &str, environment: &[&str]) -> JobDescription {
+        JobDescription {
+            name: Some(name.to_string()),
+            partition: None,
+            script: "#!/bin/bash\necho done\n".to_string(),
+            environment: environment
+                .iter()
+                .map(|variable| variable.to_string())
+                .collect(),
+            current_working_directory: "/tmp".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn parsable_output_is_the_job_id() {
+        let backend = backend();
+        assert_eq!(backend.submit(&job("cluster", &[])).await.unwrap(), 4242);
+        assert_eq!(backend.submit(&job("plain", &[])).await.unwrap(), 4243);
+        assert!(matches!(
+            backend.submit(&job("garbage", &[])).await,
+            Err(AppError::SlurmPermanentError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn sbatch_errors_are_classified() {
+        let backend = backend();
+        assert!(matches!(
+            backend.submit(&job("rejected", &[])).await,
+            Err(AppError::SlurmPermanentError(message)) if message.contains("invalid partition")
+        ));
+        assert!(matches!(
+            backend.submit(&job("busy", &[])).await,
+            Err(AppError::SlurmRetryableError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn sbatch_only_gets_the_job_environment() {
+        let job = job("environment", &["SECRET=it's a 'secret'"]);
+        assert_eq!(backend().submit(&job).await.unwrap(), 4244);
+    }
+
+    async fn status(job_id: u32) -> Option<(String, Option<i64>)> {
+        backend()
+            .status(job_id)
+            .await
+            .unwrap()
+            .map(|info| (info.state, info.exit_code))
+    }
+
+    #[tokio::test]
+    async fn queued_jobs_have_their_squeue_state() {
+        assert_eq!(status(1).await, Some(("RUNNING".to_string(), None)));
+        assert_eq!(status(2).await, Some(("PENDING".to_string(), None)));
+    }
+
+    #[tokio::test]
+    async fn finished_jobs_have_their_sacct_state_and_exit_code() {
+        assert_eq!(status(3).await, Some(("COMPLETED".to_string(), Some(0))));
+        // the exit code is the part before the signal
+        assert_eq!(status(4).await, Some(("CANCELLED".to_string(), Some(0))));
+        assert_eq!(status(5).await, Some(("FAILED".to_string(), Some(2))));
+        assert_eq!(
+            status(6).await,
+            Some(("OUT_OF_MEMORY".to_string(), Some(0)))
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_jobs_have_no_status() {
+        assert_eq!(status(7).await, None);
+        assert_eq!(status(8).await, None);
+    }
+
+    // runs the script ssh sends to the login node with a login shell's environment
+    async fn run_remote(script: &str) -> std::process::Output {
+        let mut child = Command::new("bash")
+            .arg("-s")
+            .env_clear()
+            .env("PATH", format!("{}:/usr/bin:/bin", FAKE_SLURM))
+            .env("HOME", "/home/slurm")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(script.as_bytes()).await.unwrap();
+        drop(stdin);
+        child.wait_with_output().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn remote_scripts_run_the_program_with_only_the_job_environment() {
+        let args = ["--job-name=environment".to_string()];
+        let env = [("SECRET".to_string(), "it's a 'secret'".to_string())];
+        // the job's environment has no PATH, sbatch is found on the login shell's
+        let output = run_remote(&remote_script(
+            "sbatch",
+            &args,
+            &env,
+            Some("#!/bin/bash\necho done"),
+        ))
+        .await;
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "4244\n");
+    }
+
+    #[tokio::test]
+    async fn remote_scripts_keep_stdin_containing_the_delimiter() {
+        let output = run_remote(&remote_script(
+            "cat",
+            &[],
+            &[],
+            Some("SLURM_ACTIONS_STDIN\n$HOME `date`\n"),
+        ))
+        .await;
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "SLURM_ACTIONS_STDIN\n$HOME `date`\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn remote_scripts_fail_for_missing_programs() {
+        let output = run_remote(&remote_script("no-such-sbatch", &[], &[], None)).await;
+        assert_eq!(output.status.code(), Some(127));
+    }
+}
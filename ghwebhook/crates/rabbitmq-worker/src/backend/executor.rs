@@ -0,0 +1,620 @@
+use std::collections::HashMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use lib::errors::AppError;
+use tokio::process::Command;
+
+use super::SubmissionBackend;
+use crate::config::AppConfig;
+use crate::slurm::{JobDescription, JobInfo};
+
+// Stands in for srun: applies --chdir and --export the way srun would and runs the command
+// as a single task, ignoring options that only matter to the scheduler
+const SRUN_SHIM: &str = r#"#!/bin/bash
+exports=""
+while [ $# -gt 0 ]; do
+    case "$1" in
+        --chdir=*) cd "${1#--chdir=}" || exit 1 ;;
+        -D|--chdir) cd "$2" || exit 1; shift ;;
+        --export=*) exports="${1#--export=}" ;;
+        --export) exports="$2"; shift ;;
+        -n|-N|-c|-p|-J|-o|-e|-t|-w|-x|-A|-q|-C) shift ;;
+        --ntasks|--nodes|--cpus-per-task|--partition|--job-name|--output|--error|--time|\
+        --mem|--mem-per-cpu|--gres|--nodelist|--exclude|--account|--qos|--constraint|\
+        --ntasks-per-node|--cpu-bind|--mpi|--distribution) shift ;;
+        --) shift; break ;;
+        -*) ;;
+        *) break ;;
+    esac
+    shift
+done
+
+if [ -z "$exports" ]; then
+    exec "$@"
+fi
+
+IFS=, read -r -a variables <<< "$exports"
+inherit=false
+case "${variables[0]}" in
+    ALL) inherit=true; variables=("${variables[@]:1}") ;;
+    NONE) variables=("${variables[@]:1}") ;;
+esac
+
+# only the listed variables that are set and SLURM_* reach the task, unless ALL is listed
+environment=()
+while IFS= read -r name; do
+    environment+=("$name=${!name}")
+done < <(compgen -v SLURM_)
+for variable in "${variables[@]}"; do
+    case "$variable" in
+        *=*) environment+=("$variable") ;;
+        *) [ -n "${!variable+set}" ] && environment+=("$variable=${!variable}") ;;
+    esac
+done
+if [ "$inherit" = true ]; then
+    exec env "${environment[@]}" "$@"
+fi
+exec env -i "${environment[@]}" "$@"
+"#;
+
+// `#SBATCH` output paths default to this, like sbatch
+const DEFAULT_OUTPUT: &str = "slurm-%j.out";
+
+#[derive(Debug, Clone)]
+enum ExecutorState {
+    Running { pid: Option<u32> },
+    Completed,
+    Failed { exit_code: Option<i64> },
+    Cancelled,
+}
+
+impl ExecutorState {
+    fn info(&self) -> JobInfo {
+        let (state, exit_code) = match self {
+            ExecutorState::Running { .. } => ("RUNNING", None),
+            ExecutorState::Completed => ("COMPLETED", Some(0)),
+            ExecutorState::Failed { exit_code } => ("FAILED", *exit_code),
+            ExecutorState::Cancelled => ("CANCELLED", None),
+        };
+        JobInfo {
+            state: state.to_string(),
+            exit_code,
+        }
+    }
+}
+
+/// Runs batch scripts as plain subprocesses on the worker's host, so the whole pipeline
+/// can run without a Slurm cluster. Every job gets a directory under `executor_dir` with
+/// its script, and `srun` is replaced by a shim that runs the command directly.
+pub struct LocalExecutor {
+    dir: PathBuf,
+    jobs: Arc<Mutex<HashMap<u32, ExecutorState>>>,
+    next_job_id: Mutex<u32>,
+}
+
+impl LocalExecutor {
+    pub fn from_config(config: &AppConfig) -> Result<Self, AppError> {
+        let dir = std::path::absolute(&config.executor_dir).map_err(|err| {
+            AppError::ConfigError(config::ConfigError::Message(format!(
+                "invalid executor_dir {}: {}",
+                config.executor_dir, err
+            )))
+        })?;
+
+        let bin_dir = dir.join("bin");
+        std::fs::create_dir_all(&bin_dir).map_err(|err| executor_error("create", &bin_dir, err))?;
+        let shim = bin_dir.join("srun");
+        std::fs::write(&shim, SRUN_SHIM).map_err(|err| executor_error("write", &shim, err))?;
+        std::fs::set_permissions(&shim, std::fs::Permissions::from_mode(0o755))
+            .map_err(|err| executor_error("write", &shim, err))?;
+
+        // job ids continue after the jobs of earlier runs, so their directories and logs
+        // aren't reused
+        let last_job_id = std::fs::read_dir(&dir)
+            .map_err(|err| executor_error("read", &dir, err))?
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+            .max()
+            .unwrap_or_default();
+
+        Ok(Self {
+            dir,
+            jobs: Arc::default(),
+            next_job_id: Mutex::new(last_job_id + 1),
+        })
+    }
+
+    fn allocate_job_id(&self) -> u32 {
+        let mut next_job_id = self.next_job_id.lock().unwrap();
+        let job_id = *next_job_id;
+        *next_job_id += 1;
+        job_id
+    }
+}
+
+#[async_trait]
+impl SubmissionBackend for LocalExecutor {
+    async fn submit(&self, job: &JobDescription) -> Result<u32, AppError> {
+        let job_id = self.allocate_job_id();
+        let job_dir = self.dir.join(job_id.to_string());
+        tokio::fs::create_dir_all(&job_dir)
+            .await
+            .map_err(|err| executor_error("create", &job_dir, err))?;
+        let script_path = job_dir.join("script.sh");
+        tokio::fs::write(&script_path, &job.script)
+            .await
+            .map_err(|err| executor_error("write", &script_path, err))?;
+
+        let directives = Directives::parse(&job.script);
+        let job_name = job
+            .name
+            .clone()
+            .or(directives.job_name)
+            .unwrap_or_else(|| "script.sh".to_string());
+
+        // Slurm runs the job where it was submitted from, jobs submitted from elsewhere run in
+        // their own directory
+        let working_dir = Path::new(&job.current_working_directory);
+        let working_dir = if working_dir.is_dir() {
+            working_dir.to_path_buf()
+        } else {
+            job_dir.clone()
+        };
+
+        let expand = |pattern: &str| working_dir.join(expand_pattern(pattern, job_id, &job_name));
+        let output_path = expand(directives.output.as_deref().unwrap_or(DEFAULT_OUTPUT));
+        let error_path = directives.error.as_deref().map(expand);
+
+        let output = std::fs::File::create(&output_path)
+            .map_err(|err| executor_error("create", &output_path, err))?;
+        let error = match &error_path {
+            Some(error_path) if *error_path != output_path => std::fs::File::create(error_path)
+                .map_err(|err| executor_error("create", error_path, err))?,
+            _ => output
+                .try_clone()
+                .map_err(|err| executor_error("open", &output_path, err))?,
+        };
+
+        let mut environment = job
+            .environment
+            .iter()
+            .filter_map(|variable| variable.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+        let bin_dir = self.dir.join("bin").display().to_string();
+        let path = match environment.get("PATH") {
+            Some(path) => format!("{}:{}", bin_dir, path),
+            None => bin_dir,
+        };
+        environment.insert("PATH".to_string(), path);
+        environment.insert("SLURM_JOB_ID".to_string(), job_id.to_string());
+        environment.insert("SLURM_JOBID".to_string(), job_id.to_string());
+        environment.insert("SLURM_JOB_NAME".to_string(), job_name);
+        environment.insert(
+            "SLURM_SUBMIT_DIR".to_string(),
+            working_dir.display().to_string(),
+        );
+
+        let mut child = Command::new("bash")
+            .arg(&script_path)
+            .current_dir(&working_dir)
+            .env_clear()
+            .envs(&environment)
+            .stdin(Stdio::null())
+            .stdout(output)
+            .stderr(error)
+            // its own process group, so cancelling reaches everything the script started
+            .process_group(0)
+            .spawn()
+            .map_err(|err| {
+                AppError::SlurmPermanentError(format!("failed to run job {}: {}", job_id, err))
+            })?;
+
+        self.jobs
+            .lock()
+            .unwrap()
+            .insert(job_id, ExecutorState::Running { pid: child.id() });
+
+        let jobs = self.jobs.clone();
+        tokio::spawn(async move {
+            let state = match child.wait().await {
+                Ok(status) if status.success() => ExecutorState::Completed,
+                Ok(status) => ExecutorState::Failed {
+                    exit_code: status.code().map(i64::from),
+                },
+                Err(err) => {
+                    eprintln!("Failed to wait for local job {}: {}", job_id, err);
+                    ExecutorState::Failed { exit_code: None }
+                }
+            };
+
+            let mut jobs = jobs.lock().unwrap();
+            if !matches!(jobs.get(&job_id), Some(ExecutorState::Cancelled)) {
+                jobs.insert(job_id, state);
+            }
+        });
+
+        Ok(job_id)
+    }
+
+    async fn status(&self, job_id: u32) -> Result<Option<JobInfo>, AppError> {
+        Ok(self
+            .jobs
+            .lock()
+            .unwrap()
+            .get(&job_id)
+            .map(ExecutorState::info))
+    }
+
+    async fn cancel(&self, job_id: u32) -> Result<(), AppError> {
+        let pid = {
+            let mut jobs = self.jobs.lock().unwrap();
+            match jobs.get(&job_id) {
+                Some(ExecutorState::Running { pid }) => {
+                    let pid = *pid;
+                    jobs.insert(job_id, ExecutorState::Cancelled);
+                    pid
+                }
+                Some(_) => return Ok(()),
+                None => {
+                    return Err(AppError::SlurmPermanentError(format!(
+                        "Invalid job id specified: {}",
+                        job_id
+                    )));
+                }
+            }
+        };
+
+        if let Some(pid) = pid {
+            let status = Command::new("kill")
+                .arg("-TERM")
+                .arg("--")
+                .arg(format!("-{}", pid))
+                .status()
+                .await
+                .map_err(|err| {
+                    AppError::SlurmPermanentError(format!(
+                        "failed to cancel job {}: {}",
+                        job_id, err
+                    ))
+                })?;
+            if !status.success() {
+                eprintln!("Local job {} had already exited when cancelled", job_id);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The `#SBATCH` options the executor honours.
+#[derive(Debug, Default)]
+struct Directives {
+    job_name: Option<String>,
+    output: Option<String>,
+    error: Option<String>,
+}
+
+impl Directives {
+    // like sbatch, directives are read until the first command
+    fn parse(script: &str) -> Self {
+        let mut directives = Directives::default();
+        for line in script.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some(options) = line.strip_prefix("#SBATCH") else {
+                if line.starts_with('#') {
+                    continue;
+                }
+                break;
+            };
+
+            let mut words = options.split_whitespace();
+            while let Some(word) = words.next() {
+                let (option, value) = match word.split_once('=') {
+                    Some((option, value)) => (option, Some(value.to_string())),
+                    None => (word, None),
+                };
+                let mut value = || value.clone().or_else(|| words.next().map(str::to_string));
+                match option {
+                    "--job-name" | "-J" => directives.job_name = value(),
+                    "--output" | "-o" => directives.output = value(),
+                    "--error" | "-e" => directives.error = value(),
+                    _ => {}
+                }
+            }
+        }
+        directives
+    }
+}
+
+// the subset of sbatch's filename patterns that make sense without a cluster
+fn expand_pattern(pattern: &str, job_id: u32, job_name: &str) -> String {
+    let mut expanded = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('j') | Some('A') => expanded.push_str(&job_id.to_string()),
+            Some('x') => expanded.push_str(job_name),
+            Some('%') => expanded.push('%'),
+            Some(other) => {
+                expanded.push('%');
+                expanded.push(other);
+            }
+            None => expanded.push('%'),
+        }
+    }
+    expanded
+}
+
+fn executor_error(action: &str, path: &Path, err: std::io::Error) -> AppError {
+    AppError::SlurmPermanentError(format!("failed to {} {}: {}", action, path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use lib::script::{EventContext, ScriptConfig, render_job};
+    use lib::types::workflow::GithubWorkflow;
+    use tempdir::TempDir;
+
+    const SYSTEM_PATH: &str = "/usr/bin:/bin";
+
+    fn executor(dir: &Path) -> LocalExecutor {
+        let config: AppConfig = serde_json::from_value(serde_json::json!({
+            "executor_dir": dir.join("jobs"),
+        }))
+        .unwrap();
+        LocalExecutor::from_config(&config).unwrap()
+    }
+
+    fn job(script: &str, working_dir: &Path, environment: &[&str]) -> JobDescription {
+        JobDescription {
+            name: None,
+            partition: None,
+            script: script.to_string(),
+            environment: [format!("PATH={}", SYSTEM_PATH)]
+                .into_iter()
+                .chain(environment.iter().map(|variable| variable.to_string()))
+                .collect(),
+            current_working_directory: working_dir.display().to_string(),
+        }
+    }
+
+    async fn wait_for(executor: &LocalExecutor, job_id: u32) -> JobInfo {
+        for _ in 0..200 {
+            let info = executor.status(job_id).await.unwrap().unwrap();
+            if info.state != "RUNNING" {
+                return info;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("job {} is still running", job_id);
+    }
+
+    fn write_executable(path: &Path, content: &str) {
+        std::fs::write(path, content).unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    // what `srun <args>` run by a job prints to stdout
+    async fn srun(dir: &Path, args: &str, environment: &[&str]) -> String {
+        let executor = executor(dir);
+        let script = format!("#!/bin/bash\nsrun {}\n", args);
+        let job_id = executor
+            .submit(&job(&script, dir, environment))
+            .await
+            .unwrap();
+        let info = wait_for(&executor, job_id).await;
+        let output = std::fs::read_to_string(dir.join(format!("slurm-{}.out", job_id))).unwrap();
+        assert_eq!(info.state, "COMPLETED", "{}", output);
+        output
+    }
+
+    #[tokio::test]
+    async fn srun_exports_only_the_listed_variables() {
+        let dir = TempDir::new("executor").unwrap();
+        let output = srun(
+            dir.path(),
+            "--export=NONE,FOO,BAR=2,UNSET /usr/bin/env",
+            &["FOO=1", "OTHER=3"],
+        )
+        .await;
+
+        let mut variables = output
+            .lines()
+            .filter(|line| !line.starts_with("SLURM_"))
+            .collect::<Vec<_>>();
+        variables.sort();
+        assert_eq!(variables, ["BAR=2", "FOO=1"]);
+    }
+
+    #[tokio::test]
+    async fn srun_exports_all_variables_with_the_listed_ones() {
+        let dir = TempDir::new("executor").unwrap();
+        let output = srun(
+            dir.path(),
+            "--export=ALL,FOO,BAR=2 /usr/bin/env",
+            &["FOO=1", "OTHER=3"],
+        )
+        .await;
+
+        for variable in ["FOO=1", "BAR=2", "OTHER=3"] {
+            assert!(output.lines().any(|line| line == variable), "{}", output);
+        }
+    }
+
+    #[tokio::test]
+    async fn srun_skips_the_values_of_long_options() {
+        let dir = TempDir::new("executor").unwrap();
+        std::fs::create_dir(dir.path().join("work")).unwrap();
+        let output = srun(
+            dir.path(),
+            "--ntasks 1 --chdir work --export ALL,FOO=1 --label bash -c 'echo \"$PWD $FOO\"'",
+            &[],
+        )
+        .await;
+        assert_eq!(output, format!("{}/work 1\n", dir.path().display()));
+    }
+
+    #[tokio::test]
+    async fn output_paths_name_the_job() {
+        let dir = TempDir::new("executor").unwrap();
+        let executor = executor(dir.path());
+        let script = "#!/bin/bash\n#SBATCH --job-name=named\n#SBATCH --output=%x_%j.log\n#SBATCH -e %j.err\n\necho out\necho err >&2\n";
+        let job_id = executor
+            .submit(&job(script, dir.path(), &[]))
+            .await
+            .unwrap();
+        wait_for(&executor, job_id).await;
+
+        let read = |name: String| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read(format!("named_{}.log", job_id)), "out\n");
+        assert_eq!(read(format!("{}.err", job_id)), "err\n");
+        assert!(
+            dir.path()
+                .join("jobs")
+                .join(job_id.to_string())
+                .join("script.sh")
+                .is_file()
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_jobs_report_their_exit_code() {
+        let dir = TempDir::new("executor").unwrap();
+        let executor = executor(dir.path());
+        let job_id = executor
+            .submit(&job("#!/bin/bash\nexit 3\n", dir.path(), &[]))
+            .await
+            .unwrap();
+        let info = wait_for(&executor, job_id).await;
+        assert_eq!((info.state.as_str(), info.exit_code), ("FAILED", Some(3)));
+
+        let job_id = executor
+            .submit(&job("#!/bin/bash\ntrue\n", dir.path(), &[]))
+            .await
+            .unwrap();
+        let info = wait_for(&executor, job_id).await;
+        assert_eq!(
+            (info.state.as_str(), info.exit_code),
+            ("COMPLETED", Some(0))
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelled_jobs_stop() {
+        let dir = TempDir::new("executor").unwrap();
+        let executor = executor(dir.path());
+        let marker = dir.path().join("finished");
+        let script = format!(
+            "#!/bin/bash\nsrun sleep 30\ntouch {}\n",
+            lib::script::shell_quote(&marker.display().to_string())
+        );
+        let job_id = executor
+            .submit(&job(&script, dir.path(), &[]))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        executor.cancel(job_id).await.unwrap();
+        assert_eq!(
+            executor.status(job_id).await.unwrap().unwrap().state,
+            "CANCELLED"
+        );
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!marker.exists());
+        assert_eq!(
+            executor.status(job_id).await.unwrap().unwrap().state,
+            "CANCELLED"
+        );
+
+        assert!(matches!(
+            executor.cancel(job_id + 1).await,
+            Err(AppError::SlurmPermanentError(_))
+        ));
+    }
+
+    // runs the job rendered from lib's run_steps fixture, with `make` and `cargo` stubbed
+    #[tokio::test]
+    async fn rendered_jobs_run_end_to_end() {
+        let dir = TempDir::new("executor").unwrap();
+        let origin = dir.path().join("origin");
+        let sha = std::process::Command::new("bash")
+            .arg("-c")
+            .arg(
+                "git init -q \"$0\" && cd \"$0\" && echo checked out > file && git add file &&
+                git -c user.name=octo -c user.email=octo@example.com commit -q -m first &&
+                git rev-parse HEAD",
+            )
+            .arg(&origin)
+            .output()
+            .unwrap()
+            .stdout;
+        let sha = String::from_utf8(sha).unwrap().trim().to_string();
+
+        let stubs = dir.path().join("stubs");
+        std::fs::create_dir(&stubs).unwrap();
+        write_executable(
+            &stubs.join("make"),
+            "#!/bin/bash\necho \"make $* in $(cat file)\"\necho \"token $GITHUB_TOKEN\"\n",
+        );
+        write_executable(&stubs.join("cargo"), "#!/bin/bash\necho \"cargo $*\"\n");
+
+        let workflow: GithubWorkflow = serde_yaml::from_str(
+            &std::fs::read_to_string(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../lib/tests/fixtures/render_job/run_steps.yml"
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+        let (job_name, workflow_job) = workflow.jobs.iter().next().unwrap();
+        let context = EventContext {
+            repository: "octo/repo".to_string(),
+            repository_name: "repo".to_string(),
+            clone_url: origin.display().to_string(),
+            git_ref: "refs/heads/main".to_string(),
+            sha: sha.clone(),
+            event_name: "push".to_string(),
+            head_ref: None,
+            base_ref: None,
+            inputs: Default::default(),
+        };
+        let config = ScriptConfig {
+            path: format!("{}:{}", stubs.display(), SYSTEM_PATH),
+            temp_dir: dir.path().display().to_string(),
+            ..ScriptConfig::default()
+        };
+        let script = render_job(&workflow, job_name, workflow_job, &context, &config).unwrap();
+
+        let executor = executor(dir.path());
+        let job_id = executor
+            .submit(&job(&script, dir.path(), &["GITHUB_TOKEN=ghs_job"]))
+            .await
+            .unwrap();
+        let info = wait_for(&executor, job_id).await;
+
+        let output =
+            std::fs::read_to_string(dir.path().join(format!("repo_build_{}.log", job_id))).unwrap();
+        assert_eq!(info.state, "COMPLETED", "{}", output);
+        assert!(output.contains("make -j4 in checked out\n"), "{}", output);
+        assert!(output.contains("token ***\n"), "{}", output);
+        assert!(output.contains(&format!("testing {}\n", sha)), "{}", output);
+        assert!(output.contains("cargo test --workspace -- --nocapture\n"));
+        assert!(!output.contains("ghs_job"));
+        // the workspace is removed when the job ends
+        assert!(!dir.path().join(format!("repo_build_{}", job_id)).exists());
+    }
+}
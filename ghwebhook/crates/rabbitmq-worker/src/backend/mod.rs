@@ -0,0 +1,65 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use lib::errors::AppError;
+use serde::Deserialize;
+
+use crate::config::AppConfig;
+use crate::slurm::{JobDescription, JobInfo, SlurmRestClient};
+
+mod cli;
+mod executor;
+
+pub use cli::{CliBackend, CommandRunner};
+pub use executor::LocalExecutor;
+
+/// Which backend the worker submits jobs with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    Slurmrestd,
+    Ssh,
+    Local,
+    // runs the scripts on the worker's host without Slurm
+    Executor,
+}
+
+/// Submits batch jobs to a cluster and follows them. Failures that may go away when
+/// retried are reported as `SlurmRetryableError`.
+#[async_trait]
+pub trait SubmissionBackend: Send + Sync {
+    /// Submits the job and returns its Slurm job id.
+    async fn submit(&self, job: &JobDescription) -> Result<u32, AppError>;
+
+    /// Returns the job's state, `None` once the cluster doesn't know the job anymore.
+    async fn status(&self, job_id: u32) -> Result<Option<JobInfo>, AppError>;
+
+    async fn cancel(&self, job_id: u32) -> Result<(), AppError>;
+}
+
+pub fn from_config(
+    config: &AppConfig,
+    http: reqwest::Client,
+) -> Result<Arc<dyn SubmissionBackend>, AppError> {
+    Ok(match config.submission_backend {
+        BackendKind::Slurmrestd => Arc::new(SlurmRestClient::from_config(config, http)?),
+        BackendKind::Ssh => Arc::new(CliBackend::new(CommandRunner::ssh_from_config(config)?)),
+        BackendKind::Local => Arc::new(CliBackend::new(CommandRunner::Local)),
+        BackendKind::Executor => Arc::new(LocalExecutor::from_config(config)?),
+    })
+}
+
+#[async_trait]
+impl SubmissionBackend for SlurmRestClient {
+    async fn submit(&self, job: &JobDescription) -> Result<u32, AppError> {
+        SlurmRestClient::submit(self, job).await
+    }
+
+    async fn status(&self, job_id: u32) -> Result<Option<JobInfo>, AppError> {
+        self.job(job_id).await
+    }
+
+    async fn cancel(&self, job_id: u32) -> Result<(), AppError> {
+        SlurmRestClient::cancel(self, job_id).await
+    }
+}
@@ -0,0 +1,408 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::Utc;
+use lib::types::runstatus::RunStatus;
+use tokio::sync::{Mutex, broadcast};
+
+use crate::github::{
+    CheckRunConclusion, CheckRunOutput, CheckRunRequest, CheckRunStatus, GithubAuth,
+};
+use crate::tracker::{JobTransition, UNKNOWN_STATE};
+
+/// A check run created for one workflow job.
+#[derive(Debug, Clone)]
+pub struct JobCheck {
+    pub installation_id: u64,
+    pub repository: String,
+    pub check_run_id: u64,
+    pub partition: String,
+}
+
+/// Reports workflow jobs to GitHub as check runs. The Checks API is only available to
+/// GitHub Apps, so nothing is reported when the worker authenticates with a plain token.
+/// Failing to report is logged and never fails the job itself.
+#[derive(Clone)]
+pub struct ChecksReporter {
+    github: Arc<GithubAuth>,
+    // check runs of submitted jobs, by slurm job id
+    checks: Arc<Mutex<HashMap<u32, JobCheck>>>,
+}
+
+impl ChecksReporter {
+    pub fn new(github: Arc<GithubAuth>) -> Self {
+        Self {
+            github,
+            checks: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub async fn queued(
+        &self,
+        installation_id: Option<u64>,
+        repository: &str,
+        head_sha: &str,
+        name: &str,
+        partition: &str,
+    ) -> Option<JobCheck> {
+        let app = self.github.app()?;
+        let installation_id = installation_id?;
+
+        let request = CheckRunRequest {
+            name: Some(name.to_string()),
+            head_sha: Some(head_sha.to_string()),
+            status: Some(CheckRunStatus::Queued),
+            output: Some(CheckRunOutput {
+                title: "Submitting to Slurm".to_string(),
+                summary: format!("Submitting job to partition `{}`.", partition),
+            }),
+            ..Default::default()
+        };
+
+        match app
+            .create_check_run(installation_id, repository, &request)
+            .await
+        {
+            Ok(check_run_id) => Some(JobCheck {
+                installation_id,
+                repository: repository.to_string(),
+                check_run_id,
+                partition: partition.to_string(),
+            }),
+            Err(err) => {
+                eprintln!("Failed to create check run {}: {}", name, err);
+                None
+            }
+        }
+    }
+
+    pub async fn submitted(&self, check: &JobCheck, slurm_job_id: u32) {
+        self.checks.lock().await.insert(slurm_job_id, check.clone());
+
+        let request = CheckRunRequest {
+            external_id: Some(slurm_job_id.to_string()),
+            output: Some(CheckRunOutput {
+                title: format!("Slurm job {}", slurm_job_id),
+                summary: format!(
+                    "Queued as Slurm job {} on partition `{}`.",
+                    slurm_job_id, check.partition
+                ),
+            }),
+            ..Default::default()
+        };
+        self.update(check, &request).await;
+    }
+
+    /// Reports the Slurm job of a check run created before the worker restarted.
+    pub async fn resume(&self, check: JobCheck, slurm_job_id: u32) {
+        self.checks.lock().await.insert(slurm_job_id, check);
+    }
+
+    pub async fn in_progress(&self, check: &JobCheck) {
+        let request = CheckRunRequest {
+            status: Some(CheckRunStatus::InProgress),
+            started_at: Some(Utc::now()),
+            ..Default::default()
+        };
+        self.update(check, &request).await;
+    }
+
+    pub async fn completed(
+        &self,
+        check: &JobCheck,
+        slurm_job_id: u32,
+        slurm_state: &str,
+        exit_code: Option<i64>,
+        conclusion: CheckRunConclusion,
+    ) {
+        let summary = if slurm_state == UNKNOWN_STATE {
+            format!(
+                "Neither slurmctld nor slurmdbd knows Slurm job {} on partition `{}` any more, so how it ended is unknown.",
+                slurm_job_id, check.partition
+            )
+        } else {
+            let exit_code = exit_code
+                .map(|exit_code| format!(" and exit code {}", exit_code))
+                .unwrap_or_default();
+            format!(
+                "Slurm job {} on partition `{}` finished with state `{}`{}.",
+                slurm_job_id, check.partition, slurm_state, exit_code
+            )
+        };
+        let request = CheckRunRequest {
+            status: Some(CheckRunStatus::Completed),
+            conclusion: Some(conclusion),
+            completed_at: Some(Utc::now()),
+            output: Some(CheckRunOutput {
+                title: format!("Slurm job {} {}", slurm_job_id, slurm_state),
+                summary,
+            }),
+            ..Default::default()
+        };
+        self.update(check, &request).await;
+    }
+
+    pub async fn submission_failed(&self, check: &JobCheck, error: &str) {
+        let request = CheckRunRequest {
+            status: Some(CheckRunStatus::Completed),
+            conclusion: Some(CheckRunConclusion::Failure),
+            completed_at: Some(Utc::now()),
+            output: Some(CheckRunOutput {
+                title: "Slurm submission failed".to_string(),
+                summary: format!(
+                    "Submitting to partition `{}` failed:\n\n```\n{}\n```",
+                    check.partition, error
+                ),
+            }),
+            ..Default::default()
+        };
+        self.update(check, &request).await;
+    }
+
+    /// Follows the job tracker, moving the check run of every submitted job along as its
+    /// Slurm state changes.
+    pub fn spawn(&self, mut transitions: broadcast::Receiver<JobTransition>) {
+        let reporter = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let transition = match transitions.recv().await {
+                    Ok(transition) => transition,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        eprintln!("Check reporter missed {} job transitions", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                reporter.transition(&transition).await;
+            }
+        });
+    }
+
+    async fn transition(&self, transition: &JobTransition) {
+        let slurm_job_id = transition.job.slurm_job_id;
+        let check = match self.checks.lock().await.get(&slurm_job_id) {
+            Some(check) => check.clone(),
+            None => return,
+        };
+
+        let conclusion = match transition.status {
+            RunStatus::WaitingForApproval | RunStatus::Queued => return,
+            RunStatus::InProgress => {
+                self.in_progress(&check).await;
+                return;
+            }
+            RunStatus::Success => CheckRunConclusion::Success,
+            RunStatus::Failure => CheckRunConclusion::Failure,
+            RunStatus::Cancelled => CheckRunConclusion::Cancelled,
+            RunStatus::TimedOut => CheckRunConclusion::TimedOut,
+        };
+
+        self.completed(
+            &check,
+            slurm_job_id,
+            &transition.slurm_state,
+            transition.exit_code,
+            conclusion,
+        )
+        .await;
+        self.checks.lock().await.remove(&slurm_job_id);
+    }
+
+    async fn update(&self, check: &JobCheck, request: &CheckRunRequest) {
+        let Some(app) = self.github.app() else {
+            return;
+        };
+
+        if let Err(err) = app
+            .update_check_run(
+                check.installation_id,
+                &check.repository,
+                check.check_run_id,
+                request,
+            )
+            .await
+        {
+            eprintln!("Failed to update check run {}: {}", check.check_run_id, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::{Value, json};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::github::mock;
+    use crate::tracker::TrackedJob;
+
+    async fn github() -> (MockServer, ChecksReporter) {
+        let server = MockServer::start().await;
+        mock::mount_installation_tokens(&server).await;
+        Mock::given(method("POST"))
+            .and(path("/repos/octo/repo/check-runs"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(json!({ "id": 77 })))
+            .mount(&server)
+            .await;
+        Mock::given(method("PATCH"))
+            .and(path("/repos/octo/repo/check-runs/77"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": 77 })))
+            .mount(&server)
+            .await;
+
+        let reporter = ChecksReporter::new(Arc::new(GithubAuth::App(mock::app_client(&server))));
+        (server, reporter)
+    }
+
+    // bodies of the check run requests, in order
+    async fn check_run_requests(server: &MockServer) -> Vec<(String, Value)> {
+        server
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|request| request.url.path().contains("/check-runs"))
+            .map(|request| {
+                (
+                    request.method.to_string(),
+                    serde_json::from_slice(&request.body).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn transition(slurm_state: &str, exit_code: Option<i64>) -> JobTransition {
+        JobTransition {
+            job: TrackedJob {
+                job_id: 1,
+                slurm_job_id: 4242,
+                repository: "octo/repo".to_string(),
+                workflow: "CI".to_string(),
+                job_name: "build".to_string(),
+                partition: "debug".to_string(),
+            },
+            slurm_state: slurm_state.to_string(),
+            exit_code,
+            previous: None,
+            status: RunStatus::from_slurm_state(slurm_state),
+        }
+    }
+
+    async fn submit(reporter: &ChecksReporter) -> JobCheck {
+        let check = reporter
+            .queued(Some(1), "octo/repo", "abc123", "CI / build", "debug")
+            .await
+            .unwrap();
+        reporter.submitted(&check, 4242).await;
+        check
+    }
+
+    #[tokio::test]
+    async fn a_successful_job_goes_from_queued_to_completed() {
+        let (server, reporter) = github().await;
+
+        let check = submit(&reporter).await;
+        assert_eq!(check.check_run_id, 77);
+        reporter.transition(&transition("RUNNING", None)).await;
+        reporter.transition(&transition("COMPLETED", Some(0))).await;
+
+        let requests = check_run_requests(&server).await;
+        assert_eq!(requests.len(), 4);
+
+        let (method, created) = &requests[0];
+        assert_eq!(method, "POST");
+        assert_eq!(created["name"], "CI / build");
+        assert_eq!(created["head_sha"], "abc123");
+        assert_eq!(created["status"], "queued");
+
+        let (method, submitted) = &requests[1];
+        assert_eq!(method, "PATCH");
+        assert_eq!(submitted["external_id"], "4242");
+        assert!(submitted.get("status").is_none());
+
+        assert_eq!(requests[2].1["status"], "in_progress");
+        assert!(requests[2].1["started_at"].is_string());
+
+        let completed = &requests[3].1;
+        assert_eq!(completed["status"], "completed");
+        assert_eq!(completed["conclusion"], "success");
+        assert!(
+            completed["output"]["summary"]
+                .as_str()
+                .unwrap()
+                .contains("`COMPLETED` and exit code 0")
+        );
+    }
+
+    #[tokio::test]
+    async fn a_failed_job_concludes_with_failure() {
+        let (server, reporter) = github().await;
+
+        submit(&reporter).await;
+        reporter.transition(&transition("FAILED", Some(2))).await;
+        // the check run is done with, later transitions of the job are ignored
+        reporter.transition(&transition("COMPLETED", Some(0))).await;
+
+        let requests = check_run_requests(&server).await;
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[2].1["status"], "completed");
+        assert_eq!(requests[2].1["conclusion"], "failure");
+    }
+
+    #[tokio::test]
+    async fn a_rejected_submission_fails_the_check_run() {
+        let (server, reporter) = github().await;
+
+        let check = reporter
+            .queued(Some(1), "octo/repo", "abc123", "CI / build", "debug")
+            .await
+            .unwrap();
+        reporter
+            .submission_failed(&check, "invalid partition specified")
+            .await;
+
+        let requests = check_run_requests(&server).await;
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].1["conclusion"], "failure");
+        assert!(
+            requests[1].1["output"]["summary"]
+                .as_str()
+                .unwrap()
+                .contains("invalid partition specified")
+        );
+    }
+
+    #[tokio::test]
+    async fn nothing_is_reported_without_an_installation() {
+        let (server, reporter) = github().await;
+
+        assert!(
+            reporter
+                .queued(None, "octo/repo", "abc123", "CI / build", "debug")
+                .await
+                .is_none()
+        );
+        assert!(check_run_requests(&server).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn github_errors_do_not_fail_the_job() {
+        let server = MockServer::start().await;
+        mock::mount_installation_tokens(&server).await;
+        Mock::given(method("POST"))
+            .and(path("/repos/octo/repo/check-runs"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+        let reporter = ChecksReporter::new(Arc::new(GithubAuth::App(mock::app_client(&server))));
+
+        assert!(
+            reporter
+                .queued(Some(1), "octo/repo", "abc123", "CI / build", "debug")
+                .await
+                .is_none()
+        );
+    }
+}
@@ -1,17 +1,84 @@
 use config::{self, Config};
+use rabbitmq_stream_client::types::OffsetSpecification;
 use serde::Deserialize;
 
+use crate::backend::BackendKind;
+
 #[derive(Debug, Deserialize)]
 pub struct AppConfig {
     #[serde(default = "default_rabbitmq_host")]
     pub rabbitmq_host: String,
     #[serde(default = "default_rabbitmq_port")]
     pub rabbitmq_port: u16,
-    pub github_token: String,
-    pub slurmrestd_host: String,
+    // used when the worker isn't configured as a github app
+    pub github_token: Option<String>,
+    pub github_app_id: Option<u64>,
+    // path to the app's PEM encoded private key
+    pub github_app_private_key: Option<String>,
+    #[serde(default = "default_github_api_url")]
+    pub github_api_url: String,
+    #[serde(default = "default_submission_backend")]
+    pub submission_backend: BackendKind,
+    // only needed by the slurmrestd backend
+    pub slurmrestd_host: Option<String>,
+    #[serde(default = "default_slurmrestd_port")]
     pub slurmrestd_port: u16,
-    pub slurmrestd_user: String,
-    pub slurmrestd_token: String,
+    pub slurmrestd_user: Option<String>,
+    // path to the cluster's HS256 jwt_key, tokens are signed with it when set
+    pub slurmrestd_jwt_key: Option<String>,
+    // v0.0.39 to v0.0.42, or "auto" to use the newest one slurmrestd serves
+    #[serde(default = "default_slurmrestd_api_version")]
+    pub slurmrestd_api_version: String,
+    #[serde(default = "default_slurmrestd_token_lifespan_secs")]
+    pub slurmrestd_token_lifespan_secs: u64,
+    // static token from `scontrol token`, used when no jwt_key is configured
+    pub slurmrestd_token: Option<String>,
+    #[serde(default = "default_slurm_submit_max_attempts")]
+    pub slurm_submit_max_attempts: u32,
+    #[serde(default = "default_slurm_submit_initial_delay_ms")]
+    pub slurm_submit_initial_delay_ms: u64,
+    #[serde(default = "default_slurm_submit_max_delay_ms")]
+    pub slurm_submit_max_delay_ms: u64,
+    #[serde(default = "default_tracker_poll_interval_secs")]
+    pub tracker_poll_interval_secs: u64,
+    #[serde(default = "default_database_path")]
+    pub database_path: String,
+    // where a consumer without a stored offset starts reading the stream
+    #[serde(default = "default_consumer_start_offset")]
+    pub consumer_start_offset: StartOffset,
+    // login node the ssh backend runs the Slurm CLI on
+    pub ssh_host: Option<String>,
+    pub ssh_user: Option<String>,
+    #[serde(default = "default_ssh_port")]
+    pub ssh_port: u16,
+    // path to the private key, ssh's defaults are used when unset
+    pub ssh_key: Option<String>,
+    pub ssh_known_hosts: Option<String>,
+    // job scripts and the srun shim of the executor backend
+    #[serde(default = "default_executor_dir")]
+    pub executor_dir: String,
+    // base64 encoded 32 byte key the secrets are encrypted with
+    pub secrets_master_key: Option<String>,
+    // YAML file of the rules that decide which pull request runs wait for approval
+    pub approval_policy: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StartOffset {
+    First,
+    Last,
+    Next,
+}
+
+impl StartOffset {
+    pub fn specification(&self) -> OffsetSpecification {
+        match self {
+            StartOffset::First => OffsetSpecification::First,
+            StartOffset::Last => OffsetSpecification::Last,
+            StartOffset::Next => OffsetSpecification::Next,
+        }
+    }
 }
 
 fn default_rabbitmq_host() -> String {
@@ -22,15 +89,67 @@ fn default_rabbitmq_port() -> u16 {
     5552
 }
 
+fn default_github_api_url() -> String {
+    "https://api.github.com".to_string()
+}
+
+fn default_submission_backend() -> BackendKind {
+    BackendKind::Slurmrestd
+}
+
+fn default_slurmrestd_port() -> u16 {
+    6820
+}
+
+fn default_slurmrestd_api_version() -> String {
+    "auto".to_string()
+}
+
+fn default_slurmrestd_token_lifespan_secs() -> u64 {
+    1_800
+}
+
+fn default_slurm_submit_max_attempts() -> u32 {
+    5
+}
+
+fn default_slurm_submit_initial_delay_ms() -> u64 {
+    1_000
+}
+
+fn default_slurm_submit_max_delay_ms() -> u64 {
+    30_000
+}
+
+fn default_tracker_poll_interval_secs() -> u64 {
+    15
+}
+
+fn default_database_path() -> String {
+    "slurm-actions.db".to_string()
+}
+
+fn default_consumer_start_offset() -> StartOffset {
+    StartOffset::First
+}
+
+fn default_ssh_port() -> u16 {
+    22
+}
+
+fn default_executor_dir() -> String {
+    "slurm-actions-jobs".to_string()
+}
+
 impl AppConfig {
     pub fn new() -> Result<Self, lib::errors::AppError> {
         let settings = Config::builder()
             .add_source(config::Environment::with_prefix("GHWEBHOOKS_RMQ_CONSUMER"))
             .build()
-            .map_err(|e| lib::errors::AppError::ConfigError(e))?;
+            .map_err(lib::errors::AppError::ConfigError)?;
 
         settings
             .try_deserialize::<Self>()
-            .map_err(|e| lib::errors::AppError::ConfigError(e))
+            .map_err(lib::errors::AppError::ConfigError)
     }
 }
@@ -0,0 +1,58 @@
+use lib::types::{
+    dispatch::DispatchRequest,
+    envelope::QueueEnvelope,
+    githubevent::Installation,
+    webhookevent::{User, WebhookEvent, WorkflowDispatchEvent},
+};
+
+use crate::dlq::{Failure, FailureStage};
+use crate::worker::{Worker, run_webhook_event};
+
+/// Runs a `workflow_dispatch` the api published, as if GitHub had sent it.
+pub async fn run_dispatch(worker: &Worker, envelope: &QueueEnvelope) -> Result<(), Failure> {
+    let request: DispatchRequest = match serde_json::from_value(envelope.payload.clone()) {
+        Ok(request) => request,
+        Err(err) => return Err(Failure::new(FailureStage::Parse, err)),
+    };
+
+    let installation_id = match worker.github_auth.app() {
+        Some(app) => match app.repository_installation(&request.repository).await {
+            Ok(installation_id) => Some(installation_id),
+            Err(err) => return Err(Failure::new(FailureStage::Auth, err)),
+        },
+        None => None,
+    };
+    let github_token = match worker.github_auth.token(installation_id).await {
+        Ok(github_token) => github_token,
+        Err(err) => return Err(Failure::new(FailureStage::Auth, err)),
+    };
+    let repository = match worker
+        .github_api
+        .repository(&github_token, &request.repository)
+        .await
+    {
+        Ok(repository) => repository,
+        Err(err) => return Err(Failure::new(FailureStage::Checkout, err)),
+    };
+
+    let webhook_event = WebhookEvent::WorkflowDispatch(WorkflowDispatchEvent {
+        inputs: Some(request.inputs),
+        ref_: request.git_ref,
+        repository,
+        installation: installation_id.map(|id| Installation {
+            id,
+            node_id: String::new(),
+        }),
+        workflow: request.workflow,
+        sender: User {
+            login: request.sender,
+            id: 0,
+            type_: None,
+        },
+    });
+    let envelope = QueueEnvelope {
+        installation_id,
+        ..envelope.clone()
+    };
+    run_webhook_event(worker, &envelope, &webhook_event).await
+}
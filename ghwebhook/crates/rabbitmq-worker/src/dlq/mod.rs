@@ -0,0 +1,441 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use chrono::DateTime;
+use futures_util::stream::StreamExt;
+use lib::errors::AppError;
+use lib::types::envelope::unix_timestamp;
+use rabbitmq_stream_client::types::{Message, OffsetSpecification};
+use rabbitmq_stream_client::{Environment, NoDedup, Producer};
+
+use crate::services::{DEAD_LETTER_STREAM, STREAM, create_rabbitmq_producer, message_property};
+
+// message properties the worker reads the event name and delivery id of legacy messages from
+const FORWARDED_PROPERTIES: &[&str] = &["x-github-event", "x-github-delivery"];
+
+const STAGE_PROPERTY: &str = "x-dlq-stage";
+const ERROR_PROPERTY: &str = "x-dlq-error";
+const ATTEMPTS_PROPERTY: &str = "x-dlq-attempts";
+const FAILED_AT_PROPERTY: &str = "x-dlq-failed-at";
+
+// reading the dead-letter stream stops once no message arrived for this long
+const READ_IDLE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The step of handling a message that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureStage {
+    Decode,
+    Parse,
+    Store,
+    Auth,
+    Checkout,
+    Workflows,
+}
+
+impl fmt::Display for FailureStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FailureStage::Decode => "decode",
+            FailureStage::Parse => "parse",
+            FailureStage::Store => "store",
+            FailureStage::Auth => "auth",
+            FailureStage::Checkout => "checkout",
+            FailureStage::Workflows => "workflows",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Failure {
+    pub stage: FailureStage,
+    pub error: String,
+}
+
+impl Failure {
+    pub fn new(stage: FailureStage, error: impl fmt::Display) -> Self {
+        Self {
+            stage,
+            error: error.to_string(),
+        }
+    }
+}
+
+// the application properties the dead-letter stream reads and writes, by name
+type Properties = BTreeMap<&'static str, String>;
+
+fn read_properties(message: &Message) -> Properties {
+    FORWARDED_PROPERTIES
+        .iter()
+        .chain(&[
+            STAGE_PROPERTY,
+            ERROR_PROPERTY,
+            ATTEMPTS_PROPERTY,
+            FAILED_AT_PROPERTY,
+        ])
+        .filter_map(|key| Some((*key, message_property(message, key)?)))
+        .collect()
+}
+
+fn forwarded_properties(properties: &Properties) -> Properties {
+    properties
+        .iter()
+        .filter(|(key, _)| FORWARDED_PROPERTIES.contains(key))
+        .map(|(key, value)| (*key, value.clone()))
+        .collect()
+}
+
+// number of times the message has been dead-lettered before, carried over by replays
+fn attempts(properties: &Properties) -> u32 {
+    properties
+        .get(ATTEMPTS_PROPERTY)
+        .and_then(|attempts| attempts.parse().ok())
+        .unwrap_or_default()
+}
+
+/// Properties of the dead letter of a message with `properties` that failed at `failed_at`.
+fn dead_letter_properties(
+    properties: &Properties,
+    failure: &Failure,
+    failed_at: u64,
+) -> Properties {
+    let mut dead_letter = forwarded_properties(properties);
+    dead_letter.insert(STAGE_PROPERTY, failure.stage.to_string());
+    dead_letter.insert(ERROR_PROPERTY, failure.error.clone());
+    dead_letter.insert(ATTEMPTS_PROPERTY, (attempts(properties) + 1).to_string());
+    dead_letter.insert(FAILED_AT_PROPERTY, failed_at.to_string());
+    dead_letter
+}
+
+/// Properties of a dead letter replayed to the main stream. The attempt count travels with
+/// it, so failing again dead-letters it with the next attempt number.
+fn replay_properties(properties: &Properties) -> Properties {
+    let mut replayed = forwarded_properties(properties);
+    replayed.insert(ATTEMPTS_PROPERTY, attempts(properties).to_string());
+    replayed
+}
+
+/// A message on the dead-letter stream. The body is the original message body, the failure
+/// is described in application properties.
+pub struct DeadLetter {
+    pub offset: u64,
+    pub body: Vec<u8>,
+    pub stage: String,
+    pub error: String,
+    pub attempts: u32,
+    pub failed_at: u64,
+    pub event: Option<String>,
+    pub delivery_id: Option<String>,
+}
+
+impl DeadLetter {
+    fn decode(offset: u64, body: Vec<u8>, properties: &Properties) -> Self {
+        // the envelope carries the event name and delivery id, legacy messages only have
+        // them in properties
+        let envelope: Option<serde_json::Value> = serde_json::from_slice(&body).ok();
+        let envelope_field = |field: &str| {
+            envelope
+                .as_ref()
+                .and_then(|envelope| envelope.get(field))
+                .and_then(serde_json::Value::as_str)
+                .map(str::to_string)
+        };
+        let property = |key: &str| properties.get(key).cloned();
+
+        Self {
+            offset,
+            stage: property(STAGE_PROPERTY).unwrap_or_default(),
+            error: property(ERROR_PROPERTY).unwrap_or_default(),
+            attempts: attempts(properties),
+            failed_at: property(FAILED_AT_PROPERTY)
+                .and_then(|failed_at| failed_at.parse().ok())
+                .unwrap_or_default(),
+            event: envelope_field("event").or_else(|| property("x-github-event")),
+            delivery_id: envelope_field("delivery_id").or_else(|| property("x-github-delivery")),
+            body,
+        }
+    }
+
+    fn from_message(offset: u64, message: &Message) -> Self {
+        let body = message.data().map(<[u8]>::to_vec).unwrap_or_default();
+        Self::decode(offset, body, &read_properties(message))
+    }
+}
+
+/// Publishes messages the worker failed to handle to the dead-letter stream.
+pub struct DeadLetterPublisher {
+    producer: Producer<NoDedup>,
+}
+
+impl DeadLetterPublisher {
+    pub async fn new(environment: &Environment) -> Result<Self, AppError> {
+        Ok(Self {
+            producer: create_rabbitmq_producer(environment, DEAD_LETTER_STREAM).await?,
+        })
+    }
+
+    pub async fn publish(&self, message: &Message, failure: &Failure) -> Result<(), AppError> {
+        let properties =
+            dead_letter_properties(&read_properties(message), failure, unix_timestamp());
+        send(&self.producer, build_message(message.data(), properties)).await
+    }
+}
+
+fn build_message(body: Option<&[u8]>, properties: Properties) -> Message {
+    let mut builder = Message::builder().application_properties();
+    for (key, value) in properties {
+        builder = builder.insert(key, value.as_str());
+    }
+
+    builder
+        .message_builder()
+        .body(body.map(<[u8]>::to_vec).unwrap_or_default())
+        .build()
+}
+
+async fn send(producer: &Producer<NoDedup>, message: Message) -> Result<(), AppError> {
+    let status = producer
+        .send_with_confirm(message)
+        .await
+        .map_err(|err| AppError::RabbitMQProducerPublishError(err.to_string()))?;
+
+    if !status.confirmed() {
+        return Err(AppError::RabbitMQProducerPublishError(format!(
+            "message not confirmed: {:?}",
+            status.status()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Reads dead-lettered messages starting at `offset`, stopping after `limit` messages or
+/// once the end of the stream is reached.
+async fn read(
+    environment: &Environment,
+    offset: OffsetSpecification,
+    limit: Option<usize>,
+) -> Result<Vec<(DeadLetter, Message)>, AppError> {
+    let mut consumer = environment
+        .consumer()
+        .offset(offset)
+        .build(DEAD_LETTER_STREAM)
+        .await
+        .map_err(AppError::RabbitMQConsumerCreateError)?;
+
+    let mut dead_letters = Vec::new();
+    while limit.is_none_or(|limit| dead_letters.len() < limit) {
+        let delivery = match tokio::time::timeout(READ_IDLE_TIMEOUT, consumer.next()).await {
+            Ok(Some(delivery)) => {
+                delivery.map_err(|err| AppError::RabbitMQConsumerConsumeError(err.to_string()))?
+            }
+            Ok(None) | Err(_) => break,
+        };
+        dead_letters.push((
+            DeadLetter::from_message(delivery.offset(), delivery.message()),
+            delivery.message().clone(),
+        ));
+    }
+
+    consumer
+        .handle()
+        .close()
+        .await
+        .map_err(AppError::RabbitMQConsumerCloseError)?;
+
+    Ok(dead_letters)
+}
+
+async fn read_one(
+    environment: &Environment,
+    offset: u64,
+) -> Result<(DeadLetter, Message), AppError> {
+    read(environment, OffsetSpecification::Offset(offset), Some(1))
+        .await?
+        .into_iter()
+        .find(|(dead_letter, _)| dead_letter.offset == offset)
+        .ok_or_else(|| {
+            AppError::RabbitMQConsumerConsumeError(format!(
+                "no dead-lettered message at offset {}",
+                offset
+            ))
+        })
+}
+
+pub fn format_timestamp(timestamp: u64) -> String {
+    DateTime::from_timestamp(timestamp as i64, 0)
+        .map(|time| time.to_rfc3339())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+pub async fn list(environment: &Environment) -> Result<(), AppError> {
+    let dead_letters = read(environment, OffsetSpecification::First, None).await?;
+    if dead_letters.is_empty() {
+        println!("No dead-lettered messages");
+        return Ok(());
+    }
+
+    for (dead_letter, _) in dead_letters {
+        println!(
+            "{}\t{}\t{}\tattempt {}\t{} {}\t{}",
+            dead_letter.offset,
+            format_timestamp(dead_letter.failed_at),
+            dead_letter.stage,
+            dead_letter.attempts,
+            dead_letter.event.as_deref().unwrap_or("-"),
+            dead_letter.delivery_id.as_deref().unwrap_or("-"),
+            dead_letter.error.lines().next().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+pub async fn show(environment: &Environment, offset: u64) -> Result<(), AppError> {
+    let (dead_letter, _) = read_one(environment, offset).await?;
+
+    println!("Offset:      {}", dead_letter.offset);
+    println!("Failed at:   {}", format_timestamp(dead_letter.failed_at));
+    println!("Stage:       {}", dead_letter.stage);
+    println!("Attempts:    {}", dead_letter.attempts);
+    println!(
+        "Event:       {}",
+        dead_letter.event.as_deref().unwrap_or("-")
+    );
+    println!(
+        "Delivery:    {}",
+        dead_letter.delivery_id.as_deref().unwrap_or("-")
+    );
+    println!("Error:\n{}\n", dead_letter.error);
+
+    let body = match serde_json::from_slice::<serde_json::Value>(&dead_letter.body) {
+        Ok(body) => serde_json::to_string_pretty(&body)
+            .unwrap_or_else(|_| String::from_utf8_lossy(&dead_letter.body).into_owned()),
+        Err(_) => String::from_utf8_lossy(&dead_letter.body).into_owned(),
+    };
+    println!("Body:\n{}", body);
+    Ok(())
+}
+
+/// Publishes a dead-lettered message back to the main stream.
+pub async fn replay(environment: &Environment, offset: u64) -> Result<(), AppError> {
+    let (dead_letter, message) = read_one(environment, offset).await?;
+    let properties = replay_properties(&read_properties(&message));
+
+    let producer = create_rabbitmq_producer(environment, STREAM).await?;
+    send(&producer, build_message(message.data(), properties)).await?;
+    producer
+        .close()
+        .await
+        .map_err(|err| AppError::RabbitMQProducerPublishError(err.to_string()))?;
+
+    println!(
+        "Replayed dead-lettered message {} (attempt {}) to {}",
+        offset, dead_letter.attempts, STREAM
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(entries: &[(&'static str, &str)]) -> Properties {
+        entries
+            .iter()
+            .map(|(key, value)| (*key, value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn dead_letters_describe_the_failure() {
+        let original = properties(&[("x-github-event", "push"), ("x-github-delivery", "d1")]);
+        let failure = Failure::new(FailureStage::Checkout, "commit not found");
+
+        assert_eq!(
+            dead_letter_properties(&original, &failure, 1700000000),
+            properties(&[
+                ("x-github-event", "push"),
+                ("x-github-delivery", "d1"),
+                (STAGE_PROPERTY, "checkout"),
+                (ERROR_PROPERTY, "commit not found"),
+                (ATTEMPTS_PROPERTY, "1"),
+                (FAILED_AT_PROPERTY, "1700000000"),
+            ])
+        );
+    }
+
+    #[test]
+    fn replayed_messages_fail_with_the_next_attempt() {
+        let first = dead_letter_properties(
+            &Properties::new(),
+            &Failure::new(FailureStage::Auth, "bad credentials"),
+            1700000000,
+        );
+
+        // the replay drops the failure but keeps counting
+        let replayed = replay_properties(&first);
+        assert_eq!(replayed, properties(&[(ATTEMPTS_PROPERTY, "1")]));
+
+        let second = dead_letter_properties(
+            &replayed,
+            &Failure::new(FailureStage::Workflows, "invalid workflow"),
+            1700000100,
+        );
+        let dead_letter = DeadLetter::decode(3, Vec::new(), &second);
+        assert_eq!(dead_letter.attempts, 2);
+        assert_eq!(dead_letter.stage, "workflows");
+        assert_eq!(dead_letter.error, "invalid workflow");
+        assert_eq!(dead_letter.failed_at, 1700000100);
+    }
+
+    #[test]
+    fn envelopes_name_the_event_and_delivery() {
+        let body = serde_json::to_vec(&serde_json::json!({
+            "schema_version": 1,
+            "event": "pull_request",
+            "delivery_id": "d2",
+            "received_at": 0,
+            "installation_id": 42,
+            "payload": {},
+        }))
+        .unwrap();
+        let dead_letter = DeadLetter::decode(
+            7,
+            body.clone(),
+            &properties(&[("x-github-event", "push"), (STAGE_PROPERTY, "parse")]),
+        );
+
+        assert_eq!(dead_letter.offset, 7);
+        assert_eq!(dead_letter.body, body);
+        assert_eq!(dead_letter.event.as_deref(), Some("pull_request"));
+        assert_eq!(dead_letter.delivery_id.as_deref(), Some("d2"));
+        assert_eq!(dead_letter.stage, "parse");
+    }
+
+    #[test]
+    fn legacy_messages_name_them_in_properties() {
+        let dead_letter = DeadLetter::decode(
+            0,
+            br#"{"ref": "refs/heads/main"}"#.to_vec(),
+            &properties(&[("x-github-event", "push"), ("x-github-delivery", "d3")]),
+        );
+        assert_eq!(dead_letter.event.as_deref(), Some("push"));
+        assert_eq!(dead_letter.delivery_id.as_deref(), Some("d3"));
+
+        let dead_letter = DeadLetter::decode(
+            0,
+            b"not json".to_vec(),
+            &properties(&[(ATTEMPTS_PROPERTY, "many"), (FAILED_AT_PROPERTY, "")]),
+        );
+        assert_eq!(dead_letter.event, None);
+        assert_eq!(dead_letter.delivery_id, None);
+        assert_eq!(dead_letter.stage, "");
+        assert_eq!(dead_letter.attempts, 0);
+        assert_eq!(dead_letter.failed_at, 0);
+    }
+
+    #[test]
+    fn timestamps_are_formatted_as_rfc3339() {
+        assert_eq!(format_timestamp(1700000000), "2023-11-14T22:13:20+00:00");
+    }
+}
@@ -0,0 +1,676 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use lib::types::webhookevent::EventRepository;
+
+use crate::config::AppConfig;
+
+// installation tokens are refreshed this long before github expires them
+const TOKEN_REFRESH_MARGIN_SECS: i64 = 300;
+
+/// How the worker authenticates to GitHub, both for cloning and for `GITHUB_TOKEN` in jobs.
+pub enum GithubAuth {
+    App(GithubAppClient),
+    Token(String),
+}
+
+impl GithubAuth {
+    pub fn from_config(
+        config: &AppConfig,
+        http: reqwest::Client,
+    ) -> Result<Self, lib::errors::AppError> {
+        match (&config.github_app_id, &config.github_app_private_key) {
+            (Some(app_id), Some(private_key_path)) => {
+                let private_key = std::fs::read(private_key_path).map_err(|err| {
+                    lib::errors::AppError::GithubAppAuthError(format!(
+                        "failed to read private key {}: {}",
+                        private_key_path, err
+                    ))
+                })?;
+                Ok(GithubAuth::App(GithubAppClient::new(
+                    *app_id,
+                    &private_key,
+                    &config.github_api_url,
+                    http,
+                )?))
+            }
+            _ => match &config.github_token {
+                Some(token) => Ok(GithubAuth::Token(token.clone())),
+                None => Err(lib::errors::AppError::GithubAppAuthError(
+                    "either github_app_id and github_app_private_key or github_token must be set"
+                        .to_string(),
+                )),
+            },
+        }
+    }
+
+    pub fn app(&self) -> Option<&GithubAppClient> {
+        match self {
+            GithubAuth::App(app) => Some(app),
+            GithubAuth::Token(_) => None,
+        }
+    }
+
+    /// A token for the worker's own requests, with every repository of the installation.
+    pub async fn token(
+        &self,
+        installation_id: Option<u64>,
+    ) -> Result<String, lib::errors::AppError> {
+        match self {
+            GithubAuth::App(app) => {
+                app.installation_token(Self::installation(installation_id)?)
+                    .await
+            }
+            GithubAuth::Token(token) => Ok(token.clone()),
+        }
+    }
+
+    /// The `GITHUB_TOKEN` of a job, minted for it alone. A personal access token can't be
+    /// scoped, so jobs that only get read access get no token at all.
+    pub async fn job_token(
+        &self,
+        installation_id: Option<u64>,
+        scope: &TokenScope,
+    ) -> Result<String, lib::errors::AppError> {
+        match (self, scope) {
+            (GithubAuth::App(app), _) => {
+                app.mint_token(Self::installation(installation_id)?, scope)
+                    .await
+            }
+            (GithubAuth::Token(_), TokenScope::ReadOnly(_)) => Ok(String::new()),
+            (GithubAuth::Token(token), _) => Ok(token.clone()),
+        }
+    }
+
+    fn installation(installation_id: Option<u64>) -> Result<u64, lib::errors::AppError> {
+        installation_id.ok_or_else(|| {
+            lib::errors::AppError::GithubAppAuthError("event has no installation id".to_string())
+        })
+    }
+}
+
+/// What an installation access token can reach.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenScope {
+    // every repository and permission of the installation, for the worker's own requests
+    Installation,
+    // one repository, named without its owner, with the installation's permissions
+    Repository(String),
+    // read access to the contents of one repository, for jobs running a fork's code
+    ReadOnly(String),
+}
+
+impl TokenScope {
+    fn request(&self) -> Option<AccessTokenRequest<'_>> {
+        match self {
+            TokenScope::Installation => None,
+            TokenScope::Repository(repository) => Some(AccessTokenRequest {
+                repositories: [repository],
+                permissions: None,
+            }),
+            TokenScope::ReadOnly(repository) => Some(AccessTokenRequest {
+                repositories: [repository],
+                permissions: Some(HashMap::from([("contents", "read")])),
+            }),
+        }
+    }
+}
+
+/// REST calls made with an installation token or the configured personal access token.
+pub struct GithubApi {
+    api_url: String,
+    http: reqwest::Client,
+}
+
+impl GithubApi {
+    pub fn from_config(config: &AppConfig, http: reqwest::Client) -> Self {
+        Self {
+            api_url: config.github_api_url.trim_end_matches('/').to_string(),
+            http,
+        }
+    }
+
+    pub async fn repository(
+        &self,
+        token: &str,
+        repository: &str,
+    ) -> Result<EventRepository, lib::errors::AppError> {
+        self.http
+            .get(format!("{}/repos/{}", self.api_url, repository))
+            .bearer_auth(token)
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", "slurm-actions")
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|err| lib::errors::AppError::GithubApiError(err.to_string()))?
+            .json()
+            .await
+            .map_err(|err| lib::errors::AppError::GithubApiError(err.to_string()))
+    }
+
+    /// The commit pull request `number` of `repository` is at now.
+    pub async fn pull_request_head(
+        &self,
+        token: &str,
+        repository: &str,
+        number: u64,
+    ) -> Result<String, lib::errors::AppError> {
+        let pull_request: PullRequestHead = self
+            .http
+            .get(format!(
+                "{}/repos/{}/pulls/{}",
+                self.api_url, repository, number
+            ))
+            .bearer_auth(token)
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", "slurm-actions")
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|err| lib::errors::AppError::GithubApiError(err.to_string()))?
+            .json()
+            .await
+            .map_err(|err| lib::errors::AppError::GithubApiError(err.to_string()))?;
+        Ok(pull_request.head.sha)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PullRequestHead {
+    head: CommitRef,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommitRef {
+    sha: String,
+}
+
+pub struct GithubAppClient {
+    app_id: u64,
+    encoding_key: EncodingKey,
+    api_url: String,
+    http: reqwest::Client,
+    tokens: Mutex<HashMap<u64, InstallationToken>>,
+}
+
+#[derive(Debug, Serialize)]
+struct AccessTokenRequest<'a> {
+    repositories: [&'a str; 1],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    permissions: Option<HashMap<&'static str, &'static str>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RepositoryInstallation {
+    id: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct InstallationToken {
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckRunStatus {
+    Queued,
+    InProgress,
+    Completed,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckRunConclusion {
+    Success,
+    Failure,
+    Cancelled,
+    TimedOut,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckRunOutput {
+    pub title: String,
+    pub summary: String,
+}
+
+/// Body of the create and update check run endpoints, unset fields are left unchanged.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CheckRunRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub head_sha: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<CheckRunStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conclusion: Option<CheckRunConclusion>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<CheckRunOutput>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct CheckRunResponse {
+    id: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct AppClaims {
+    iat: i64,
+    exp: i64,
+    iss: String,
+}
+
+impl GithubAppClient {
+    pub fn new(
+        app_id: u64,
+        private_key_pem: &[u8],
+        api_url: &str,
+        http: reqwest::Client,
+    ) -> Result<Self, lib::errors::AppError> {
+        let encoding_key = EncodingKey::from_rsa_pem(private_key_pem)
+            .map_err(|err| lib::errors::AppError::GithubAppAuthError(err.to_string()))?;
+
+        Ok(Self {
+            app_id,
+            encoding_key,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            http,
+            tokens: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Signs a short lived JWT that authenticates as the app itself.
+    pub fn app_jwt(&self) -> Result<String, lib::errors::AppError> {
+        let now = Utc::now().timestamp();
+        let claims = AppClaims {
+            // backdated to allow for clock drift, github rejects tokens issued in the future
+            iat: now - 60,
+            exp: now + 540,
+            iss: self.app_id.to_string(),
+        };
+
+        jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &self.encoding_key)
+            .map_err(|err| lib::errors::AppError::GithubAppAuthError(err.to_string()))
+    }
+
+    /// Returns a cached access token to every repository of the installation, minting a
+    /// new one when the cached token is missing or about to expire.
+    pub async fn installation_token(
+        &self,
+        installation_id: u64,
+    ) -> Result<String, lib::errors::AppError> {
+        if let Some(cached) = self.tokens.lock().await.get(&installation_id)
+            && cached.expires_at - Duration::seconds(TOKEN_REFRESH_MARGIN_SECS) > Utc::now()
+        {
+            return Ok(cached.token.clone());
+        }
+
+        // not locked while minting, concurrent misses mint a token each
+        let token = self
+            .request_token(installation_id, &TokenScope::Installation)
+            .await?;
+        self.tokens
+            .lock()
+            .await
+            .insert(installation_id, token.clone());
+
+        Ok(token.token)
+    }
+
+    /// Mints a new access token for `scope`, it isn't cached.
+    pub async fn mint_token(
+        &self,
+        installation_id: u64,
+        scope: &TokenScope,
+    ) -> Result<String, lib::errors::AppError> {
+        Ok(self.request_token(installation_id, scope).await?.token)
+    }
+
+    async fn request_token(
+        &self,
+        installation_id: u64,
+        scope: &TokenScope,
+    ) -> Result<InstallationToken, lib::errors::AppError> {
+        let mut request = self
+            .http
+            .post(format!(
+                "{}/app/installations/{}/access_tokens",
+                self.api_url, installation_id
+            ))
+            .bearer_auth(self.app_jwt()?)
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", "slurm-actions");
+        if let Some(body) = scope.request() {
+            request = request.json(&body);
+        }
+        request
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|err| lib::errors::AppError::GithubApiError(err.to_string()))?
+            .json()
+            .await
+            .map_err(|err| lib::errors::AppError::GithubApiError(err.to_string()))
+    }
+
+    /// The installation of the app on `repository`, for events that don't come from GitHub.
+    pub async fn repository_installation(
+        &self,
+        repository: &str,
+    ) -> Result<u64, lib::errors::AppError> {
+        let installation: RepositoryInstallation = self
+            .http
+            .get(format!(
+                "{}/repos/{}/installation",
+                self.api_url, repository
+            ))
+            .bearer_auth(self.app_jwt()?)
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", "slurm-actions")
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|err| lib::errors::AppError::GithubApiError(err.to_string()))?
+            .json()
+            .await
+            .map_err(|err| lib::errors::AppError::GithubApiError(err.to_string()))?;
+
+        Ok(installation.id)
+    }
+
+    pub async fn create_check_run(
+        &self,
+        installation_id: u64,
+        repository: &str,
+        request: &CheckRunRequest,
+    ) -> Result<u64, lib::errors::AppError> {
+        let token = self.installation_token(installation_id).await?;
+
+        let response = self
+            .http
+            .post(format!("{}/repos/{}/check-runs", self.api_url, repository))
+            .bearer_auth(token)
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", "slurm-actions")
+            .json(request)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|err| lib::errors::AppError::GithubApiError(err.to_string()))?;
+
+        let check_run: CheckRunResponse = response
+            .json()
+            .await
+            .map_err(|err| lib::errors::AppError::GithubApiError(err.to_string()))?;
+
+        Ok(check_run.id)
+    }
+
+    pub async fn update_check_run(
+        &self,
+        installation_id: u64,
+        repository: &str,
+        check_run_id: u64,
+        request: &CheckRunRequest,
+    ) -> Result<(), lib::errors::AppError> {
+        let token = self.installation_token(installation_id).await?;
+
+        self.http
+            .patch(format!(
+                "{}/repos/{}/check-runs/{}",
+                self.api_url, repository, check_run_id
+            ))
+            .bearer_auth(token)
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", "slurm-actions")
+            .json(request)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|err| lib::errors::AppError::GithubApiError(err.to_string()))?;
+
+        Ok(())
+    }
+}
+
+/// A GitHub App pointed at a wiremock stand-in for GitHub.
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+
+    use wiremock::matchers::{method, path_regex};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    pub const PRIVATE_KEY: &[u8] = include_bytes!("../../tests/fixtures/github-app.pem");
+    pub const PUBLIC_KEY: &[u8] = include_bytes!("../../tests/fixtures/github-app.pub.pem");
+
+    pub fn app_client(server: &MockServer) -> GithubAppClient {
+        GithubAppClient::new(1234, PRIVATE_KEY, &server.uri(), reqwest::Client::new()).unwrap()
+    }
+
+    pub fn token_response(token: &str, expires_in: Duration) -> ResponseTemplate {
+        ResponseTemplate::new(201).set_body_json(serde_json::json!({
+            "token": token,
+            "expires_at": Utc::now() + expires_in,
+        }))
+    }
+
+    /// Answers every installation token request with `ghs_test`.
+    pub async fn mount_installation_tokens(server: &MockServer) {
+        Mock::given(method("POST"))
+            .and(path_regex(r"^/app/installations/\d+/access_tokens$"))
+            .respond_with(token_response("ghs_test", Duration::hours(1)))
+            .mount(server)
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::{PUBLIC_KEY, app_client, token_response};
+    use super::*;
+
+    use jsonwebtoken::{DecodingKey, Validation};
+    use wiremock::matchers::{body_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn installation_token_is_minted_with_the_app_jwt_and_cached() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/app/installations/42/access_tokens"))
+            .and(header("Accept", "application/vnd.github+json"))
+            .respond_with(token_response("ghs_first", Duration::hours(1)))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let app = app_client(&server);
+        assert_eq!(app.installation_token(42).await.unwrap(), "ghs_first");
+        assert_eq!(app.installation_token(42).await.unwrap(), "ghs_first");
+
+        let requests = server.received_requests().await.unwrap();
+        let authorization = requests[0].headers["authorization"].to_str().unwrap();
+        let jwt = authorization.strip_prefix("Bearer ").unwrap();
+        let claims = jsonwebtoken::decode::<serde_json::Value>(
+            jwt,
+            &DecodingKey::from_rsa_pem(PUBLIC_KEY).unwrap(),
+            &Validation::new(Algorithm::RS256),
+        )
+        .unwrap()
+        .claims;
+        assert_eq!(claims["iss"], "1234");
+        assert!(claims["iat"].as_i64().unwrap() < Utc::now().timestamp());
+    }
+
+    #[tokio::test]
+    async fn tokens_about_to_expire_are_replaced() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/app/installations/42/access_tokens"))
+            .respond_with(token_response("ghs_short", Duration::seconds(60)))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let app = app_client(&server);
+        app.installation_token(42).await.unwrap();
+        app.installation_token(42).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn tokens_are_cached_per_installation() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/app/installations/1/access_tokens"))
+            .respond_with(token_response("ghs_one", Duration::hours(1)))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/app/installations/2/access_tokens"))
+            .respond_with(token_response("ghs_two", Duration::hours(1)))
+            .mount(&server)
+            .await;
+
+        let app = app_client(&server);
+        assert_eq!(app.installation_token(1).await.unwrap(), "ghs_one");
+        assert_eq!(app.installation_token(2).await.unwrap(), "ghs_two");
+    }
+
+    #[tokio::test]
+    async fn rejected_token_requests_are_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/app/installations/42/access_tokens"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&server)
+            .await;
+
+        let err = app_client(&server)
+            .installation_token(42)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, lib::errors::AppError::GithubApiError(_)));
+    }
+
+    #[tokio::test]
+    async fn pull_request_heads_are_read_from_the_api() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/repos/octo/repo/pulls/7"))
+            .and(header("Authorization", "Bearer ghs_test"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "number": 7,
+                "head": {"ref": "feature", "sha": "e5bd3914e2e596debea16f433f57875b5b90bcd6"},
+            })))
+            .mount(&server)
+            .await;
+
+        let api = GithubApi {
+            api_url: server.uri(),
+            http: reqwest::Client::new(),
+        };
+        assert_eq!(
+            api.pull_request_head("ghs_test", "octo/repo", 7)
+                .await
+                .unwrap(),
+            "e5bd3914e2e596debea16f433f57875b5b90bcd6"
+        );
+        assert!(matches!(
+            api.pull_request_head("ghs_test", "octo/repo", 8).await,
+            Err(lib::errors::AppError::GithubApiError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn job_tokens_are_scoped_to_the_repository() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/app/installations/42/access_tokens"))
+            .and(body_json(serde_json::json!({"repositories": ["repo"]})))
+            .respond_with(token_response("ghs_repo", Duration::hours(1)))
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/app/installations/42/access_tokens"))
+            .and(body_json(serde_json::json!({
+                "repositories": ["repo"],
+                "permissions": {"contents": "read"},
+            })))
+            .respond_with(token_response("ghs_read", Duration::hours(1)))
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/app/installations/42/access_tokens"))
+            .and(body_json(serde_json::json!({"repositories": ["other"]})))
+            .respond_with(token_response("ghs_other", Duration::hours(1)))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let auth = GithubAuth::App(app_client(&server));
+        let repository = TokenScope::Repository("repo".to_string());
+        let read_only = TokenScope::ReadOnly("repo".to_string());
+        let other = TokenScope::Repository("other".to_string());
+
+        // every job gets a token of its own
+        for _ in 0..2 {
+            assert_eq!(
+                auth.job_token(Some(42), &repository).await.unwrap(),
+                "ghs_repo"
+            );
+            assert_eq!(
+                auth.job_token(Some(42), &read_only).await.unwrap(),
+                "ghs_read"
+            );
+            assert_eq!(auth.job_token(Some(42), &other).await.unwrap(), "ghs_other");
+        }
+    }
+
+    #[tokio::test]
+    async fn installation_tokens_are_not_scoped() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/app/installations/42/access_tokens"))
+            .respond_with(token_response("ghs_all", Duration::hours(1)))
+            .mount(&server)
+            .await;
+
+        let auth = GithubAuth::App(app_client(&server));
+        assert_eq!(auth.token(Some(42)).await.unwrap(), "ghs_all");
+
+        let requests = server.received_requests().await.unwrap();
+        assert!(requests[0].body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn personal_access_tokens_are_not_given_to_read_only_jobs() {
+        let auth = GithubAuth::Token("ghp_secret".to_string());
+        assert_eq!(
+            auth.job_token(None, &TokenScope::Repository("repo".to_string()))
+                .await
+                .unwrap(),
+            "ghp_secret"
+        );
+        assert_eq!(
+            auth.job_token(None, &TokenScope::ReadOnly("repo".to_string()))
+                .await
+                .unwrap(),
+            ""
+        );
+    }
+}
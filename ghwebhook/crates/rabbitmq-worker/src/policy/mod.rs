@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+
+use lib::errors::AppError;
+use lib::types::workflowevent::WorkflowEvent;
+use serde::Deserialize;
+
+use crate::config::AppConfig;
+
+/// When pull request runs wait for a maintainer's approval.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RequireApproval {
+    /// Runs of pull requests from untrusted authors or forks.
+    #[default]
+    Untrusted,
+    Always,
+    Never,
+}
+
+/// The approval rules of a repository. Author associations are GitHub's, e.g. `MEMBER` or
+/// `FIRST_TIME_CONTRIBUTOR`, and users are logins.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ApprovalRules {
+    pub require_approval: RequireApproval,
+    // authors whose pull requests run without approval
+    pub trusted_associations: Vec<String>,
+    pub trusted_users: Vec<String>,
+    // whether pull requests of trusted authors from forks run without approval
+    pub trust_forks: bool,
+    // who can approve with a `/approve` comment
+    pub approver_associations: Vec<String>,
+    pub approvers: Vec<String>,
+}
+
+impl Default for ApprovalRules {
+    fn default() -> Self {
+        Self {
+            require_approval: RequireApproval::default(),
+            trusted_associations: associations(&["OWNER", "MEMBER", "COLLABORATOR"]),
+            trusted_users: Vec::new(),
+            trust_forks: false,
+            // collaborators may only have triage access
+            approver_associations: associations(&["OWNER", "MEMBER"]),
+            approvers: Vec::new(),
+        }
+    }
+}
+
+fn associations(associations: &[&str]) -> Vec<String> {
+    associations
+        .iter()
+        .map(|association| association.to_string())
+        .collect()
+}
+
+/// Rules for every repository, with overrides keyed by `owner/name`. A repository's rules
+/// replace the default ones as a whole, fields it doesn't set take the built-in defaults.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ApprovalPolicy {
+    #[serde(rename = "default")]
+    pub default_rules: ApprovalRules,
+    pub repositories: HashMap<String, ApprovalRules>,
+}
+
+impl ApprovalPolicy {
+    /// Reads the YAML file `approval_policy` points to, or the built-in rules without one.
+    pub fn from_config(config: &AppConfig) -> Result<Self, AppError> {
+        let Some(path) = &config.approval_policy else {
+            return Ok(Self::default());
+        };
+
+        let policy_error = |err: String| {
+            AppError::ConfigError(config::ConfigError::Message(format!(
+                "approval policy {}: {}",
+                path, err
+            )))
+        };
+        let contents =
+            std::fs::read_to_string(path).map_err(|err| policy_error(err.to_string()))?;
+        serde_yaml::from_str(&contents).map_err(|err| policy_error(err.to_string()))
+    }
+
+    pub fn rules(&self, repository: &str) -> &ApprovalRules {
+        self.repositories
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(repository))
+            .map(|(_, rules)| rules)
+            .unwrap_or(&self.default_rules)
+    }
+
+    /// Why the runs of `event` need approval, or `None` if they can run right away. Pushes
+    /// come from people with write access and never need it.
+    pub fn approval_reason(&self, event: &WorkflowEvent) -> Option<String> {
+        let pull_request = event.pull_request()?;
+        let rules = self.rules(event.repository());
+        let author = &pull_request.pull_request.user.login;
+        let association = pull_request
+            .pull_request
+            .author_association
+            .as_deref()
+            .unwrap_or("NONE");
+
+        match rules.require_approval {
+            RequireApproval::Never => None,
+            RequireApproval::Always => Some("every pull request needs approval".to_string()),
+            RequireApproval::Untrusted => {
+                if !is_listed(&rules.trusted_users, author)
+                    && !is_listed(&rules.trusted_associations, association)
+                {
+                    Some(format!(
+                        "{} is not trusted (author association {})",
+                        author, association
+                    ))
+                } else if event.is_from_fork() && !rules.trust_forks {
+                    Some("the pull request comes from a fork".to_string())
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Whether `login` can approve held runs of `repository`.
+    pub fn can_approve(&self, repository: &str, login: &str, association: Option<&str>) -> bool {
+        let rules = self.rules(repository);
+        is_listed(&rules.approvers, login)
+            || association
+                .is_some_and(|association| is_listed(&rules.approver_associations, association))
+    }
+}
+
+// logins and associations are case-insensitive
+fn is_listed(list: &[String], name: &str) -> bool {
+    list.iter().any(|entry| entry.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use lib::types::webhookevent::{PullRequestEvent, WebhookEvent};
+
+    const PULL_REQUEST: &str =
+        include_str!("../../../lib/tests/fixtures/webhooks/pull_request.json");
+    const PUSH: &str = include_str!("../../../lib/tests/fixtures/webhooks/push.json");
+
+    // the fixture's pull request of `forker`, from a fork unless `from_fork` is false
+    fn pull_request(association: &str, from_fork: bool) -> PullRequestEvent {
+        let mut payload: serde_json::Value = serde_json::from_str(PULL_REQUEST).unwrap();
+        payload["pull_request"]["author_association"] = association.into();
+        if !from_fork {
+            payload["pull_request"]["head"]["repo"]["full_name"] = "octo/repo".into();
+        }
+        match WebhookEvent::from_value("pull_request", payload).unwrap() {
+            WebhookEvent::PullRequest(pull_request) => pull_request,
+            _ => unreachable!(),
+        }
+    }
+
+    fn from_yaml(yaml: &str) -> ApprovalPolicy {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn reason(policy: &ApprovalPolicy, pull_request: &PullRequestEvent) -> Option<String> {
+        let reason = policy.approval_reason(&WorkflowEvent::PullRequest(pull_request));
+        // both events of a delivery are held alike
+        assert_eq!(
+            policy.approval_reason(&WorkflowEvent::PullRequestTarget(pull_request)),
+            reason
+        );
+        reason
+    }
+
+    #[test]
+    fn trusted_authors_run_without_approval() {
+        let policy = ApprovalPolicy::default();
+        for association in ["OWNER", "MEMBER", "COLLABORATOR", "member"] {
+            assert_eq!(reason(&policy, &pull_request(association, false)), None);
+        }
+    }
+
+    #[test]
+    fn untrusted_authors_need_approval() {
+        let policy = ApprovalPolicy::default();
+        for association in ["CONTRIBUTOR", "FIRST_TIME_CONTRIBUTOR", "NONE"] {
+            assert_eq!(
+                reason(&policy, &pull_request(association, false)),
+                Some(format!(
+                    "forker is not trusted (author association {})",
+                    association
+                ))
+            );
+        }
+
+        let trusted = from_yaml("default: {trusted_users: [Forker]}");
+        assert_eq!(reason(&trusted, &pull_request("NONE", false)), None);
+    }
+
+    #[test]
+    fn forks_need_approval_unless_trusted() {
+        let fork = pull_request("MEMBER", true);
+        assert_eq!(
+            reason(&ApprovalPolicy::default(), &fork),
+            Some("the pull request comes from a fork".to_string())
+        );
+        assert_eq!(
+            reason(&from_yaml("default: {trust_forks: true}"), &fork),
+            None
+        );
+
+        // trusting forks doesn't trust their authors
+        assert!(
+            reason(
+                &from_yaml("default: {trust_forks: true}"),
+                &pull_request("NONE", true)
+            )
+            .is_some()
+        );
+    }
+
+    #[test]
+    fn approval_can_be_always_or_never_required() {
+        let always = from_yaml("default: {require_approval: always}");
+        assert_eq!(
+            reason(&always, &pull_request("OWNER", false)),
+            Some("every pull request needs approval".to_string())
+        );
+
+        let never = from_yaml("default: {require_approval: never}");
+        assert_eq!(reason(&never, &pull_request("NONE", true)), None);
+    }
+
+    #[test]
+    fn pushes_never_need_approval() {
+        let policy = from_yaml("default: {require_approval: always}");
+        let push = WebhookEvent::from_value("push", serde_json::from_str(PUSH).unwrap()).unwrap();
+        for event in WorkflowEvent::from_webhook(&push) {
+            assert_eq!(policy.approval_reason(&event), None);
+        }
+    }
+
+    #[test]
+    fn repositories_replace_the_default_rules() {
+        let policy = from_yaml(
+            "default: {require_approval: never}
+repositories:
+  Octo/Repo: {trusted_associations: [CONTRIBUTOR]}",
+        );
+        assert_eq!(reason(&policy, &pull_request("CONTRIBUTOR", false)), None);
+        // unset fields take the built-in defaults, not the default rules
+        assert!(reason(&policy, &pull_request("NONE", false)).is_some());
+        assert!(reason(&policy, &pull_request("CONTRIBUTOR", true)).is_some());
+    }
+
+    #[test]
+    fn owners_and_members_can_approve_by_default() {
+        let policy = ApprovalPolicy::default();
+        assert!(policy.can_approve("octo/repo", "octocat", Some("OWNER")));
+        assert!(policy.can_approve("octo/repo", "octocat", Some("member")));
+        assert!(!policy.can_approve("octo/repo", "octocat", Some("COLLABORATOR")));
+        assert!(!policy.can_approve("octo/repo", "octocat", Some("CONTRIBUTOR")));
+        assert!(!policy.can_approve("octo/repo", "octocat", None));
+    }
+
+    #[test]
+    fn approvers_can_be_listed_by_login() {
+        let policy = from_yaml(
+            "repositories:
+  octo/repo: {approvers: [Reviewer], approver_associations: []}",
+        );
+        assert!(policy.can_approve("octo/repo", "reviewer", None));
+        assert!(!policy.can_approve("octo/repo", "octocat", Some("OWNER")));
+        // other repositories keep the default rules
+        assert!(!policy.can_approve("octo/other", "reviewer", None));
+        assert!(policy.can_approve("octo/other", "octocat", Some("OWNER")));
+    }
+}
@@ -0,0 +1,71 @@
+use std::future::Future;
+use std::time::Duration;
+
+use lib::errors::AppError;
+use rand::Rng;
+
+use crate::config::AppConfig;
+
+/// Retries operations that fail with a retryable error, waiting an exponentially growing,
+/// jittered delay between attempts.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_delay: Duration,
+    max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            max_attempts: config.slurm_submit_max_attempts.max(1),
+            initial_delay: Duration::from_millis(config.slurm_submit_initial_delay_ms),
+            max_delay: Duration::from_millis(config.slurm_submit_max_delay_ms),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn new(max_attempts: u32, initial_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            max_attempts,
+            initial_delay,
+            max_delay,
+        }
+    }
+
+    pub async fn run<T, F, Fut>(&self, description: &str, mut operation: F) -> Result<T, AppError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, AppError>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation().await {
+                Err(err) if is_retryable(&err) && attempt < self.max_attempts => {
+                    let delay = self.delay(attempt);
+                    eprintln!(
+                        "{} failed (attempt {}/{}), retrying in {:?}: {}",
+                        description, attempt, self.max_attempts, delay, err
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // "equal jitter": half of the backoff is kept so retries never bunch up at zero
+    fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_delay
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(self.max_delay);
+        let half = backoff / 2;
+        half + half.mul_f64(rand::rng().random::<f64>())
+    }
+}
+
+fn is_retryable(err: &AppError) -> bool {
+    matches!(err, AppError::SlurmRetryableError(_))
+}
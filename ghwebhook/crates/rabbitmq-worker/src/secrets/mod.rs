@@ -0,0 +1,131 @@
+use std::io::Read;
+
+use lib::errors::AppError;
+use lib::secrets::{SecretCipher, normalize_name};
+use lib::store::RunStore;
+use lib::types::workflow::GithubWorkflowJob;
+
+use crate::config::AppConfig;
+use crate::dlq::format_timestamp;
+
+// secrets of a worker that isn't configured as a github app are stored under this
+// installation
+pub const NO_INSTALLATION: u64 = 0;
+
+pub fn cipher_from_config(config: &AppConfig) -> Result<Option<SecretCipher>, AppError> {
+    config
+        .secrets_master_key
+        .as_deref()
+        .map(SecretCipher::from_base64_key)
+        .transpose()
+}
+
+fn require_cipher(config: &AppConfig) -> Result<SecretCipher, AppError> {
+    cipher_from_config(config)?
+        .ok_or_else(|| AppError::SecretError("secrets_master_key is not configured".to_string()))
+}
+
+/// Stores a secret, reading its value from stdin so it doesn't end up in the shell history.
+pub fn set(
+    config: &AppConfig,
+    store: &RunStore,
+    installation_id: u64,
+    repository: &str,
+    name: &str,
+) -> Result<(), AppError> {
+    let cipher = require_cipher(config)?;
+    let name = normalize_name(name)?;
+
+    let mut value = String::new();
+    std::io::stdin()
+        .read_to_string(&mut value)
+        .map_err(|err| AppError::SecretError(format!("failed to read the value: {}", err)))?;
+    // `echo value |` adds a newline that isn't part of the secret
+    let value = value.strip_suffix('\n').unwrap_or(&value);
+    let value = value.strip_suffix('\r').unwrap_or(value);
+
+    let sealed = cipher.seal(installation_id, repository, &name, value)?;
+    store.put_secret(installation_id, repository, &name, &sealed)?;
+    println!("Stored {} for {}", name, repository);
+    Ok(())
+}
+
+pub fn list(store: &RunStore, installation_id: u64, repository: &str) -> Result<(), AppError> {
+    let secrets = store.list_secrets(installation_id, repository)?;
+    if secrets.is_empty() {
+        println!("No secrets for {}", repository);
+        return Ok(());
+    }
+
+    for secret in secrets {
+        println!(
+            "{}\tcreated {}\tupdated {}",
+            secret.name,
+            format_timestamp(secret.created_at),
+            format_timestamp(secret.updated_at)
+        );
+    }
+    Ok(())
+}
+
+pub fn delete(
+    store: &RunStore,
+    installation_id: u64,
+    repository: &str,
+    name: &str,
+) -> Result<(), AppError> {
+    let name = normalize_name(name)?;
+    if !store.delete_secret(installation_id, repository, &name)? {
+        return Err(AppError::SecretError(format!(
+            "{} has no secret {}",
+            repository, name
+        )));
+    }
+    println!("Deleted {} of {}", name, repository);
+    Ok(())
+}
+
+/// Decrypts the secrets `job` refers to, as `VARIABLE=value` entries of the job's
+/// environment. Secrets that don't exist are empty, like on GitHub.
+pub fn job_environment(
+    store: &RunStore,
+    cipher: Option<&SecretCipher>,
+    installation_id: u64,
+    repository: &str,
+    job: &GithubWorkflowJob,
+) -> Result<Vec<String>, AppError> {
+    // the token is always in the job's environment
+    let names = lib::script::expressions::job_secret_names(job)
+        .into_iter()
+        .filter(|name| name != lib::script::GITHUB_TOKEN_VARIABLE)
+        .collect::<Vec<_>>();
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let Some(cipher) = cipher else {
+        eprintln!(
+            "⚠️ secrets_master_key is not configured, {} are empty",
+            names.join(", ")
+        );
+        return Ok(Vec::new());
+    };
+
+    let sealed_secrets = store.sealed_secrets(installation_id, repository, &names)?;
+    for name in &names {
+        if !sealed_secrets
+            .iter()
+            .any(|(sealed_name, _)| sealed_name == name)
+        {
+            eprintln!("⚠️ {} has no secret {}, it is empty", repository, name);
+        }
+    }
+
+    sealed_secrets
+        .iter()
+        .map(|(name, sealed)| {
+            let value = cipher.open(installation_id, repository, name, sealed)?;
+            Ok(format!("{}={}", lib::script::secret_variable(name), value))
+        })
+        .collect()
+}
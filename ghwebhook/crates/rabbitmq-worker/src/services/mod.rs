@@ -1,47 +1,134 @@
-use rabbitmq_stream_client::error::StreamCreateError;
-use rabbitmq_stream_client::types::{ByteCapacity, ResponseCode};
-use rabbitmq_stream_client::{Consumer, Environment};
+use std::time::Duration;
 
-use crate::config::AppConfig;
+use rabbitmq_stream_client::error::{ClientError, StreamCreateError};
+use rabbitmq_stream_client::types::{
+    ByteCapacity, Message, OffsetSpecification, ResponseCode, SimpleValue,
+};
+use rabbitmq_stream_client::{Consumer, Environment, MessageContext, NoDedup, Producer};
 
-pub async fn create_rabbitmq_consumer(
+use crate::config::{AppConfig, StartOffset};
+
+pub const STREAM: &str = "ghwebhook";
+pub const DEAD_LETTER_STREAM: &str = "ghwebhook.dlq";
+
+const CONSUMER_NAME: &str = "ghwebhook_consumer";
+
+pub async fn create_rabbitmq_environment(
     config: &AppConfig,
-    stream: &str,
-    max_length_gb: u64,
-) -> Result<Consumer, lib::errors::AppError> {
-    let environment = Environment::builder()
+) -> Result<Environment, lib::errors::AppError> {
+    Environment::builder()
         .host(config.rabbitmq_host.clone().as_str())
         .port(config.rabbitmq_port)
         .build()
         .await
-        .map_err(|err| lib::errors::AppError::RabbitMQClientError(err))?;
+        .map_err(lib::errors::AppError::RabbitMQClientError)
+}
 
+pub async fn create_stream(environment: &Environment, stream: &str, max_length_gb: u64) {
     let create_response = environment
         .stream_creator()
         .max_length(ByteCapacity::GB(max_length_gb))
         .create(stream)
         .await;
 
-    if let Err(e) = create_response {
-        if let StreamCreateError::Create { stream, status } = e {
-            match status {
-                // we can ignore this error because the stream already exists
-                ResponseCode::StreamAlreadyExists => {
-                    println!("Stream {} already exists, skipping create.", stream);
-                }
-                err => {
-                    println!("Error creating stream: {:?} {:?}", stream, err);
-                }
+    if let Err(e) = create_response
+        && let StreamCreateError::Create { stream, status } = e
+    {
+        match status {
+            // we can ignore this error because the stream already exists
+            ResponseCode::StreamAlreadyExists => {
+                println!("Stream {} already exists, skipping create.", stream);
+            }
+            err => {
+                println!("Error creating stream: {:?} {:?}", stream, err);
             }
         }
     }
+}
+
+pub async fn create_rabbitmq_consumer(
+    environment: &Environment,
+    config: &AppConfig,
+    stream: &str,
+) -> Result<Consumer, lib::errors::AppError> {
+    let start_offset = config.consumer_start_offset;
 
-    Ok(environment
+    // with a single active consumer the broker asks for the offset to start from whenever
+    // this consumer becomes the active one, including on startup
+    environment
         .consumer()
-        .name("ghwebhook_consumer")
-        .offset(rabbitmq_stream_client::types::OffsetSpecification::First)
+        .name(CONSUMER_NAME)
+        .offset(start_offset.specification())
         .enable_single_active_consumer(true)
+        .consumer_update(move |_active, context| resume_offset(context, start_offset))
+        .build(stream)
+        .await
+        .map_err(lib::errors::AppError::RabbitMQConsumerCreateError)
+}
+
+pub async fn create_rabbitmq_producer(
+    environment: &Environment,
+    stream: &str,
+) -> Result<Producer<NoDedup>, lib::errors::AppError> {
+    environment
+        .producer()
         .build(stream)
         .await
-        .map_err(|err| lib::errors::AppError::RabbitMQConsumerCreateError(err))?)
+        .map_err(lib::errors::AppError::RabbitMQProducerCreateError)
+}
+
+// attempts at reading the stored offset before the worker gives up
+const QUERY_OFFSET_ATTEMPTS: u32 = 5;
+
+/// Resumes after the last offset the consumer stored, or at `start_offset` if it has never
+/// stored one. Any other failure is retried, and the worker exits if the offset still can't
+/// be read: starting from `start_offset` would replay or skip the stream.
+async fn resume_offset(context: MessageContext, start_offset: StartOffset) -> OffsetSpecification {
+    let mut delay = Duration::from_secs(1);
+    for attempt in 1..=QUERY_OFFSET_ATTEMPTS {
+        match context
+            .client()
+            .query_offset(context.name(), &context.stream())
+            .await
+        {
+            Ok(offset) => {
+                println!("Resuming {} after offset {}", context.name(), offset);
+                return OffsetSpecification::Offset(offset + 1);
+            }
+            Err(ClientError::RequestError(ResponseCode::OffsetNotFound)) => {
+                println!(
+                    "No stored offset for {}, starting from {:?}",
+                    context.name(),
+                    start_offset
+                );
+                return start_offset.specification();
+            }
+            Err(err) => {
+                eprintln!(
+                    "Failed to query the stored offset of {} (attempt {}/{}): {}",
+                    context.name(),
+                    attempt,
+                    QUERY_OFFSET_ATTEMPTS,
+                    err
+                );
+                if attempt < QUERY_OFFSET_ATTEMPTS {
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+            }
+        }
+    }
+
+    eprintln!(
+        "Giving up on the stored offset of {}, exiting instead of guessing where to resume",
+        context.name()
+    );
+    std::process::exit(1);
+}
+
+pub fn message_property(message: &Message, key: &str) -> Option<String> {
+    match message.application_properties()?.get(key)? {
+        SimpleValue::String(value) => Some(value.clone()),
+        _ => None,
+    }
 }
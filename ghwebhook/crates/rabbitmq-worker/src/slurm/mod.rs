@@ -0,0 +1,817 @@
+use chrono::Utc;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tokio::sync::{Mutex, OnceCell};
+
+use crate::config::AppConfig;
+
+mod types;
+mod version;
+
+pub use types::{JobDescription, JobInfo, Node, Partition};
+pub use version::ApiVersion;
+
+use types::{
+    RawError, RawJob, RawJobsResponse, RawNodesResponse, RawPartitionsResponse, RawSubmitResponse,
+    SubmitRequest,
+};
+
+// minted tokens are replaced once less than this share of their lifespan is left
+const TOKEN_REFRESH_DIVISOR: i64 = 5;
+
+/// How the worker authenticates to slurmrestd: a token it signs itself with the cluster's
+/// `jwt_key`, or a static token from `scontrol token`.
+pub enum SlurmAuth {
+    Jwt(SlurmJwtSigner),
+    Token(String),
+}
+
+impl SlurmAuth {
+    pub fn from_config(config: &AppConfig) -> Result<Self, lib::errors::AppError> {
+        match (&config.slurmrestd_jwt_key, &config.slurmrestd_token) {
+            (Some(key_path), _) => {
+                let key = std::fs::read(key_path).map_err(|err| {
+                    lib::errors::AppError::SlurmAuthError(format!(
+                        "failed to read jwt key {}: {}",
+                        key_path, err
+                    ))
+                })?;
+                Ok(SlurmAuth::Jwt(SlurmJwtSigner::new(
+                    &key,
+                    required(&config.slurmrestd_user, "slurmrestd_user")?,
+                    config.slurmrestd_token_lifespan_secs,
+                )?))
+            }
+            (None, Some(token)) => Ok(SlurmAuth::Token(token.clone())),
+            (None, None) => Err(lib::errors::AppError::SlurmAuthError(
+                "either slurmrestd_jwt_key or slurmrestd_token must be set".to_string(),
+            )),
+        }
+    }
+
+    pub async fn token(&self) -> Result<String, lib::errors::AppError> {
+        match self {
+            SlurmAuth::Jwt(signer) => signer.token().await,
+            SlurmAuth::Token(token) => Ok(token.clone()),
+        }
+    }
+}
+
+/// Signs tokens the way `scontrol token` does, with the HS256 key slurmctld and slurmrestd
+/// are configured with through `AuthAltParameters=jwt_key=`.
+pub struct SlurmJwtSigner {
+    encoding_key: EncodingKey,
+    user: String,
+    lifespan_secs: i64,
+    token: Mutex<Option<MintedToken>>,
+}
+
+struct MintedToken {
+    token: String,
+    expires_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct SlurmClaims<'a> {
+    iat: i64,
+    exp: i64,
+    // slurm user name the token authenticates
+    sun: &'a str,
+}
+
+impl SlurmJwtSigner {
+    pub fn new(key: &[u8], user: &str, lifespan_secs: u64) -> Result<Self, lib::errors::AppError> {
+        if key.is_empty() {
+            return Err(lib::errors::AppError::SlurmAuthError(
+                "jwt key is empty".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            encoding_key: EncodingKey::from_secret(key),
+            user: user.to_string(),
+            lifespan_secs: lifespan_secs.max(60) as i64,
+            token: Mutex::new(None),
+        })
+    }
+
+    pub async fn token(&self) -> Result<String, lib::errors::AppError> {
+        self.token_at(Utc::now().timestamp()).await
+    }
+
+    // the token to use at `now`, in seconds since the unix epoch
+    async fn token_at(&self, now: i64) -> Result<String, lib::errors::AppError> {
+        let mut cached = self.token.lock().await;
+
+        if let Some(minted) = cached.as_ref()
+            && minted.expires_at - now > self.lifespan_secs / TOKEN_REFRESH_DIVISOR
+        {
+            return Ok(minted.token.clone());
+        }
+
+        let claims = SlurmClaims {
+            iat: now,
+            exp: now + self.lifespan_secs,
+            sun: &self.user,
+        };
+        let token =
+            jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &self.encoding_key)
+                .map_err(|err| lib::errors::AppError::SlurmAuthError(err.to_string()))?;
+
+        *cached = Some(MintedToken {
+            token: token.clone(),
+            expires_at: claims.exp,
+        });
+        Ok(token)
+    }
+}
+
+/// Typed client for slurmrestd's OpenAPI plugins. Differences between the supported
+/// versions are handled here, callers only see the version-independent types.
+pub struct SlurmRestClient {
+    http: reqwest::Client,
+    base_url: String,
+    user: String,
+    auth: SlurmAuth,
+    // set when a version is configured, detected from /openapi on first use otherwise
+    configured_version: Option<ApiVersion>,
+    detected_version: OnceCell<ApiVersion>,
+}
+
+impl SlurmRestClient {
+    pub fn from_config(
+        config: &AppConfig,
+        http: reqwest::Client,
+    ) -> Result<Self, lib::errors::AppError> {
+        let configured_version = match config.slurmrestd_api_version.as_str() {
+            "auto" => None,
+            version => Some(version.parse::<ApiVersion>().map_err(|err| {
+                lib::errors::AppError::ConfigError(config::ConfigError::Message(err))
+            })?),
+        };
+
+        Ok(Self {
+            http,
+            base_url: format!(
+                "http://{}:{}",
+                required(&config.slurmrestd_host, "slurmrestd_host")?,
+                config.slurmrestd_port
+            ),
+            user: required(&config.slurmrestd_user, "slurmrestd_user")?.to_string(),
+            auth: SlurmAuth::from_config(config)?,
+            configured_version,
+            detected_version: OnceCell::new(),
+        })
+    }
+
+    pub async fn version(&self) -> Result<ApiVersion, lib::errors::AppError> {
+        if let Some(version) = self.configured_version {
+            return Ok(version);
+        }
+
+        self.detected_version
+            .get_or_try_init(|| self.detect_version())
+            .await
+            .copied()
+    }
+
+    // slurmrestd serves the specification of every loaded plugin, the newest supported
+    // one that can submit jobs is used
+    async fn detect_version(&self) -> Result<ApiVersion, lib::errors::AppError> {
+        for path in ["/openapi/v3", "/openapi"] {
+            let Some(specification) = self.get::<serde_json::Value>(path).await? else {
+                continue;
+            };
+            let Some(paths) = specification
+                .get("paths")
+                .and_then(serde_json::Value::as_object)
+            else {
+                continue;
+            };
+
+            if let Some(version) = ApiVersion::SUPPORTED
+                .into_iter()
+                .find(|version| paths.contains_key(&version.slurm_path("job/submit")))
+            {
+                println!("Using slurmrestd API {}", version);
+                return Ok(version);
+            }
+        }
+
+        Err(lib::errors::AppError::SlurmRestdError(
+            "slurmrestd serves none of the supported API versions v0.0.39 to v0.0.42".to_string(),
+        ))
+    }
+
+    /// Submits a batch job and returns its Slurm job id. Failures are either
+    /// `SlurmRetryableError`, when submitting again later may succeed, or `SlurmPermanentError`.
+    pub async fn submit(&self, job: &JobDescription) -> Result<u32, lib::errors::AppError> {
+        let version = self.version().await?;
+        let response = self
+            .request(reqwest::Method::POST, &version.slurm_path("job/submit"))
+            .await?
+            .json(&SubmitRequest { job })
+            .send()
+            .await
+            .map_err(submit_transport_error)?;
+
+        let status = response.status();
+        let text = response.text().await.map_err(submit_transport_error)?;
+        let submitted = serde_json::from_str::<RawSubmitResponse>(&text).unwrap_or_default();
+        let errors = error_messages(&submitted.errors);
+
+        if status.is_success() && errors.is_empty() {
+            return submitted
+                .job_id
+                .or(submitted.result.and_then(|result| result.job_id))
+                .ok_or_else(|| {
+                    lib::errors::AppError::SlurmPermanentError(format!(
+                        "response has no job id: {}",
+                        text
+                    ))
+                });
+        }
+
+        Err(classify(status, failure_message(status, &text, &errors)))
+    }
+
+    /// Returns the job's state, asking slurmdbd once slurmctld has forgotten a finished job.
+    /// `None` means neither knows about the job.
+    pub async fn job(&self, job_id: u32) -> Result<Option<JobInfo>, lib::errors::AppError> {
+        let version = self.version().await?;
+        let job_path = format!("job/{}", job_id);
+
+        for path in [
+            version.slurm_path(&job_path),
+            version.slurmdb_path(&job_path),
+        ] {
+            let response = match self.get::<RawJobsResponse>(&path).await {
+                Ok(response) => response,
+                // slurmctld answers with an error once it has purged a finished job
+                Err(lib::errors::AppError::SlurmPermanentError(message))
+                    if message.to_lowercase().contains("invalid job id") =>
+                {
+                    None
+                }
+                Err(err) => return Err(err),
+            };
+            if let Some(info) = response
+                .and_then(|response| response.jobs.into_iter().next())
+                .and_then(RawJob::into_info)
+            {
+                return Ok(Some(info));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub async fn cancel(&self, job_id: u32) -> Result<(), lib::errors::AppError> {
+        let version = self.version().await?;
+        let response = self
+            .request(
+                reqwest::Method::DELETE,
+                &version.slurm_path(&format!("job/{}", job_id)),
+            )
+            .await?
+            .send()
+            .await
+            .map_err(transport_error)?;
+
+        let status = response.status();
+        let text = response.text().await.map_err(transport_error)?;
+        let errors = serde_json::from_str::<RawSubmitResponse>(&text)
+            .map(|response| error_messages(&response.errors))
+            .unwrap_or_default();
+
+        if status.is_success() && errors.is_empty() {
+            return Ok(());
+        }
+        Err(classify(status, failure_message(status, &text, &errors)))
+    }
+
+    pub async fn nodes(&self) -> Result<Vec<Node>, lib::errors::AppError> {
+        let version = self.version().await?;
+        let response = self
+            .get::<RawNodesResponse>(&version.slurm_path("nodes"))
+            .await?
+            .ok_or_else(|| {
+                lib::errors::AppError::SlurmRestdError("failed to list nodes".to_string())
+            })?;
+        Ok(response.nodes.into_iter().map(Node::from).collect())
+    }
+
+    pub async fn partitions(&self) -> Result<Vec<Partition>, lib::errors::AppError> {
+        let version = self.version().await?;
+        let response = self
+            .get::<RawPartitionsResponse>(&version.slurm_path("partitions"))
+            .await?
+            .ok_or_else(|| {
+                lib::errors::AppError::SlurmRestdError("failed to list partitions".to_string())
+            })?;
+        Ok(response
+            .partitions
+            .into_iter()
+            .map(Partition::from)
+            .collect())
+    }
+
+    async fn request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+    ) -> Result<reqwest::RequestBuilder, lib::errors::AppError> {
+        Ok(self
+            .http
+            .request(method, format!("{}{}", self.base_url, path))
+            .header("X-SLURM-USER-NAME", self.user.as_str())
+            .header("X-SLURM-USER-TOKEN", self.auth.token().await?))
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> Result<Option<T>, lib::errors::AppError> {
+        let response = self
+            .request(reqwest::Method::GET, path)
+            .await?
+            .send()
+            .await
+            .map_err(transport_error)?;
+
+        // paths of plugins slurmrestd hasn't loaded don't exist, any other failure is an
+        // error the caller has to see
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            let text = response.text().await.map_err(transport_error)?;
+            let errors = serde_json::from_str::<RawSubmitResponse>(&text)
+                .map(|response| error_messages(&response.errors))
+                .unwrap_or_default();
+            return Err(classify(status, failure_message(status, &text, &errors)));
+        }
+
+        response
+            .json()
+            .await
+            .map(Some)
+            .map_err(|err| lib::errors::AppError::SlurmRestdError(err.to_string()))
+    }
+}
+
+pub async fn print_info(client: &SlurmRestClient) -> Result<(), lib::errors::AppError> {
+    println!("slurmrestd API: {}", client.version().await?);
+
+    println!("\nPartitions:");
+    for partition in client.partitions().await? {
+        println!(
+            "  {}\t{}\t{} nodes\t{}",
+            partition.name,
+            partition.state.join(","),
+            partition
+                .total_nodes
+                .map(|total| total.to_string())
+                .unwrap_or_else(|| "?".to_string()),
+            partition.nodes.unwrap_or_default()
+        );
+    }
+
+    println!("\nNodes:");
+    for node in client.nodes().await? {
+        println!(
+            "  {}\t{}\t{} cpus\t{}",
+            node.name,
+            node.state.join(","),
+            node.cpus
+                .map(|cpus| cpus.to_string())
+                .unwrap_or_else(|| "?".to_string()),
+            node.partitions.join(",")
+        );
+    }
+    Ok(())
+}
+
+fn error_messages(errors: &[RawError]) -> Vec<String> {
+    errors
+        .iter()
+        .filter_map(RawError::message)
+        .map(str::to_string)
+        .collect()
+}
+
+fn failure_message(status: reqwest::StatusCode, text: &str, errors: &[String]) -> String {
+    if errors.is_empty() {
+        format!("{}: {}", status, text)
+    } else {
+        format!("{}: {}", status, errors.join("; "))
+    }
+}
+
+// messages slurmctld and slurmrestd use when the controller is restarting or overloaded
+const RETRYABLE_MESSAGES: &[&str] = &[
+    "temporarily unavailable",
+    "unable to contact slurm controller",
+    "socket timed out",
+    "connection refused",
+    "try again",
+];
+
+// errors about the job itself, which no amount of retrying fixes
+const PERMANENT_MESSAGES: &[&str] = &[
+    "invalid partition",
+    "batch script",
+    "invalid account",
+    "invalid qos",
+    "invalid job",
+    "requested node configuration is not available",
+    "access/permission denied",
+];
+
+fn classify(status: reqwest::StatusCode, message: String) -> lib::errors::AppError {
+    let transient = status.is_server_error()
+        || status == reqwest::StatusCode::REQUEST_TIMEOUT
+        || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+    classify_message(message, transient)
+}
+
+/// Splits a Slurm failure into a retryable or permanent error by its message. `transient`
+/// is whether the failure looks temporary when the message is not recognized.
+pub fn classify_message(message: String, transient: bool) -> lib::errors::AppError {
+    let lowercase = message.to_lowercase();
+    let matches = |patterns: &[&str]| patterns.iter().any(|pattern| lowercase.contains(pattern));
+
+    let retryable = if matches(PERMANENT_MESSAGES) {
+        false
+    } else {
+        matches(RETRYABLE_MESSAGES) || transient
+    };
+
+    if retryable {
+        lib::errors::AppError::SlurmRetryableError(message)
+    } else {
+        lib::errors::AppError::SlurmPermanentError(message)
+    }
+}
+
+// connection failures and timeouts are retryable, a request that can't be built is not
+fn transport_error(err: reqwest::Error) -> lib::errors::AppError {
+    if err.is_builder() {
+        lib::errors::AppError::SlurmPermanentError(err.to_string())
+    } else {
+        lib::errors::AppError::SlurmRetryableError(err.to_string())
+    }
+}
+
+// a submission is only safe to repeat if it never reached slurmrestd: once the request is
+// sent, a timeout or a dropped connection may come after slurmctld queued the job
+fn submit_transport_error(err: reqwest::Error) -> lib::errors::AppError {
+    if err.is_connect() || err.is_builder() {
+        transport_error(err)
+    } else {
+        lib::errors::AppError::SlurmRestdError(format!(
+            "the job may have been submitted, not retrying: {}",
+            err
+        ))
+    }
+}
+
+fn required<'a>(value: &'a Option<String>, name: &str) -> Result<&'a str, lib::errors::AppError> {
+    value.as_deref().ok_or_else(|| {
+        lib::errors::AppError::ConfigError(config::ConfigError::Message(format!(
+            "{} must be set for the slurmrestd submission backend",
+            name
+        )))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use lib::errors::AppError;
+    use serde_json::json;
+    use tempdir::TempDir;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::retry::RetryPolicy;
+
+    const SUBMIT_PATH: &str = "/slurm/v0.0.41/job/submit";
+
+    const JWT_KEY: &[u8] = b"a jwt_key as slurmctld reads it";
+
+    fn decode_claims(token: &str) -> serde_json::Value {
+        let mut validation = jsonwebtoken::Validation::new(Algorithm::HS256);
+        validation.set_required_spec_claims(&["exp", "iat"]);
+        jsonwebtoken::decode::<serde_json::Value>(
+            token,
+            &jsonwebtoken::DecodingKey::from_secret(JWT_KEY),
+            &validation,
+        )
+        .unwrap()
+        .claims
+    }
+
+    #[tokio::test]
+    async fn tokens_are_signed_with_the_jwt_key() {
+        let signer = SlurmJwtSigner::new(JWT_KEY, "slurm", 600).unwrap();
+        let now = Utc::now().timestamp();
+        let claims = decode_claims(&signer.token().await.unwrap());
+
+        let iat = claims["iat"].as_i64().unwrap();
+        assert!((now..now + 5).contains(&iat));
+        assert_eq!(claims["exp"].as_i64().unwrap(), iat + 600);
+        assert_eq!(claims["sun"], "slurm");
+
+        let wrong_key = jsonwebtoken::decode::<serde_json::Value>(
+            &signer.token().await.unwrap(),
+            &jsonwebtoken::DecodingKey::from_secret(b"another key"),
+            &jsonwebtoken::Validation::new(Algorithm::HS256),
+        );
+        assert!(wrong_key.is_err());
+    }
+
+    #[tokio::test]
+    async fn tokens_are_refreshed_with_a_fifth_of_their_lifespan_left() {
+        let signer = SlurmJwtSigner::new(JWT_KEY, "slurm", 600).unwrap();
+        let first = signer.token_at(1_000).await.unwrap();
+
+        // expires at 1600, refreshed once 120 seconds or less are left
+        assert_eq!(signer.token_at(1_479).await.unwrap(), first);
+        let second = signer.token_at(1_480).await.unwrap();
+        assert_ne!(second, first);
+        assert_eq!(signer.token_at(1_500).await.unwrap(), second);
+    }
+
+    #[test]
+    fn empty_keys_are_rejected_and_short_lifespans_raised() {
+        assert!(matches!(
+            SlurmJwtSigner::new(b"", "slurm", 600),
+            Err(AppError::SlurmAuthError(_))
+        ));
+        assert_eq!(
+            SlurmJwtSigner::new(JWT_KEY, "slurm", 1)
+                .unwrap()
+                .lifespan_secs,
+            60
+        );
+    }
+
+    fn config(settings: serde_json::Value) -> AppConfig {
+        serde_json::from_value(settings).unwrap()
+    }
+
+    #[tokio::test]
+    async fn static_tokens_are_used_without_a_jwt_key() {
+        let auth =
+            SlurmAuth::from_config(&config(json!({"slurmrestd_token": "scontrol"}))).unwrap();
+        assert_eq!(auth.token().await.unwrap(), "scontrol");
+
+        assert!(matches!(
+            SlurmAuth::from_config(&config(json!({}))),
+            Err(AppError::SlurmAuthError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn jwt_keys_take_precedence_over_static_tokens() {
+        let dir = TempDir::new("slurm-auth").unwrap();
+        let key_path = dir.path().join("jwt_key");
+        std::fs::write(&key_path, JWT_KEY).unwrap();
+
+        let auth = SlurmAuth::from_config(&config(json!({
+            "slurmrestd_jwt_key": key_path,
+            "slurmrestd_user": "slurm",
+            "slurmrestd_token": "scontrol",
+        })))
+        .unwrap();
+        assert_eq!(decode_claims(&auth.token().await.unwrap())["sun"], "slurm");
+
+        // the user the token is for must be configured
+        assert!(matches!(
+            SlurmAuth::from_config(&config(json!({"slurmrestd_jwt_key": key_path}))),
+            Err(AppError::ConfigError(_))
+        ));
+    }
+
+    fn client(base_url: &str) -> SlurmRestClient {
+        SlurmRestClient {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_millis(500))
+                .build()
+                .unwrap(),
+            base_url: base_url.to_string(),
+            user: "slurm".to_string(),
+            auth: SlurmAuth::Token("token".to_string()),
+            configured_version: Some(ApiVersion::V0041),
+            detected_version: OnceCell::new(),
+        }
+    }
+
+    fn job() -> JobDescription {
+        JobDescription {
+            name: None,
+            partition: None,
+            script: "#!/bin/bash\ntrue\n".to_string(),
+            environment: vec!["PATH=/usr/bin:/bin".to_string()],
+            current_working_directory: "/tmp".to_string(),
+        }
+    }
+
+    fn slurm_errors(message: &str) -> serde_json::Value {
+        json!({ "errors": [{ "error": "error", "description": message, "error_number": 1 }] })
+    }
+
+    async fn submit_responds(server: &MockServer, response: ResponseTemplate) {
+        Mock::given(method("POST"))
+            .and(path(SUBMIT_PATH))
+            .respond_with(response)
+            .mount(server)
+            .await;
+    }
+
+    fn retry_policy() -> RetryPolicy {
+        RetryPolicy::new(3, Duration::from_millis(1), Duration::from_millis(5))
+    }
+
+    #[tokio::test]
+    async fn submit_returns_the_job_id() {
+        let server = MockServer::start().await;
+        submit_responds(
+            &server,
+            ResponseTemplate::new(200).set_body_json(json!({
+                "job_id": 4242,
+                "result": { "job_id": 4242 },
+                "errors": [],
+            })),
+        )
+        .await;
+
+        assert_eq!(client(&server.uri()).submit(&job()).await.unwrap(), 4242);
+
+        let requests = server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(body["job"]["current_working_directory"], "/tmp");
+        assert_eq!(requests[0].headers["x-slurm-user-token"], "token");
+    }
+
+    #[tokio::test]
+    async fn unavailable_slurm_is_retryable() {
+        for response in [
+            ResponseTemplate::new(503),
+            ResponseTemplate::new(500).set_body_json(slurm_errors(
+                "Unable to contact slurm controller (connect failure)",
+            )),
+            ResponseTemplate::new(200).set_body_json(slurm_errors("Slurm temporarily unavailable")),
+        ] {
+            let server = MockServer::start().await;
+            submit_responds(&server, response).await;
+
+            let err = client(&server.uri()).submit(&job()).await.unwrap_err();
+            assert!(matches!(err, AppError::SlurmRetryableError(_)), "{}", err);
+        }
+    }
+
+    #[tokio::test]
+    async fn errors_about_the_job_are_permanent() {
+        for response in [
+            ResponseTemplate::new(500)
+                .set_body_json(slurm_errors("Invalid partition name specified")),
+            ResponseTemplate::new(200).set_body_json(slurm_errors(
+                "Batch job submission failed: Invalid account or account/partition combination specified",
+            )),
+        ] {
+            let server = MockServer::start().await;
+            submit_responds(&server, response).await;
+
+            let err = client(&server.uri()).submit(&job()).await.unwrap_err();
+            assert!(matches!(err, AppError::SlurmPermanentError(_)), "{}", err);
+        }
+    }
+
+    #[tokio::test]
+    async fn refused_connections_are_retryable() {
+        // a port nothing listens on
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+
+        let err = client(&format!("http://{}", address))
+            .submit(&job())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::SlurmRetryableError(_)), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn submissions_that_time_out_are_not_repeated() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path(SUBMIT_PATH))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "job_id": 4242 }))
+                    .set_delay(Duration::from_secs(2)),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let (client, job) = (client(&server.uri()), job());
+        let err = retry_policy()
+            .run("Submitting job", || client.submit(&job))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::SlurmRestdError(_)), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn retries_until_slurm_is_back() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path(SUBMIT_PATH))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        submit_responds(
+            &server,
+            ResponseTemplate::new(200).set_body_json(json!({ "job_id": 4242 })),
+        )
+        .await;
+
+        let (client, job) = (client(&server.uri()), job());
+        let job_id = retry_policy()
+            .run("Submitting job", || client.submit(&job))
+            .await
+            .unwrap();
+        assert_eq!(job_id, 4242);
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn permanent_errors_are_not_retried() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path(SUBMIT_PATH))
+            .respond_with(
+                ResponseTemplate::new(500)
+                    .set_body_json(slurm_errors("Invalid partition name specified")),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let (client, job) = (client(&server.uri()), job());
+        let err = retry_policy()
+            .run("Submitting job", || client.submit(&job))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::SlurmPermanentError(_)), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn unknown_jobs_are_none_and_other_failures_are_errors() {
+        let server = MockServer::start().await;
+        // purged by slurmctld and not in slurmdbd
+        Mock::given(method("GET"))
+            .and(path("/slurm/v0.0.41/job/1"))
+            .respond_with(
+                ResponseTemplate::new(500).set_body_json(slurm_errors("Invalid job id specified")),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/slurmdb/v0.0.41/job/1"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/slurm/v0.0.41/job/2"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/slurm/v0.0.41/job/3"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "jobs": [{ "job_state": ["RUNNING"] }] })),
+            )
+            .mount(&server)
+            .await;
+
+        let client = client(&server.uri());
+        assert!(client.job(1).await.unwrap().is_none());
+        assert!(matches!(
+            client.job(2).await.unwrap_err(),
+            AppError::SlurmRetryableError(_)
+        ));
+        assert_eq!(client.job(3).await.unwrap().unwrap().state, "RUNNING");
+    }
+}
@@ -0,0 +1,330 @@
+use serde::{Deserialize, Serialize};
+
+/// The job description of a batch job submission. `#SBATCH` lines in the script still
+/// apply, fields set here take precedence.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobDescription {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partition: Option<String>,
+    pub script: String,
+    // `KEY=value` pairs
+    pub environment: Vec<String>,
+    pub current_working_directory: String,
+}
+
+#[derive(Debug, Serialize)]
+pub(super) struct SubmitRequest<'a> {
+    pub job: &'a JobDescription,
+}
+
+/// State of a job as reported by slurmctld, or by slurmdbd once slurmctld has forgotten it.
+#[derive(Debug, Clone)]
+pub struct JobInfo {
+    pub state: String,
+    pub exit_code: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub name: String,
+    pub state: Vec<String>,
+    pub partitions: Vec<String>,
+    pub cpus: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Partition {
+    pub name: String,
+    pub state: Vec<String>,
+    pub nodes: Option<String>,
+    pub total_nodes: Option<i64>,
+}
+
+// integers are plain numbers in some versions and fields, and `{"set", "infinite", "number"}`
+// objects in others
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(super) enum RawNumber {
+    Plain(i64),
+    NoVal {
+        #[serde(default)]
+        set: bool,
+        #[serde(default)]
+        infinite: bool,
+        #[serde(default)]
+        number: i64,
+    },
+}
+
+impl RawNumber {
+    pub fn value(&self) -> Option<i64> {
+        match self {
+            RawNumber::Plain(number) => Some(*number),
+            RawNumber::NoVal {
+                set: true,
+                infinite: false,
+                number,
+            } => Some(*number),
+            RawNumber::NoVal { .. } => None,
+        }
+    }
+}
+
+// states are a single string up to v0.0.39 and a list of flags after
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(super) enum RawFlags {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl RawFlags {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            RawFlags::One(flag) => vec![flag],
+            RawFlags::Many(flags) => flags,
+        }
+    }
+}
+
+// comma separated string or list, depending on the version
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(super) enum RawList {
+    Joined(String),
+    Many(Vec<String>),
+}
+
+impl RawList {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            RawList::Joined(list) => list
+                .split(',')
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect(),
+            RawList::Many(list) => list,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct RawError {
+    pub error: Option<String>,
+    pub description: Option<String>,
+}
+
+impl RawError {
+    pub fn message(&self) -> Option<&str> {
+        self.description
+            .as_deref()
+            .filter(|description| !description.is_empty())
+            .or(self.error.as_deref())
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct RawSubmitResponse {
+    pub job_id: Option<u32>,
+    // v0.0.40 and later also nest the job id in `result`
+    pub result: Option<RawSubmitResult>,
+    #[serde(default)]
+    pub errors: Vec<RawError>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct RawSubmitResult {
+    pub job_id: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct RawJobsResponse {
+    #[serde(default)]
+    pub jobs: Vec<RawJob>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct RawJob {
+    // slurmctld
+    pub job_state: Option<RawFlags>,
+    // slurmdbd
+    pub state: Option<RawDbState>,
+    pub exit_code: Option<RawExitCode>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct RawDbState {
+    pub current: RawFlags,
+}
+
+// slurmdbd reports `{"status", "return_code"}` since v0.0.39 and slurmctld since v0.0.40.
+// Untagged variants are tried in order and `RawNumber::NoVal` accepts any object, so the
+// verbose form has to come first and require `return_code`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(super) enum RawExitCode {
+    Verbose { return_code: RawNumber },
+    Plain(RawNumber),
+}
+
+impl RawJob {
+    pub fn into_info(self) -> Option<JobInfo> {
+        let state = self
+            .job_state
+            .or(self.state.map(|state| state.current))?
+            .into_vec()
+            .into_iter()
+            .next()?;
+
+        let exit_code = match self.exit_code {
+            Some(RawExitCode::Verbose { return_code }) => return_code.value(),
+            Some(RawExitCode::Plain(number)) => number.value(),
+            None => None,
+        };
+
+        Some(JobInfo { state, exit_code })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct RawNodesResponse {
+    #[serde(default)]
+    pub nodes: Vec<RawNode>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct RawNode {
+    pub name: String,
+    pub state: Option<RawFlags>,
+    pub partitions: Option<RawList>,
+    pub cpus: Option<RawNumber>,
+}
+
+impl From<RawNode> for Node {
+    fn from(node: RawNode) -> Self {
+        Node {
+            name: node.name,
+            state: node.state.map(RawFlags::into_vec).unwrap_or_default(),
+            partitions: node.partitions.map(RawList::into_vec).unwrap_or_default(),
+            cpus: node.cpus.and_then(|cpus| cpus.value()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct RawPartitionsResponse {
+    #[serde(default)]
+    pub partitions: Vec<RawPartition>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct RawPartition {
+    pub name: String,
+    // v0.0.39 and earlier report these at the top level, later versions group them
+    pub state: Option<RawFlags>,
+    pub total_nodes: Option<RawNumber>,
+    pub nodes: Option<RawPartitionNodes>,
+    pub partition: Option<RawPartitionDetails>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(super) enum RawPartitionNodes {
+    Names(String),
+    Details {
+        configured: Option<String>,
+        total: Option<RawNumber>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct RawPartitionDetails {
+    pub state: Option<RawFlags>,
+}
+
+impl From<RawPartition> for Partition {
+    fn from(partition: RawPartition) -> Self {
+        let state = partition
+            .state
+            .or(partition.partition.and_then(|details| details.state))
+            .map(RawFlags::into_vec)
+            .unwrap_or_default();
+
+        let (nodes, total_nodes) = match partition.nodes {
+            Some(RawPartitionNodes::Names(names)) => (Some(names), None),
+            Some(RawPartitionNodes::Details { configured, total }) => (configured, total),
+            None => (None, None),
+        };
+
+        Partition {
+            name: partition.name,
+            state,
+            nodes,
+            total_nodes: partition
+                .total_nodes
+                .or(total_nodes)
+                .and_then(|total| total.value()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job_info(fixture: &str) -> JobInfo {
+        let path = format!(
+            "{}/tests/fixtures/slurmrestd/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            fixture
+        );
+        let body = std::fs::read_to_string(&path).unwrap();
+        let response: RawJobsResponse = serde_json::from_str(&body).unwrap();
+        response
+            .jobs
+            .into_iter()
+            .next()
+            .unwrap()
+            .into_info()
+            .unwrap()
+    }
+
+    #[test]
+    fn reads_v0_0_39_slurmctld_jobs() {
+        let info = job_info("v0.0.39-slurm-job.json");
+        assert_eq!(info.state, "FAILED");
+        assert_eq!(info.exit_code, Some(256));
+    }
+
+    #[test]
+    fn reads_v0_0_39_slurmdbd_jobs() {
+        let info = job_info("v0.0.39-slurmdb-job.json");
+        assert_eq!(info.state, "FAILED");
+        assert_eq!(info.exit_code, Some(2));
+    }
+
+    #[test]
+    fn reads_v0_0_41_slurmctld_jobs() {
+        let info = job_info("v0.0.41-slurm-job.json");
+        assert_eq!(info.state, "FAILED");
+        assert_eq!(info.exit_code, Some(2));
+    }
+
+    #[test]
+    fn reads_v0_0_41_slurmdbd_jobs() {
+        let info = job_info("v0.0.41-slurmdb-job.json");
+        assert_eq!(info.state, "COMPLETED");
+        assert_eq!(info.exit_code, Some(0));
+    }
+
+    #[test]
+    fn unset_exit_codes_are_none() {
+        let job: RawJob = serde_json::from_str(
+            r#"{"job_state": ["PENDING"], "exit_code": {"status": ["PENDING"], "return_code": {"set": false, "infinite": false, "number": 0}}}"#,
+        )
+        .unwrap();
+        assert_eq!(job.into_info().unwrap().exit_code, None);
+    }
+}
@@ -0,0 +1,62 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// slurmrestd OpenAPI plugin versions the client can talk to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ApiVersion {
+    V0039,
+    V0040,
+    V0041,
+    V0042,
+}
+
+impl ApiVersion {
+    // newest first, so detection prefers the newest version a slurmrestd serves
+    pub const SUPPORTED: [ApiVersion; 4] = [
+        ApiVersion::V0042,
+        ApiVersion::V0041,
+        ApiVersion::V0040,
+        ApiVersion::V0039,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiVersion::V0039 => "v0.0.39",
+            ApiVersion::V0040 => "v0.0.40",
+            ApiVersion::V0041 => "v0.0.41",
+            ApiVersion::V0042 => "v0.0.42",
+        }
+    }
+
+    pub fn slurm_path(&self, path: &str) -> String {
+        format!("/slurm/{}/{}", self.as_str(), path)
+    }
+
+    pub fn slurmdb_path(&self, path: &str) -> String {
+        format!("/slurmdb/{}/{}", self.as_str(), path)
+    }
+}
+
+impl fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ApiVersion {
+    type Err = String;
+
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        let version = version.trim();
+        let version = version.strip_prefix('v').unwrap_or(version);
+        ApiVersion::SUPPORTED
+            .into_iter()
+            .find(|supported| &supported.as_str()[1..] == version)
+            .ok_or_else(|| {
+                format!(
+                    "unsupported slurmrestd API version {}, expected one of v0.0.39 to v0.0.42",
+                    version
+                )
+            })
+    }
+}
@@ -0,0 +1,525 @@
+use std::path::Path;
+
+use glob::glob;
+use lib::script::{EventContext, ScriptConfig};
+use lib::store::{NewRun, StepRecord, WorkflowRunRecord};
+use lib::types::{
+    envelope::QueueEnvelope, runstatus::RunStatus, workflow::GithubWorkflow,
+    workflowevent::WorkflowEvent,
+};
+use tempdir::TempDir;
+use tokio::{fs, io::AsyncReadExt};
+
+use crate::dlq::{Failure, FailureStage};
+use crate::github::TokenScope;
+use crate::worker::Worker;
+use crate::{secrets, slurm, tracker};
+
+/// The workflows an event triggers, read from the commit the event runs them on.
+pub struct TriggeredWorkflows {
+    pub context: EventContext,
+    // the commit check runs are reported on
+    pub check_sha: String,
+    // workflows of pull requests from forks get neither the repository's secrets nor write
+    // access
+    pub with_secrets: bool,
+    // why the inputs of a workflow_dispatch were rejected, its run fails without jobs
+    pub input_error: Option<String>,
+    pub workflows: Vec<GithubWorkflow>,
+}
+
+/// Reads the workflows `event` triggers. `held_sha` is the commit a held run read them
+/// from, for events whose commit isn't known up front.
+pub async fn read_workflows(
+    event: &WorkflowEvent<'_>,
+    github_token: &str,
+    held_sha: Option<&str>,
+) -> Result<Option<TriggeredWorkflows>, Failure> {
+    if let WorkflowEvent::PullRequest(pull_request) = event
+        && pull_request.pull_request.mergeable == Some(false)
+    {
+        println!(
+            "Pull request #{} has conflicts, skipping its pull_request workflows.",
+            pull_request.number
+        );
+        return Ok(None);
+    }
+
+    let tempdir = match TempDir::new("ghwebhook") {
+        Ok(tempdir) => tempdir,
+        Err(err) => {
+            return Err(Failure::new(
+                FailureStage::Checkout,
+                format!("Failed to create tempdir: {}", err),
+            ));
+        }
+    };
+
+    let (workflow_ref, workflow_sha) = event.workflow_ref();
+    let workflow_sha = workflow_sha.or(held_sha);
+    println!(
+        "Cloning git repo: {} at {}",
+        event.clone_url(),
+        workflow_sha.unwrap_or(&workflow_ref)
+    );
+
+    let git_repo = match lib::clone_git_repo(
+        event.clone_url(),
+        tempdir.path(),
+        &workflow_ref,
+        workflow_sha,
+        Some(github_token),
+    ) {
+        Ok(git_repo) => git_repo,
+        Err(err) => {
+            return Err(Failure::new(
+                FailureStage::Checkout,
+                format!(
+                    "Failed to check out {} of {}: {}",
+                    workflow_sha.unwrap_or(&workflow_ref),
+                    event.repository(),
+                    err
+                ),
+            ));
+        }
+    };
+    let checked_out_sha = match git_repo.head().ok().and_then(|head| head.target()) {
+        Some(oid) => oid.to_string(),
+        None => {
+            return Err(Failure::new(
+                FailureStage::Checkout,
+                format!(
+                    "Failed to resolve {} of {}",
+                    workflow_ref,
+                    event.repository()
+                ),
+            ));
+        }
+    };
+
+    let temp_dir_str = match tempdir.path().to_str() {
+        Some(temp_dir_str) => temp_dir_str,
+        None => {
+            return Err(Failure::new(
+                FailureStage::Checkout,
+                "Failed to convert tempdir path to string",
+            ));
+        }
+    };
+    let workflow_files = match glob(format!("{}/.github/workflows/*", temp_dir_str).as_str()) {
+        Ok(workflow_files) => workflow_files,
+        Err(err) => {
+            return Err(Failure::new(
+                FailureStage::Workflows,
+                format!("Failed to glob workflow files: {}", err),
+            ));
+        }
+    };
+
+    let mut workflows: Vec<lib::types::workflow::GithubWorkflow> = Vec::new();
+    for workflow_file in workflow_files {
+        let workflow_file = match workflow_file {
+            Ok(workflow_file) => workflow_file,
+            Err(err) => {
+                return Err(Failure::new(
+                    FailureStage::Workflows,
+                    format!("Failed to read workflow file: {}", err),
+                ));
+            }
+        };
+        // workflow_dispatch runs one workflow, the others needn't even parse
+        if let Some(file) = event.workflow_file()
+            && workflow_file.strip_prefix(tempdir.path()).ok() != Some(Path::new(file))
+        {
+            continue;
+        }
+        let workflow_file_str = match workflow_file.to_str() {
+            Some(workflow_file_str) => match fs::File::open(workflow_file_str).await {
+                Ok(mut file) => {
+                    let mut buffer = String::new();
+                    match file.read_to_string(&mut buffer).await {
+                        Ok(_) => buffer,
+                        Err(err) => {
+                            return Err(Failure::new(
+                                FailureStage::Workflows,
+                                format!("Failed to read workflow file: {}", err),
+                            ));
+                        }
+                    }
+                }
+                Err(err) => {
+                    return Err(Failure::new(
+                        FailureStage::Workflows,
+                        format!("Failed to open workflow file: {}", err),
+                    ));
+                }
+            },
+            None => {
+                return Err(Failure::new(
+                    FailureStage::Workflows,
+                    "Failed to convert workflow file path to string",
+                ));
+            }
+        };
+
+        let parsed_workflow: lib::types::workflow::GithubWorkflow =
+            match serde_yaml::from_str(&workflow_file_str) {
+                Ok(workflow) => workflow,
+                Err(err) => {
+                    return Err(Failure::new(
+                        FailureStage::Workflows,
+                        format!(
+                            "Failed to parse workflow {}: {}",
+                            workflow_file
+                                .strip_prefix(temp_dir_str)
+                                .unwrap_or(&workflow_file)
+                                .display(),
+                            err
+                        ),
+                    ));
+                }
+            };
+        workflows.push(parsed_workflow);
+    }
+
+    if workflows.is_empty() {
+        eprintln!("No workflow files found");
+    }
+
+    // the diff is only needed for paths filters
+    let changed_files = if workflows
+        .iter()
+        .any(|workflow| lib::filters_paths(workflow, event))
+    {
+        match lib::changed_files(&git_repo, event, Some(github_token)) {
+            Ok(changed_files) => changed_files,
+            Err(err) => return Err(Failure::new(FailureStage::Checkout, err)),
+        }
+    } else {
+        Vec::new()
+    };
+
+    let workflows = workflows
+        .into_iter()
+        .filter(|workflow| lib::should_trigger_workflow(workflow, event, &changed_files))
+        .collect::<Vec<GithubWorkflow>>();
+    if workflows.is_empty() {
+        return Ok(None);
+    }
+
+    let (inputs, input_error) = match event {
+        WorkflowEvent::WorkflowDispatch(dispatch) => {
+            // only the dispatched file is read
+            let [workflow] = workflows.as_slice() else {
+                return Err(Failure::new(
+                    FailureStage::Workflows,
+                    format!(
+                        "{} workflows matched the dispatch of {}",
+                        workflows.len(),
+                        dispatch.workflow
+                    ),
+                ));
+            };
+            let supplied = dispatch.inputs.clone().unwrap_or_default();
+            // replaying the message wouldn't change the inputs, so the run fails instead
+            match workflow.on.dispatch_inputs(&supplied) {
+                Ok(inputs) => (inputs, None),
+                Err(err) => (Default::default(), Some(err.to_string())),
+            }
+        }
+        _ => Default::default(),
+    };
+
+    // the job script checks out GITHUB_SHA, so the compute node builds the commit the
+    // workflows were read from unless the event builds another one
+    let (git_ref, sha) = event.job_ref();
+    let check_sha = event
+        .check_sha()
+        .map(str::to_string)
+        .unwrap_or(checked_out_sha.clone());
+    let context = EventContext {
+        repository: event.repository().to_string(),
+        repository_name: event.repository_name().to_string(),
+        clone_url: event.clone_url().to_string(),
+        git_ref,
+        sha: sha.map(str::to_string).unwrap_or(checked_out_sha),
+        event_name: event.name().to_string(),
+        head_ref: event.head_ref().map(str::to_string),
+        base_ref: event.base_ref().map(str::to_string),
+        inputs,
+    };
+
+    Ok(Some(TriggeredWorkflows {
+        context,
+        check_sha,
+        with_secrets: with_secrets(event),
+        input_error,
+        workflows,
+    }))
+}
+
+// Jobs of a fork's pull request run the fork's code: pull_request ones on the merge commit
+// and pull_request_target ones on its head.
+fn with_secrets(event: &WorkflowEvent) -> bool {
+    !event.is_from_fork()
+}
+
+// jobs only reach their own repository, and a fork's code only reads it
+fn token_scope(with_secrets: bool, repository_name: &str) -> TokenScope {
+    if with_secrets {
+        TokenScope::Repository(repository_name.to_string())
+    } else {
+        TokenScope::ReadOnly(repository_name.to_string())
+    }
+}
+
+/// Submits the jobs of triggered workflows. Workflows of an approved delivery reuse the runs
+/// that were held for it, which were created in the same order.
+pub async fn submit_workflows(
+    worker: &Worker,
+    envelope: &QueueEnvelope,
+    triggered: &TriggeredWorkflows,
+    held_runs: &[WorkflowRunRecord],
+) {
+    let event_context = &triggered.context;
+    let token_scope = token_scope(triggered.with_secrets, &event_context.repository_name);
+    let repo_full_name = &event_context.repository;
+    let script_config = ScriptConfig::default();
+    let mut held_runs = held_runs
+        .iter()
+        .filter(|run| run.event == event_context.event_name);
+
+    for workflow in &triggered.workflows {
+        let workflow_name = workflow.name.clone().unwrap_or_default();
+        let run_id = match held_runs.next() {
+            Some(held_run) => worker
+                .run_store
+                .release_run(held_run.id)
+                .map(|_| held_run.id),
+            None => worker.run_store.create_run(&NewRun {
+                delivery_id: envelope.delivery_id.as_deref(),
+                repository: repo_full_name,
+                workflow: &workflow_name,
+                event: &event_context.event_name,
+                git_ref: &event_context.git_ref,
+                sha: &event_context.sha,
+                approval_id: None,
+            }),
+        };
+        let run_id = match run_id {
+            Ok(run_id) => run_id,
+            Err(err) => {
+                eprintln!("Failed to record workflow run: {}", err);
+                continue;
+            }
+        };
+
+        if let Some(input_error) = &triggered.input_error {
+            eprintln!("⚠️ Not running {}: {}", workflow_name, input_error);
+            if let Err(err) = worker.run_store.fail_run(run_id) {
+                eprintln!("Failed to record workflow run status: {}", err);
+            }
+            continue;
+        }
+
+        for (job_name, job) in &workflow.jobs {
+            let runs_on = lib::script::job_partition(job, event_context);
+            let script =
+                lib::script::render_job(workflow, job_name, job, event_context, &script_config);
+            println!("Running job {}", job_name);
+
+            let steps = job
+                .steps
+                .iter()
+                .enumerate()
+                .map(|(position, step)| StepRecord {
+                    position: position as u32,
+                    name: step.name.clone(),
+                    uses: step.uses.clone(),
+                    run: step.run.clone(),
+                })
+                .collect::<Vec<StepRecord>>();
+            let job_id = match worker
+                .run_store
+                .create_job(run_id, job_name, &runs_on, &steps)
+            {
+                Ok(job_id) => job_id,
+                Err(err) => {
+                    eprintln!("Failed to record job {}: {}", job_name, err);
+                    continue;
+                }
+            };
+
+            let job_secrets = if triggered.with_secrets {
+                secrets::job_environment(
+                    &worker.run_store,
+                    worker.secret_cipher.as_ref(),
+                    envelope.installation_id.unwrap_or(secrets::NO_INSTALLATION),
+                    repo_full_name,
+                    job,
+                )
+            } else {
+                println!("Job {} runs a fork's code, secrets are empty", job_name);
+                Ok(Vec::new())
+            };
+
+            let check = worker
+                .checks_reporter
+                .queued(
+                    envelope.installation_id,
+                    repo_full_name,
+                    &triggered.check_sha,
+                    &format!("{} / {}", workflow_name, job_name),
+                    &runs_on,
+                )
+                .await;
+
+            // minted for this job alone, a cached token could expire while the job is queued
+            let github_token = worker
+                .github_auth
+                .job_token(envelope.installation_id, &token_scope)
+                .await;
+
+            let submit_result = match (script, job_secrets, github_token) {
+                (Ok(script), Ok(job_secrets), Ok(github_token)) => {
+                    let job_description = slurm::JobDescription {
+                        name: None,
+                        partition: None,
+                        script,
+                        // the token and secrets travel only in the job's environment, never
+                        // in the script
+                        environment: [
+                            "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin:/snap/bin"
+                                .to_string(),
+                            format!("{}={}", lib::script::GITHUB_TOKEN_VARIABLE, github_token),
+                        ]
+                        .into_iter()
+                        .chain(job_secrets)
+                        .collect(),
+                        current_working_directory: "/home/slurm".to_string(),
+                    };
+
+                    worker
+                        .retry_policy
+                        .run(&format!("Submitting job {}", job_name), || {
+                            worker.backend.submit(&job_description)
+                        })
+                        .await
+                        .map_err(|e| e.to_string())
+                }
+                (Err(err), _, _) | (_, Err(err), _) | (_, _, Err(err)) => Err(err.to_string()),
+            };
+
+            match submit_result {
+                Ok(slurm_job_id) => {
+                    println!("✅ Submitted job {job_name} as Slurm job {slurm_job_id}");
+                    if let Err(err) = worker.run_store.set_slurm_job_id(job_id, slurm_job_id) {
+                        eprintln!("Failed to record Slurm job id: {}", err);
+                    }
+                    if let Some(check) = &check {
+                        worker.checks_reporter.submitted(check, slurm_job_id).await;
+                        if let Err(err) = worker.run_store.set_check_run(
+                            job_id,
+                            check.installation_id,
+                            check.check_run_id,
+                        ) {
+                            eprintln!("Failed to record check run: {}", err);
+                        }
+                    }
+                    worker
+                        .job_tracker
+                        .track(tracker::TrackedJob {
+                            job_id,
+                            slurm_job_id,
+                            repository: repo_full_name.clone(),
+                            workflow: workflow_name.clone(),
+                            job_name: job_name.clone(),
+                            partition: runs_on.clone(),
+                        })
+                        .await;
+                }
+                Err(e) => {
+                    eprintln!("⚠️ {e}");
+                    if let Err(err) =
+                        worker
+                            .run_store
+                            .transition_job(job_id, RunStatus::Failure, None)
+                    {
+                        eprintln!("Failed to record job status: {}", err);
+                    }
+                    if let Some(check) = &check {
+                        worker.checks_reporter.submission_failed(check, &e).await;
+                    }
+                    continue;
+                }
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use lib::types::webhookevent::WebhookEvent;
+
+    const PUSH: &str = include_str!("../../../lib/tests/fixtures/webhooks/push.json");
+    const PULL_REQUEST: &str =
+        include_str!("../../../lib/tests/fixtures/webhooks/pull_request.json");
+
+    fn webhook_event(event: &str, payload: &str) -> WebhookEvent {
+        WebhookEvent::from_value(event, serde_json::from_str(payload).unwrap()).unwrap()
+    }
+
+    // whether each workflow event of the delivery gets secrets, and the scope of its token
+    fn access(webhook: &WebhookEvent) -> Vec<(&'static str, bool, TokenScope)> {
+        WorkflowEvent::from_webhook(webhook)
+            .iter()
+            .map(|event| {
+                let with_secrets = with_secrets(event);
+                (
+                    event.name(),
+                    with_secrets,
+                    token_scope(with_secrets, event.repository_name()),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pushes_get_secrets_and_write_access() {
+        assert_eq!(
+            access(&webhook_event("push", PUSH)),
+            [("push", true, TokenScope::Repository("repo".to_string()))]
+        );
+    }
+
+    #[test]
+    fn fork_pull_requests_get_no_secrets_and_read_access() {
+        let read_only = TokenScope::ReadOnly("repo".to_string());
+        assert_eq!(
+            access(&webhook_event("pull_request", PULL_REQUEST)),
+            [
+                ("pull_request", false, read_only.clone()),
+                // runs the fork's head, unlike on github
+                ("pull_request_target", false, read_only),
+            ]
+        );
+    }
+
+    #[test]
+    fn pull_requests_from_the_repository_get_secrets() {
+        let mut payload: serde_json::Value = serde_json::from_str(PULL_REQUEST).unwrap();
+        payload["pull_request"]["head"]["repo"]["full_name"] = "octo/repo".into();
+        let webhook = webhook_event("pull_request", &payload.to_string());
+
+        let repository = TokenScope::Repository("repo".to_string());
+        assert_eq!(
+            access(&webhook),
+            [
+                ("pull_request", true, repository.clone()),
+                ("pull_request_target", true, repository),
+            ]
+        );
+    }
+}
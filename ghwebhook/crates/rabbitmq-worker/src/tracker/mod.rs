@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use lib::store::RunStore;
+use lib::types::runstatus::RunStatus;
+use tokio::sync::{Mutex, broadcast};
+
+use crate::backend::SubmissionBackend;
+
+// jobs are given up on after this many polls in which neither slurmctld nor slurmdbd knew
+// them
+const MAX_MISSED_POLLS: u32 = 5;
+
+/// Slurm state reported for a job that was given up on, Slurm itself has no name for it.
+pub const UNKNOWN_STATE: &str = "UNKNOWN";
+
+#[derive(Debug, Clone)]
+pub struct TrackedJob {
+    // id of the job in the run database
+    pub job_id: i64,
+    pub slurm_job_id: u32,
+    pub repository: String,
+    pub workflow: String,
+    pub job_name: String,
+    pub partition: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct JobTransition {
+    pub job: TrackedJob,
+    pub slurm_state: String,
+    pub exit_code: Option<i64>,
+    pub previous: Option<RunStatus>,
+    pub status: RunStatus,
+}
+
+struct TrackedState {
+    job: TrackedJob,
+    status: Option<RunStatus>,
+    missed_polls: u32,
+}
+
+/// Follows submitted Slurm jobs until they finish and broadcasts every status change to
+/// the subscribers.
+#[derive(Clone)]
+pub struct JobTracker {
+    jobs: Arc<Mutex<HashMap<u32, TrackedState>>>,
+    sender: broadcast::Sender<JobTransition>,
+}
+
+impl Default for JobTracker {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(1024);
+        Self {
+            jobs: Arc::new(Mutex::new(HashMap::new())),
+            sender,
+        }
+    }
+}
+
+impl JobTracker {
+    pub fn subscribe(&self) -> broadcast::Receiver<JobTransition> {
+        self.sender.subscribe()
+    }
+
+    pub async fn track(&self, job: TrackedJob) {
+        self.follow(job, None).await;
+    }
+
+    /// Follows a job submitted before the worker restarted, which was last seen in
+    /// `status`.
+    pub async fn resume(&self, job: TrackedJob, status: RunStatus) {
+        self.follow(job, Some(status)).await;
+    }
+
+    async fn follow(&self, job: TrackedJob, status: Option<RunStatus>) {
+        self.jobs.lock().await.insert(
+            job.slurm_job_id,
+            TrackedState {
+                job,
+                status,
+                missed_polls: 0,
+            },
+        );
+    }
+
+    /// Prints every transition, mostly so there is a record of it in the worker's output.
+    pub fn spawn_logger(&self) {
+        let mut transitions = self.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match transitions.recv().await {
+                    Ok(transition) => println!(
+                        "{} / {} ({}) Slurm job {} on {}: {} -> {} ({})",
+                        transition.job.workflow,
+                        transition.job.job_name,
+                        transition.job.repository,
+                        transition.job.slurm_job_id,
+                        transition.job.partition,
+                        transition
+                            .previous
+                            .map(|s| s.as_str())
+                            .unwrap_or("submitted"),
+                        transition.status.as_str(),
+                        transition.slurm_state
+                    ),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+    }
+
+    /// Writes every transition to the run database.
+    pub fn spawn_recorder(&self, store: Arc<RunStore>) {
+        let mut transitions = self.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let transition = match transitions.recv().await {
+                    Ok(transition) => transition,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        eprintln!("Run recorder missed {} job transitions", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+
+                if let Err(err) = store.transition_job(
+                    transition.job.job_id,
+                    transition.status,
+                    Some(&transition.slurm_state),
+                ) {
+                    eprintln!(
+                        "Failed to record status of Slurm job {}: {}",
+                        transition.job.slurm_job_id, err
+                    );
+                }
+            }
+        });
+    }
+
+    /// Starts polling the backend for every tracked job on `interval`.
+    pub fn spawn(&self, backend: Arc<dyn SubmissionBackend>, interval: Duration) {
+        let tracker = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                tracker.poll(backend.as_ref()).await;
+            }
+        });
+    }
+
+    async fn poll(&self, backend: &dyn SubmissionBackend) {
+        let job_ids: Vec<u32> = self.jobs.lock().await.keys().copied().collect();
+
+        for job_id in job_ids {
+            let job_info = match backend.status(job_id).await {
+                Ok(job_info) => job_info,
+                Err(err) => {
+                    eprintln!("Failed to poll Slurm job {}: {}", job_id, err);
+                    continue;
+                }
+            };
+
+            let mut jobs = self.jobs.lock().await;
+            let Some(tracked) = jobs.get_mut(&job_id) else {
+                continue;
+            };
+
+            let Some(job_info) = job_info else {
+                tracked.missed_polls += 1;
+                if tracked.missed_polls >= MAX_MISSED_POLLS {
+                    eprintln!("Slurm job {} is unknown, no longer tracking it", job_id);
+                    // nobody can tell how the job ended, so it fails rather than staying
+                    // queued or running forever
+                    let transition = JobTransition {
+                        job: tracked.job.clone(),
+                        slurm_state: UNKNOWN_STATE.to_string(),
+                        exit_code: None,
+                        previous: tracked.status,
+                        status: RunStatus::Failure,
+                    };
+                    jobs.remove(&job_id);
+                    let _ = self.sender.send(transition);
+                }
+                continue;
+            };
+            tracked.missed_polls = 0;
+
+            let status = RunStatus::from_slurm_state(&job_info.state);
+            if tracked.status == Some(status) {
+                continue;
+            }
+
+            let transition = JobTransition {
+                job: tracked.job.clone(),
+                slurm_state: job_info.state,
+                exit_code: job_info.exit_code,
+                previous: tracked.status,
+                status,
+            };
+            tracked.status = Some(status);
+            if status.is_finished() {
+                jobs.remove(&job_id);
+            }
+
+            // sending only fails when nobody is subscribed
+            let _ = self.sender.send(transition);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_trait::async_trait;
+    use lib::errors::AppError;
+
+    use crate::slurm::{JobDescription, JobInfo};
+
+    // a cluster that knows a single job, in a fixed state
+    struct Cluster {
+        job: Option<JobInfo>,
+    }
+
+    #[async_trait]
+    impl SubmissionBackend for Cluster {
+        async fn submit(&self, _job: &JobDescription) -> Result<u32, AppError> {
+            Ok(1)
+        }
+
+        async fn status(&self, _job_id: u32) -> Result<Option<JobInfo>, AppError> {
+            Ok(self.job.clone())
+        }
+
+        async fn cancel(&self, _job_id: u32) -> Result<(), AppError> {
+            Ok(())
+        }
+    }
+
+    fn job() -> TrackedJob {
+        TrackedJob {
+            job_id: 1,
+            slurm_job_id: 4242,
+            repository: "octo/repo".to_string(),
+            workflow: "CI".to_string(),
+            job_name: "build".to_string(),
+            partition: "debug".to_string(),
+        }
+    }
+
+    fn state(state: &str) -> Option<JobInfo> {
+        Some(JobInfo {
+            state: state.to_string(),
+            exit_code: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn jobs_nobody_knows_fail_after_the_missed_polls() {
+        let tracker = JobTracker::default();
+        let mut transitions = tracker.subscribe();
+        tracker.track(job()).await;
+
+        let cluster = Cluster { job: None };
+        for _ in 1..MAX_MISSED_POLLS {
+            tracker.poll(&cluster).await;
+        }
+        assert!(transitions.try_recv().is_err());
+
+        tracker.poll(&cluster).await;
+        let transition = transitions.try_recv().unwrap();
+        assert_eq!(transition.status, RunStatus::Failure);
+        assert_eq!(transition.slurm_state, UNKNOWN_STATE);
+        assert!(tracker.jobs.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn resumed_jobs_only_report_changes() {
+        let tracker = JobTracker::default();
+        let mut transitions = tracker.subscribe();
+        tracker.resume(job(), RunStatus::InProgress).await;
+
+        tracker
+            .poll(&Cluster {
+                job: state("RUNNING"),
+            })
+            .await;
+        assert!(transitions.try_recv().is_err());
+
+        tracker
+            .poll(&Cluster {
+                job: state("COMPLETED"),
+            })
+            .await;
+        let transition = transitions.try_recv().unwrap();
+        assert_eq!(transition.previous, Some(RunStatus::InProgress));
+        assert_eq!(transition.status, RunStatus::Success);
+    }
+}
@@ -0,0 +1,146 @@
+use std::sync::Arc;
+
+use lib::store::RunStore;
+use lib::types::{
+    approval::APPROVAL_EVENT, dispatch::DISPATCH_EVENT, envelope::QueueEnvelope,
+    webhookevent::WebhookEvent, workflowevent::WorkflowEvent,
+};
+use rabbitmq_stream_client::types::Message;
+
+use crate::approval::{approve_from_comment, hold_workflows, run_approval};
+use crate::dispatch::run_dispatch;
+use crate::dlq::{Failure, FailureStage};
+use crate::services::message_property;
+use crate::submission::{read_workflows, submit_workflows};
+use crate::{backend, checks, github, policy, retry, tracker};
+
+/// Clients shared by every message the worker handles.
+pub struct Worker {
+    pub github_auth: Arc<github::GithubAuth>,
+    pub github_api: github::GithubApi,
+    pub backend: Arc<dyn backend::SubmissionBackend>,
+    pub retry_policy: retry::RetryPolicy,
+    pub run_store: Arc<RunStore>,
+    pub secret_cipher: Option<lib::secrets::SecretCipher>,
+    pub approval_policy: policy::ApprovalPolicy,
+    pub job_tracker: tracker::JobTracker,
+    pub checks_reporter: checks::ChecksReporter,
+}
+
+pub async fn handle_message(worker: &Worker, message: &Message) -> Result<(), Failure> {
+    let data = match message.data() {
+        Some(data) => data,
+        None => return Err(Failure::new(FailureStage::Decode, "empty payload")),
+    };
+    let data = match String::from_utf8(data.to_vec()) {
+        Ok(data) => data,
+        Err(err) => return Err(Failure::new(FailureStage::Decode, err)),
+    };
+
+    // messages published before the envelope existed carry the event name and
+    // delivery id in application properties
+    let envelope = match QueueEnvelope::decode(
+        data.as_bytes(),
+        message_property(message, "x-github-event"),
+        message_property(message, "x-github-delivery"),
+    ) {
+        Ok(envelope) => envelope,
+        Err(err) => return Err(Failure::new(FailureStage::Decode, err)),
+    };
+
+    match worker.run_store.record_delivery(&envelope) {
+        Ok(true) => {}
+        Ok(false) => {
+            println!(
+                "Delivery {} already processed, skipping.",
+                envelope.delivery_id.as_deref().unwrap_or_default()
+            );
+            return Ok(());
+        }
+        Err(err) => return Err(Failure::new(FailureStage::Store, err)),
+    }
+
+    run_event(worker, &envelope).await?;
+
+    // failed deliveries stay unprocessed so that replaying them from the dead-letter stream
+    // isn't skipped as a duplicate
+    if let Some(delivery_id) = &envelope.delivery_id {
+        worker
+            .run_store
+            .mark_delivery_processed(delivery_id)
+            .map_err(|err| Failure::new(FailureStage::Store, err))?;
+    }
+
+    Ok(())
+}
+
+async fn run_event(worker: &Worker, envelope: &QueueEnvelope) -> Result<(), Failure> {
+    if envelope.event == APPROVAL_EVENT {
+        return run_approval(worker, envelope).await;
+    }
+    if envelope.event == DISPATCH_EVENT {
+        return run_dispatch(worker, envelope).await;
+    }
+
+    let webhook_event = match envelope.webhook_event() {
+        Ok(webhook_event) => webhook_event,
+        Err(err) => return Err(Failure::new(FailureStage::Parse, err)),
+    };
+
+    if let WebhookEvent::IssueComment(comment) = &webhook_event {
+        return approve_from_comment(worker, comment).await;
+    }
+
+    run_webhook_event(worker, envelope, &webhook_event).await
+}
+
+pub async fn run_webhook_event(
+    worker: &Worker,
+    envelope: &QueueEnvelope,
+    webhook_event: &WebhookEvent,
+) -> Result<(), Failure> {
+    let workflow_events = WorkflowEvent::from_webhook(webhook_event);
+    if workflow_events.is_empty() {
+        println!("No handler for {} events, skipping.", webhook_event.name());
+        return Ok(());
+    }
+
+    if let WebhookEvent::Push(github_event) = webhook_event
+        && github_event.deleted
+    {
+        println!("{} was deleted, skipping.", github_event.ref_);
+        return Ok(());
+    }
+
+    let github_token = match worker.github_auth.token(envelope.installation_id).await {
+        Ok(github_token) => github_token,
+        Err(err) => return Err(Failure::new(FailureStage::Auth, err)),
+    };
+
+    // nothing is submitted unless the workflows of every event can be read, so a replay
+    // after a fix doesn't run some of the workflows twice
+    let mut triggered = Vec::new();
+    for workflow_event in &workflow_events {
+        if let Some(triggered_workflows) =
+            read_workflows(workflow_event, &github_token, None).await?
+        {
+            triggered.push(triggered_workflows);
+        }
+    }
+
+    if triggered.is_empty() {
+        eprintln!("No workflows to run");
+        return Ok(());
+    }
+
+    // the events of a delivery are about the same pull request, if any
+    if let Some(reason) = worker.approval_policy.approval_reason(&workflow_events[0]) {
+        return hold_workflows(worker, envelope, &workflow_events[0], &reason, &triggered);
+    }
+
+    for triggered_workflows in triggered {
+        submit_workflows(worker, envelope, &triggered_workflows, &[]).await;
+    }
+
+    Ok(())
+}